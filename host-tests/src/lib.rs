@@ -0,0 +1,20 @@
+//! Unit tests of the hardware independent modules of the platform crate.
+//!
+//! The platform crate only builds for `aarch64-unknown-none`. The modules
+//! below only depend on `core`, so they are built again here, for the host,
+//! to run their tests:
+//!
+//! ```sh
+//! cargo test --manifest-path host-tests/Cargo.toml
+//! ```
+
+#![no_std]
+
+extern crate alloc;
+
+#[path = "../../src/ec/protocol.rs"]
+pub mod ec_protocol;
+#[path = "../../src/ps2/mouse/protocol.rs"]
+pub mod mouse_protocol;
+#[path = "../../src/simplefb/format.rs"]
+pub mod simplefb_format;
@@ -0,0 +1,45 @@
+//! Minimal ACPI table lookup.
+//!
+//! The RSDP is found through the EFI configuration table, so like
+//! [`crate::efi`] this only works while the boot identity map is active.
+
+/// Length of the common ACPI table header.
+pub const HEADER_LEN: usize = 36;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+/// Offset of `Revision` in the RSDP.
+const RSDP_REVISION: usize = 15;
+/// Offset of `XsdtAddress` in the RSDP (revision 2 and later).
+const RSDP_XSDT_ADDRESS: usize = 24;
+
+/// Reads a value of type `T` at physical address `paddr`.
+///
+/// # Safety
+///
+/// `paddr` must be identity mapped.
+pub unsafe fn read<T: Copy>(paddr: usize) -> T {
+    unsafe { core::ptr::read_unaligned(paddr as *const T) }
+}
+
+/// Returns the physical address and length of the first table with the
+/// given signature, e.g. `b"GTDT"`.
+pub fn find_table(signature: &[u8; 4]) -> Option<(usize, usize)> {
+    let rsdp = crate::efi::config_table(&crate::efi::ACPI_20_TABLE_GUID)?;
+    // SAFETY: ACPI tables live in firmware-reserved low memory, identity
+    // mapped at boot.
+    unsafe {
+        if read::<[u8; 8]>(rsdp) != *RSDP_SIGNATURE || read::<u8>(rsdp + RSDP_REVISION) < 2 {
+            return None;
+        }
+        let xsdt = read::<u64>(rsdp + RSDP_XSDT_ADDRESS) as usize;
+        if xsdt == 0 || read::<[u8; 4]>(xsdt) != *b"XSDT" {
+            return None;
+        }
+        let xsdt_len = read::<u32>(xsdt + 4) as usize;
+        (HEADER_LEN..xsdt_len).step_by(8).find_map(|off| {
+            let table = read::<u64>(xsdt + off) as usize;
+            (table != 0 && read::<[u8; 4]>(table) == *signature)
+                .then(|| (table, read::<u32>(table + 4) as usize))
+        })
+    }
+}
@@ -0,0 +1,81 @@
+//! Frame-pointer backtraces of the context interrupted by an exception.
+
+use core::fmt::Write;
+use core::ops::Range;
+
+use crate::config::plat::BOOT_STACK_SIZE;
+
+/// Maximum number of frames printed in a backtrace.
+const MAX_BACKTRACE_DEPTH: usize = 32;
+/// Size of the `axcpu` vector table.
+const VECTOR_TABLE_SIZE: usize = 0x800;
+
+unsafe extern "C" {
+    /// Vector table installed by `axcpu::init::init_trap`.
+    fn exception_vector_base();
+}
+
+/// Returns the range the stack in use can span: from the stack pointer up
+/// to the size of the boot stack, the largest kernel stack.
+///
+/// Exceptions taken at EL1 stay on the interrupted stack, so its frame
+/// records are in there too.
+fn current_stack() -> Range<usize> {
+    let sp: usize;
+    unsafe { core::arch::asm!("mov {}, sp", out(reg) sp) };
+    sp..sp.saturating_add(BOOT_STACK_SIZE)
+}
+
+/// Iterates over the `(address, return address)` of the frame records
+/// chained from `fp`, innermost first.
+///
+/// The walk stops at the first record that is misaligned or outside
+/// `stack`, so a corrupted chain cannot make it fault.
+fn frame_records(mut fp: usize, stack: Range<usize>) -> impl Iterator<Item = (usize, usize)> {
+    core::iter::from_fn(move || {
+        if !fp.is_multiple_of(16) || fp < stack.start || stack.end.saturating_sub(fp) < 16 {
+            return None;
+        }
+        // SAFETY: the record is within the stack, which is mapped.
+        let (prev_fp, lr) = unsafe { (*(fp as *const usize), *((fp + 8) as *const usize)) };
+        if lr == 0 {
+            return None;
+        }
+        let record = fp;
+        // Stacks grow down, so a caller's record is always above.
+        fp = if prev_fp > fp { prev_fp } else { 0 };
+        Some((record, lr))
+    })
+    .take(MAX_BACKTRACE_DEPTH)
+}
+
+/// Returns the frame pointer of the context interrupted by the exception
+/// being handled.
+///
+/// The vector entry keeps `x29` intact, so the first handler it calls saves
+/// the interrupted frame pointer in its frame record: the one whose return
+/// address lies in the vector table.
+fn interrupted_fp() -> Option<usize> {
+    let vectors = exception_vector_base as *const () as usize;
+    let mut fp: usize;
+    unsafe { core::arch::asm!("mov {}, x29", out(reg) fp) };
+    let (record, _) = frame_records(fp, current_stack())
+        .find(|&(_, lr)| (vectors..vectors + VECTOR_TABLE_SIZE).contains(&lr))?;
+    // SAFETY: `record` is a frame record, see `frame_records`.
+    Some(unsafe { *(record as *const usize) })
+}
+
+/// Writes the return address of each frame record of the context
+/// interrupted by the exception being handled, innermost first.
+///
+/// Must be called from an exception handler. The interrupted PC is in
+/// `ELR_EL1`, and is not repeated here.
+pub(crate) fn write_interrupted(out: &mut impl Write) {
+    let Some(fp) = interrupted_fp() else {
+        let _ = writeln!(out, "  (interrupted frame not found)");
+        return;
+    };
+    for (depth, (_, lr)) in frame_records(fp, current_stack()).enumerate() {
+        let _ = writeln!(out, "  #{:02} {:#018x}", depth, lr);
+    }
+}
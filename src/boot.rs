@@ -85,6 +85,9 @@ unsafe extern "C" fn _start() {
 }
 
 /// Relocate the kernel to the specific address.
+///
+/// Returns the DTB pointer if the kernel already runs at `target_addr`,
+/// otherwise restarts it from there.
 #[unsafe(naked)]
 unsafe extern "C" fn relocate_self(target_addr: usize, dtb: usize) {
     core::arch::naked_asm!("
@@ -123,7 +126,8 @@ unsafe extern "C" fn relocate_self(target_addr: usize, dtb: usize) {
         mov     x0, x21                 // Restore DTB
         br      x20                     // Branch to target_addr
 
-2:      mov     x30, x19                // Restore LR
+2:      mov     x0, x21                 // Return DTB
+        mov     x30, x19                // Restore LR
         ret
     ",
     )
@@ -135,10 +139,18 @@ unsafe extern "C" fn _start_primary() {
     // X0 = dtb
     core::arch::naked_asm!("
         mov     x20, x0                 // save DTB pointer (callee-saved)
+        bl      {earlycon_vectors}      // catch faults on the early console
+        mov     x0, #0x45               // 'E'
+        bl      {earlycon_mark}
+
         mov     x0, #0x8000
         lsl     x0, x0, #16             // x0 = 0x8000_0000 (target address)
         mov     x1, x20                 // x1 = dtb
         bl      {relocate_self}          // relocate_self(0x8000_0000, dtb)
+        mov     x20, x0
+        mov     x0, #0x52               // 'R'
+        bl      {earlycon_mark}
+        mov     x0, x20
 
         mrs     x19, mpidr_el1
         and     x19, x19, #0xffffff     // get current CPU id
@@ -149,14 +161,20 @@ unsafe extern "C" fn _start_primary() {
         mov     sp, x8
 
         bl      {switch_to_el1}         // switch to EL1
+        mov     x0, #0x31               // '1'
+        bl      {earlycon_mark}
         bl      {enable_fp}             // enable fp/neon
 
         adrp    x0, {boot_pt}
         bl      {init_boot_page_table}
-        
+        mov     x0, #0x50               // 'P'
+        bl      {earlycon_mark}
+
         adrp    x0, {boot_pt}
         bl      {init_mmu}              // setup MMU
-        
+        mov     x0, #0x4d               // 'M'
+        bl      {earlycon_mark}
+
         mov     x8, {phys_virt_offset}  // set SP to the high address
         add     sp, sp, x8
 
@@ -174,6 +192,8 @@ unsafe extern "C" fn _start_primary() {
         boot_stack = sym BOOT_STACK,
         boot_stack_size = const BOOT_STACK_SIZE,
         relocate_self = sym relocate_self,
+        earlycon_vectors = sym crate::earlycon::install_vectors,
+        earlycon_mark = sym crate::earlycon::mark,
         entry = sym axplat::call_main
     )
 }
@@ -0,0 +1,74 @@
+//! Kernel command line taken from the `/chosen/bootargs` DTB property.
+//!
+//! The command line is copied out of the DTB at early boot, since the blob
+//! itself lives in allocatable RAM and may be overwritten later.
+
+use lazyinit::LazyInit;
+
+const MAX_BOOTARGS_LEN: usize = 1024;
+
+static BOOTARGS: LazyInit<Cmdline> = LazyInit::new();
+
+/// A fixed-capacity copy of the command line, usable before the heap is up.
+struct Cmdline {
+    buf: [u8; MAX_BOOTARGS_LEN],
+    len: usize,
+}
+
+impl Cmdline {
+    fn new(s: &str) -> Self {
+        let mut len = s.len().min(MAX_BOOTARGS_LEN);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut buf = [0; MAX_BOOTARGS_LEN];
+        buf[..len].copy_from_slice(&s.as_bytes()[..len]);
+        Self { buf, len }
+    }
+
+    fn as_str(&self) -> &str {
+        // SAFETY: the buffer was filled from a `&str` cut at a char boundary.
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+}
+
+/// Copies the command line out of the boot DTB.
+///
+/// Missing DTB or `bootargs` property result in an empty command line.
+pub fn init() {
+    let args = crate::fdt::get()
+        .and_then(|fdt| fdt.find_node("/chosen"))
+        .and_then(|chosen| chosen.property_str("bootargs"))
+        .unwrap_or("");
+    BOOTARGS.init_once(Cmdline::new(args));
+}
+
+/// Returns the whole kernel command line.
+pub fn cmdline() -> &'static str {
+    BOOTARGS.get().map_or("", |s| s.as_str())
+}
+
+/// Iterates over the values of every `key=value` argument with the given key.
+pub fn values(key: &'static str) -> impl Iterator<Item = &'static str> {
+    cmdline().split_ascii_whitespace().filter_map(move |arg| {
+        let (k, v) = arg.split_once('=')?;
+        (k == key).then_some(v)
+    })
+}
+
+/// Returns the value of the last `key=value` argument with the given key.
+pub fn get(key: &'static str) -> Option<&'static str> {
+    values(key).last()
+}
+
+/// Returns the value of the given argument parsed as an integer.
+///
+/// Both decimal and `0x`-prefixed hexadecimal values are accepted.
+#[cfg(feature = "rtc")]
+pub fn get_usize(key: &'static str) -> Option<usize> {
+    let value = get(key)?;
+    match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
@@ -0,0 +1,418 @@
+//! Console multiplexer.
+//!
+//! Output devices (sinks) and input devices (sources) register themselves
+//! here, and [`axplat::console::ConsoleIf`] fans out writes to every enabled
+//! sink and polls every enabled source in turn.
+//!
+//! Built-in sinks and sources:
+//!
+//! | Name      | Sink              | Source          |
+//! |-----------|-------------------|-----------------|
+//! | `ttyAMA0` | PL011 UART        | PL011 UART      |
+//! | `ttyS0`   | LPC 16550 UART    | LPC 16550 UART  |
+//! | `fb0`     | framebuffer       | PS/2 keyboard   |
+//! | `mem`     | in-memory log     | -               |
+//!
+//! When the command line has `console=<name>` arguments, only the listed
+//! consoles are enabled (the in-memory log is always on). Options after a
+//! comma (`console=ttyAMA0,115200n8`) are ignored here. Without them, every
+//! console is enabled except the serial ones other than `serial-console`.
+//! A sink can also be given a log level threshold with
+//! `consolelevel.<name>=<level>`; the last such argument for a sink wins.
+//! Thresholds apply to output written with [`write_log`], which the logger
+//! calls with the level of the record being written, so every line of a
+//! multi-line record is filtered alike. Plain [`write_bytes`] output reaches
+//! every enabled sink.
+//!
+//! Once [`enter_panic_mode`] is called, all output goes through the emergency
+//! path of the UARTs and the framebuffer, which breaks locks left held by a
+//! CPU that will never release them.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use kspin::{SpinNoIrq, SpinNoIrqGuard};
+use log::LevelFilter;
+
+use crate::config::devices::{CONSOLE_LOG_BUF_SIZE, SERIAL_CONSOLE};
+use crate::ringbuf::RingBuffer;
+
+/// Maximum number of sinks or sources.
+const MAX_CONSOLES: usize = 8;
+
+/// Serial consoles, of which only `serial-console` is enabled by default.
+const SERIAL_CONSOLES: [&str; 2] = ["ttyAMA0", "ttyS0"];
+
+/// How many times the emergency path retries a lock before breaking it.
+const EMERGENCY_LOCK_TRIES: usize = 100_000;
+
+static PANIC_MODE: AtomicBool = AtomicBool::new(false);
+
+/// An output device of the console.
+pub struct ConsoleSink {
+    name: &'static str,
+    write: fn(&[u8]),
+    /// Whether `console=` boot arguments can disable this sink.
+    selectable: bool,
+    enabled: AtomicBool,
+    /// Most verbose [`LevelFilter`] written to this sink, as `usize`.
+    max_level: AtomicUsize,
+}
+
+impl ConsoleSink {
+    /// Creates a sink writing through `write`.
+    pub const fn new(name: &'static str, write: fn(&[u8])) -> Self {
+        Self {
+            name,
+            write,
+            selectable: true,
+            enabled: AtomicBool::new(true),
+            max_level: AtomicUsize::new(LevelFilter::Trace as usize),
+        }
+    }
+
+    /// Creates a sink that stays enabled regardless of `console=` arguments.
+    pub const fn new_unselectable(name: &'static str, write: fn(&[u8])) -> Self {
+        Self {
+            selectable: false,
+            ..Self::new(name, write)
+        }
+    }
+
+    /// Returns the sink name.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn accepts(&self, level: LevelFilter) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+            && level as usize <= self.max_level.load(Ordering::Relaxed)
+    }
+}
+
+/// An input device of the console.
+pub struct ConsoleSource {
+    name: &'static str,
+    read: fn() -> Option<u8>,
+    enabled: AtomicBool,
+}
+
+impl ConsoleSource {
+    /// Creates a source polled through `read`.
+    pub const fn new(name: &'static str, read: fn() -> Option<u8>) -> Self {
+        Self {
+            name,
+            read,
+            enabled: AtomicBool::new(true),
+        }
+    }
+
+    /// Returns the source name.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+static SINKS: SpinNoIrq<[Option<&'static ConsoleSink>; MAX_CONSOLES]> =
+    SpinNoIrq::new([None; MAX_CONSOLES]);
+static SOURCES: SpinNoIrq<[Option<&'static ConsoleSource>; MAX_CONSOLES]> =
+    SpinNoIrq::new([None; MAX_CONSOLES]);
+
+static LOG_BUF: SpinNoIrq<RingBuffer<u8, CONSOLE_LOG_BUF_SIZE>> =
+    SpinNoIrq::new(RingBuffer::new());
+
+fn log_buf_write(bytes: &[u8]) {
+    let mut buf = LOG_BUF.lock();
+    for &c in bytes {
+        buf.push_overwrite(c);
+    }
+}
+
+/// In-memory log sink, keeping the most recent console output.
+pub static MEM_SINK: ConsoleSink = ConsoleSink::new_unselectable("mem", log_buf_write);
+
+/// Returns whether the command line selects the console `name`, or [`None`]
+/// if there are no `console=` arguments at all.
+fn selected_by_bootargs(name: &str) -> Option<bool> {
+    let mut args = crate::bootargs::values("console").peekable();
+    args.peek()?;
+    Some(args.any(|arg| arg.split(',').next() == Some(name)))
+}
+
+/// Returns whether the console `name` starts enabled.
+fn selected(name: &str) -> bool {
+    selected_by_bootargs(name)
+        .unwrap_or_else(|| name == SERIAL_CONSOLE || !SERIAL_CONSOLES.contains(&name))
+}
+
+/// Returns the threshold set by the last `consolelevel.<name>=` argument,
+/// like [`crate::bootargs::get`] does for fixed keys.
+fn level_from_bootargs(name: &str) -> Option<LevelFilter> {
+    crate::bootargs::cmdline()
+        .split_ascii_whitespace()
+        .filter_map(|arg| {
+            let (key, value) = arg.split_once('=')?;
+            (key.strip_prefix("consolelevel.")? == name).then_some(value)
+        })
+        .next_back()?
+        .parse()
+        .ok()
+}
+
+fn add<T>(slots: &mut [Option<&'static T>; MAX_CONSOLES], item: &'static T) -> bool {
+    if slots.iter().flatten().any(|s| core::ptr::eq(*s, item)) {
+        return true;
+    }
+    match slots.iter_mut().find(|s| s.is_none()) {
+        Some(slot) => {
+            *slot = Some(item);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Registers an output device, applying the command line selection.
+///
+/// Returns `false` if there is no room left.
+pub fn register_sink(sink: &'static ConsoleSink) -> bool {
+    if sink.selectable && !selected(sink.name) {
+        sink.enabled.store(false, Ordering::Relaxed);
+    }
+    if let Some(level) = level_from_bootargs(sink.name) {
+        sink.max_level.store(level as usize, Ordering::Relaxed);
+    }
+    add(&mut SINKS.lock(), sink)
+}
+
+/// Registers an input device, applying the command line selection.
+///
+/// Returns `false` if there is no room left.
+pub fn register_source(source: &'static ConsoleSource) -> bool {
+    if !selected(source.name) {
+        source.enabled.store(false, Ordering::Relaxed);
+    }
+    add(&mut SOURCES.lock(), source)
+}
+
+/// Enables or disables every sink and source with the given name.
+///
+/// Returns `false` if no console has that name.
+pub fn set_enabled(name: &str, enabled: bool) -> bool {
+    let mut found = false;
+    for sink in SINKS.lock().iter().flatten().filter(|s| s.name == name) {
+        sink.enabled.store(enabled, Ordering::Relaxed);
+        found = true;
+    }
+    for source in SOURCES.lock().iter().flatten().filter(|s| s.name == name) {
+        source.enabled.store(enabled, Ordering::Relaxed);
+        found = true;
+    }
+    found
+}
+
+/// Returns whether any sink or source with the given name is enabled.
+pub fn is_enabled(name: &str) -> bool {
+    let enabled = |n: &str, e: &AtomicBool| n == name && e.load(Ordering::Relaxed);
+    SINKS.lock().iter().flatten().any(|s| enabled(s.name, &s.enabled))
+        || SOURCES.lock().iter().flatten().any(|s| enabled(s.name, &s.enabled))
+}
+
+/// Sets the log level threshold of the sink with the given name.
+///
+/// Returns `false` if there is no such sink.
+pub fn set_level(name: &str, level: LevelFilter) -> bool {
+    let sinks = SINKS.lock();
+    let sink = sinks.iter().flatten().find(|s| s.name == name);
+    if let Some(sink) = sink {
+        sink.max_level.store(level as usize, Ordering::Relaxed);
+    }
+    sink.is_some()
+}
+
+fn sinks() -> [Option<&'static ConsoleSink>; MAX_CONSOLES] {
+    *SINKS.lock()
+}
+
+/// [`core::fmt::Write`] adapter over [`write_bytes`], for diagnostics that
+/// go straight to the console devices, bypassing the logger.
+#[cfg(feature = "sysrq")]
+pub(crate) struct Writer;
+
+#[cfg(feature = "sysrq")]
+impl core::fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// [`core::fmt::Write`] adapter over [`emergency_write`], for reports about
+/// a CPU that may be wedged with a console lock held.
+#[cfg(feature = "lockup-detector")]
+pub(crate) struct EmergencyWriter;
+
+#[cfg(feature = "lockup-detector")]
+impl core::fmt::Write for EmergencyWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        emergency_write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Writes bytes to every enabled sink, regardless of its threshold.
+pub fn write_bytes(bytes: &[u8]) {
+    write_log(LevelFilter::Off, bytes);
+}
+
+/// Switches the console to the emergency path for good.
+///
+/// To be called by the panic handler before printing the message. Pending
+/// UART output is flushed synchronously.
+pub fn enter_panic_mode() {
+    if !PANIC_MODE.swap(true, Ordering::Relaxed) {
+        crate::pl011::emergency_write(b"");
+        crate::ns16550::emergency_write(b"");
+    }
+}
+
+/// Writes bytes to the UARTs and the framebuffer without relying on their
+/// locks being free, and to the in-memory log if it can be locked.
+pub fn emergency_write(bytes: &[u8]) {
+    crate::pl011::emergency_write(bytes);
+    crate::ns16550::emergency_write(bytes);
+    crate::simplefb::emergency_write(bytes);
+    if let Some(mut log) = LOG_BUF.try_lock() {
+        for &c in bytes {
+            log.push_overwrite(c);
+        }
+    }
+}
+
+/// Locks `lock`, breaking it if it stays held for too long.
+///
+/// Only for the emergency path: the previous holder may be a CPU that
+/// panicked or is wedged with the lock held, possibly the current one.
+pub(crate) fn lock_or_break<T>(lock: &SpinNoIrq<T>) -> SpinNoIrqGuard<'_, T> {
+    for _ in 0..EMERGENCY_LOCK_TRIES {
+        if let Some(guard) = lock.try_lock() {
+            return guard;
+        }
+        core::hint::spin_loop();
+    }
+    // SAFETY: the holder is not making progress, and the data it protects
+    // is only used to print a last message.
+    unsafe { lock.force_unlock() };
+    lock.lock()
+}
+
+/// Writes a log message of the given level to every enabled sink whose
+/// threshold lets it through. [`LevelFilter::Off`] passes every threshold.
+///
+/// This is the entry point for the logger, which passes the level of the
+/// record for every piece of it, including continuation lines. Each sink is
+/// written under its own lock only.
+pub fn write_log(level: LevelFilter, bytes: &[u8]) {
+    if PANIC_MODE.load(Ordering::Relaxed) {
+        emergency_write(bytes);
+        return;
+    }
+    // The registry lock is not held while writing, so sinks do not
+    // serialize each other.
+    for sink in sinks().iter().flatten() {
+        if sink.accepts(level) {
+            #[cfg(feature = "serial-mux")]
+            if level != LevelFilter::Off
+                && core::ptr::eq(*sink, &crate::pl011::CONSOLE_SINK)
+                && crate::serial_mux::is_enabled()
+            {
+                crate::serial_mux::write(crate::serial_mux::LOG, bytes);
+                continue;
+            }
+            (sink.write)(bytes);
+        }
+    }
+}
+
+/// Writes bytes to every enabled sink except the in-memory log.
+#[cfg(feature = "sysrq")]
+pub(crate) fn write_devices(bytes: &[u8]) {
+    for sink in sinks().iter().flatten() {
+        if !core::ptr::eq(*sink, &MEM_SINK) && sink.accepts(LevelFilter::Off) {
+            (sink.write)(bytes);
+        }
+    }
+}
+
+/// Reads a byte from the first enabled source that has one.
+pub fn getchar() -> Option<u8> {
+    let sources = *SOURCES.lock();
+    sources
+        .iter()
+        .flatten()
+        .filter(|s| s.enabled.load(Ordering::Relaxed))
+        .find_map(|s| (s.read)())
+}
+
+/// Reads bytes from the console into the given mutable slice.
+/// Returns the number of bytes read.
+pub fn read_bytes(bytes: &mut [u8]) -> usize {
+    let mut read_len = 0;
+    while read_len < bytes.len() {
+        if let Some(c) = getchar() {
+            bytes[read_len] = c;
+        } else {
+            break;
+        }
+        read_len += 1;
+    }
+    read_len
+}
+
+/// Copies the most recent console output kept by the `mem` sink into `buf`.
+///
+/// Returns the number of bytes copied, oldest first.
+pub fn read_log_buffer(buf: &mut [u8]) -> usize {
+    let log = LOG_BUF.lock();
+    let skip = log.len().saturating_sub(buf.len());
+    let mut len = 0;
+    for (dst, src) in buf.iter_mut().zip(log.iter().skip(skip)) {
+        *dst = src;
+        len += 1;
+    }
+    len
+}
+
+/// PS/2 keyboard source, the input side of the framebuffer console.
+static KEYBOARD_SOURCE: ConsoleSource = ConsoleSource::new("fb0", crate::ps2::read_byte);
+
+/// Registers the built-in consoles available at early boot.
+pub(crate) fn init_early() {
+    register_sink(&MEM_SINK);
+    register_sink(&crate::pl011::CONSOLE_SINK);
+    register_source(&crate::pl011::CONSOLE_SOURCE);
+}
+
+/// Registers the framebuffer console and the keyboard, once initialized.
+pub(crate) fn init_later() {
+    register_sink(&crate::simplefb::CONSOLE_SINK);
+    register_source(&KEYBOARD_SOURCE);
+}
+
+/// Default implementation of [`axplat::console::ConsoleIf`] using the
+/// console multiplexer.
+struct ConsoleIfImpl;
+
+#[axplat::impl_plat_interface]
+impl axplat::console::ConsoleIf for ConsoleIfImpl {
+    /// Writes given bytes to the console.
+    fn write_bytes(bytes: &[u8]) {
+        write_bytes(bytes);
+    }
+
+    /// Reads bytes from the console into the given mutable slice.
+    ///
+    /// Returns the number of bytes read.
+    fn read_bytes(bytes: &mut [u8]) -> usize {
+        read_bytes(bytes)
+    }
+}
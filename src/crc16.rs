@@ -0,0 +1,19 @@
+//! CRC-16/XMODEM (polynomial 0x1021, initial value 0).
+//!
+//! Also built into the host-side serial multiplexer decoder, so it must only
+//! depend on `core`.
+
+/// Computes the CRC of `data`.
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, &b| {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
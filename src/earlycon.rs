@@ -0,0 +1,201 @@
+//! Early console on the PL011, accessed by physical address.
+//!
+//! It works from the first instructions of `_start_primary`, before there is
+//! a stack or the MMU is on (and afterwards, through the identity map of the
+//! boot page table). The routines here are plain assembly that only clobber
+//! caller-saved registers `x0`..`x7` and never touch the stack.
+//!
+//! With the `earlycon` feature, the boot code prints a marker for each stage:
+//!
+//! | Marker | Stage                                  |
+//! |--------|----------------------------------------|
+//! | `E`    | entered `_start_primary`               |
+//! | `R`    | running at the link address            |
+//! | `1`    | switched to EL1                        |
+//! | `P`    | boot page table built                  |
+//! | `M`    | MMU enabled, jumping to the kernel     |
+//!
+//! and installs exception vectors that dump `ESR`, `ELR` and `FAR` of the
+//! current EL and stop, until the kernel installs its own vectors in
+//! `init_early`. Without the feature, all routines are no-ops.
+//!
+//! The firmware is expected to have set up the UART line; the PL011 driver
+//! takes over in [`crate::pl011::init_early`] after the last byte has left.
+
+#[cfg(feature = "earlycon")]
+use crate::config::devices::UART_PADDR;
+
+/// Writes the byte in `w0`. Clobbers `x0`..`x2`.
+#[cfg(feature = "earlycon")]
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn putc() {
+    core::arch::naked_asm!("
+        movz    x1, #{uart0}
+        movk    x1, #{uart1}, lsl #16
+        movk    x1, #{uart2}, lsl #32
+        movk    x1, #{uart3}, lsl #48
+    1:  ldr     w2, [x1, #0x18]         // UARTFR
+        tbnz    w2, #5, 1b              // wait while TXFF
+        str     w0, [x1]                // UARTDR
+        ret",
+        uart0 = const UART_PADDR & 0xffff,
+        uart1 = const (UART_PADDR >> 16) & 0xffff,
+        uart2 = const (UART_PADDR >> 32) & 0xffff,
+        uart3 = const (UART_PADDR >> 48) & 0xffff,
+    )
+}
+
+/// Writes the NUL-terminated string at `x0`. Clobbers `x0`..`x4`.
+#[cfg(feature = "earlycon")]
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn puts() {
+    core::arch::naked_asm!("
+        mov     x4, x30
+        mov     x3, x0
+    1:  ldrb    w0, [x3], #1
+        cbz     w0, 2f
+        bl      {putc}
+        b       1b
+    2:  mov     x30, x4
+        ret",
+        putc = sym putc,
+    )
+}
+
+/// Writes `x0` as `0x` followed by 16 hex digits. Clobbers `x0`..`x6`.
+#[cfg(feature = "earlycon")]
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn puthex() {
+    core::arch::naked_asm!("
+        mov     x6, x30
+        mov     x5, x0
+        mov     x0, #0x30               // '0'
+        bl      {putc}
+        mov     x0, #0x78               // 'x'
+        bl      {putc}
+        mov     x3, #60
+    1:  lsr     x0, x5, x3
+        and     x0, x0, #0xf
+        cmp     x0, #10
+        b.lo    2f
+        add     x0, x0, #0x27           // 'a' - '0' - 10
+    2:  add     x0, x0, #0x30           // '0'
+        bl      {putc}
+        subs    x3, x3, #4
+        b.ge    1b
+        mov     x30, x6
+        ret",
+        putc = sym putc,
+    )
+}
+
+/// Prints the boot stage marker in `w0`. Clobbers `x0`..`x7`.
+#[cfg(feature = "earlycon")]
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn mark() {
+    core::arch::naked_asm!("
+        mov     x7, x30
+        mov     x5, x0
+        adr     x0, 1f
+        bl      {puts}
+        mov     x0, x5
+        bl      {putc}
+        mov     x0, #0x0d               // '\\r'
+        bl      {putc}
+        mov     x0, #0x0a               // '\\n'
+        bl      {putc}
+        mov     x30, x7
+        ret
+    1:  .asciz  \"[boot] \"
+        .balign 4",
+        puts = sym puts,
+        putc = sym putc,
+    )
+}
+
+/// Points `VBAR_EL1` (and `VBAR_EL2` when running at EL2) to the early
+/// exception vectors. Clobbers `x0`, `x1`.
+#[cfg(feature = "earlycon")]
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn install_vectors() {
+    core::arch::naked_asm!("
+        adr     x0, 3f
+        mrs     x1, CurrentEL
+        cmp     x1, #(2 << 2)
+        b.ne    1f
+        msr     vbar_el2, x0
+    1:  msr     vbar_el1, x0
+        isb
+        ret
+
+    // Reports the exception whose vector index is in x0, then stops.
+    2:  mov     x19, x0
+        mrs     x20, CurrentEL
+        adr     x0, 4f
+        bl      {puts}
+        mov     x0, x19
+        bl      {puthex}
+        adr     x0, 5f
+        bl      {puts}
+        lsr     x0, x20, #2
+        bl      {puthex}
+        cmp     x20, #(2 << 2)
+        b.eq    6f
+        mrs     x21, esr_el1
+        mrs     x22, elr_el1
+        mrs     x23, far_el1
+        b       7f
+    6:  mrs     x21, esr_el2
+        mrs     x22, elr_el2
+        mrs     x23, far_el2
+    7:  adr     x0, 8f
+        bl      {puts}
+        mov     x0, x21
+        bl      {puthex}
+        adr     x0, 9f
+        bl      {puts}
+        mov     x0, x22
+        bl      {puthex}
+        adr     x0, 10f
+        bl      {puts}
+        mov     x0, x23
+        bl      {puthex}
+        mov     x0, #0x0d               // '\\r'
+        bl      {putc}
+        mov     x0, #0x0a               // '\\n'
+        bl      {putc}
+    11: wfe
+        b       11b
+
+    4:  .asciz  \"\\r\\n[boot] exception vector \"
+    5:  .asciz  \" at EL\"
+    8:  .asciz  \"\\r\\n[boot] ESR \"
+    9:  .asciz  \" ELR \"
+    10: .asciz  \" FAR \"
+
+        .balign 0x800
+    3:
+        .irp    idx, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15
+        .balign 0x80
+        mov     x0, #\\idx
+        b       2b
+        .endr",
+        puts = sym puts,
+        putc = sym putc,
+        puthex = sym puthex,
+    )
+}
+
+/// No-op without the `earlycon` feature.
+#[cfg(not(feature = "earlycon"))]
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn mark() {
+    core::arch::naked_asm!("ret")
+}
+
+/// No-op without the `earlycon` feature.
+#[cfg(not(feature = "earlycon"))]
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn install_vectors() {
+    core::arch::naked_asm!("ret")
+}
@@ -0,0 +1,236 @@
+//! Embedded controller on the LPC bus (`ec` in `lpc-devices`), the ACPI
+//! EC interface of the laptop firmware.
+//!
+//! The battery, AC adapter and lid state are read from EC RAM, at the
+//! offsets given by the `ec-*` config. The defaults there are placeholders,
+//! not taken from the N80 firmware, so `ec` is not probed unless it is added
+//! to `lpc-devices` along with the real layout. Events the EC raises are fetched with
+//! query commands, by the EC interrupt when `ec-irq` is set (and the `irq`
+//! feature is on), and whenever events are read; [`read_event`] returns them
+//! with the state they announce, and [`crate::hotkey`] gets them too.
+
+mod protocol;
+
+use axplat::time::{NANOS_PER_MILLIS, monotonic_time_nanos};
+use kspin::SpinNoIrq;
+use log::{info, warn};
+
+use crate::config::devices::{
+    EC_AC_ONLINE, EC_BATTERY_CHARGING, EC_BATTERY_PERCENT, EC_BATTERY_PRESENT,
+    EC_BATTERY_VOLTAGE, EC_LID_OPEN, EC_QUERY_AC, EC_QUERY_BATTERY, EC_QUERY_LID,
+};
+use crate::lpc;
+use crate::ringbuf::RingBuffer;
+
+pub use protocol::{Battery, EcIo, Error};
+use protocol::{Bit, Layout};
+
+/// Data port.
+const DATA_PORT: u16 = 0x62;
+/// Status (read) and command (write) port.
+const COMMAND_PORT: u16 = 0x66;
+
+/// Most queries answered in one go; more means the EC is stuck signalling.
+const MAX_QUERIES: usize = 16;
+/// Size of the event queue.
+const EVENT_BUF_SIZE: usize = 16;
+
+/// Something the EC reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The AC adapter was plugged in (`true`) or out.
+    Ac(bool),
+    /// The lid was opened (`true`) or closed.
+    Lid(bool),
+    /// The battery state changed, see [`battery`].
+    Battery,
+    /// A query event with no meaning known here, e.g. a hotkey.
+    Query(u8),
+}
+
+const fn bit((addr, bit): (usize, usize)) -> Bit {
+    Bit {
+        addr: addr as u8,
+        bit: bit as u8,
+    }
+}
+
+static LAYOUT: Layout = Layout {
+    ac_online: bit(EC_AC_ONLINE),
+    lid_open: bit(EC_LID_OPEN),
+    battery_present: bit(EC_BATTERY_PRESENT),
+    battery_charging: bit(EC_BATTERY_CHARGING),
+    battery_percent: EC_BATTERY_PERCENT as u8,
+    battery_voltage: EC_BATTERY_VOLTAGE as u8,
+};
+
+/// The EC ports in the LPC window.
+struct LpcEc;
+
+impl EcIo for LpcEc {
+    fn status(&mut self) -> u8 {
+        lpc::inb(COMMAND_PORT)
+    }
+
+    fn read_data(&mut self) -> u8 {
+        lpc::inb(DATA_PORT)
+    }
+
+    fn write_data(&mut self, val: u8) {
+        lpc::outb(DATA_PORT, val)
+    }
+
+    fn write_command(&mut self, cmd: u8) {
+        lpc::outb(COMMAND_PORT, cmd)
+    }
+
+    fn now_ms(&mut self) -> u64 {
+        monotonic_time_nanos() / NANOS_PER_MILLIS
+    }
+}
+
+/// Serializes transactions, which are several port accesses each.
+static EC: SpinNoIrq<LpcEc> = SpinNoIrq::new(LpcEc);
+static EVENTS: SpinNoIrq<RingBuffer<Event, EVENT_BUF_SIZE>> = SpinNoIrq::new(RingBuffer::new());
+
+/// Runs `f` on the EC, if there is one.
+fn with_ec<T>(f: impl FnOnce(&mut LpcEc) -> Result<T, Error>) -> Result<T, Error> {
+    if !is_present() {
+        return Err(Error::Absent);
+    }
+    f(&mut EC.lock())
+}
+
+/// Turns a query number into an event, reading the state it announces.
+fn decode_query(ec: &mut LpcEc, query: u8) -> Result<Event, Error> {
+    Ok(match query as usize {
+        q if q == EC_QUERY_AC => Event::Ac(LAYOUT.ac_online(ec)?),
+        q if q == EC_QUERY_LID => Event::Lid(LAYOUT.lid_open(ec)?),
+        q if q == EC_QUERY_BATTERY => Event::Battery,
+        _ => Event::Query(query),
+    })
+}
+
+/// Fetches the events the EC signals and queues them, also passing them to
+/// [`crate::hotkey`] if `notify` is set.
+///
+/// Returns whether anything was queued.
+fn fetch(notify: bool) -> bool {
+    if !is_present() {
+        return false;
+    }
+    let mut ec = EC.lock();
+    let mut queued = false;
+    for _ in 0..MAX_QUERIES {
+        if ec.status() & protocol::STATUS_SCI_EVT == 0 {
+            break;
+        }
+        let event = match protocol::query(&mut *ec) {
+            Ok(Some(query)) => decode_query(&mut ec, query),
+            Ok(None) => break,
+            Err(e) => Err(e),
+        };
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("EC: query failed: {:?}", e);
+                break;
+            }
+        };
+        if !EVENTS.lock().push(event) {
+            warn!("EC: event queue full, dropping {:?}", event);
+        }
+        if notify {
+            crate::hotkey::ec_event(event);
+        }
+        queued = true;
+    }
+    queued
+}
+
+/// Fetches the events the EC signals.
+///
+/// Returns whether anything was queued.
+pub(crate) fn poll() -> bool {
+    fetch(true)
+}
+
+/// Returns whether the EC was found.
+pub fn is_present() -> bool {
+    lpc::is_present("ec")
+}
+
+/// Reads a byte of EC RAM.
+pub fn read(addr: u8) -> Result<u8, Error> {
+    with_ec(|ec| protocol::read(ec, addr))
+}
+
+/// Writes a byte of EC RAM.
+pub fn write(addr: u8, val: u8) -> Result<(), Error> {
+    with_ec(|ec| protocol::write(ec, addr, val))
+}
+
+/// Returns whether the AC adapter is plugged in.
+pub fn ac_online() -> Result<bool, Error> {
+    with_ec(|ec| LAYOUT.ac_online(ec))
+}
+
+/// Returns whether the lid is open.
+pub fn lid_open() -> Result<bool, Error> {
+    with_ec(|ec| LAYOUT.lid_open(ec))
+}
+
+/// Returns the battery state.
+pub fn battery() -> Result<Battery, Error> {
+    with_ec(|ec| LAYOUT.battery(ec))
+}
+
+/// Reads the next EC event, or returns [`None`] if there is none.
+pub fn read_event() -> Option<Event> {
+    poll();
+    crate::hotkey::dispatch();
+    EVENTS.lock().pop()
+}
+
+/// Returns whether something answers at the EC ports. A floating bus reads
+/// as all ones.
+pub(crate) fn probe() -> bool {
+    lpc::inb(COMMAND_PORT) != 0xff
+}
+
+/// Logs the power state, and drops events raised before boot.
+pub(crate) fn init() {
+    if !is_present() {
+        return;
+    }
+    fetch(false);
+    EVENTS.lock().clear();
+    match (ac_online(), lid_open(), battery()) {
+        (Ok(ac), Ok(lid), Ok(battery)) => info!(
+            "EC: AC {}, lid {}, battery {:?}",
+            if ac { "online" } else { "offline" },
+            if lid { "open" } else { "closed" },
+            battery,
+        ),
+        (Err(e), ..) | (_, Err(e), _) | (.., Err(e)) => {
+            warn!("EC: cannot read power state: {:?}", e)
+        }
+    }
+}
+
+/// Enables the EC interrupt if `ec-irq` is set.
+#[cfg(feature = "irq")]
+pub(crate) fn init_irq() {
+    use crate::config::devices::EC_IRQ;
+    if !is_present() || EC_IRQ == 0 {
+        return;
+    }
+    axplat::irq::register(EC_IRQ, irq_handler);
+}
+
+#[cfg(feature = "irq")]
+fn irq_handler() {
+    if poll() {
+        crate::hotkey::dispatch();
+    }
+}
@@ -0,0 +1,380 @@
+//! ACPI embedded controller protocol.
+//!
+//! The host talks to the EC through a command/status port and a data port.
+//! Every byte written waits for the input buffer to empty, every byte read
+//! waits for the output buffer to fill, each with a timeout.
+//!
+//! This file only depends on `core`, and reaches the ports through an
+//! [`EcIo`], so that the tests below can run it against a simulated EC. The
+//! `host-tests` crate builds it for the host to run them.
+
+/// Status bits.
+const STATUS_OBF: u8 = 1 << 0;
+const STATUS_IBF: u8 = 1 << 1;
+/// An SCI event is pending, fetch it with [`query`].
+pub const STATUS_SCI_EVT: u8 = 1 << 5;
+
+/// Commands.
+const CMD_READ: u8 = 0x80;
+const CMD_WRITE: u8 = 0x81;
+const CMD_QUERY: u8 = 0x84;
+
+/// Timeout of each byte of a transaction.
+const TIMEOUT_MS: u64 = 10;
+
+/// Access to the EC ports.
+pub trait EcIo {
+    fn status(&mut self) -> u8;
+    fn read_data(&mut self) -> u8;
+    fn write_data(&mut self, val: u8);
+    fn write_command(&mut self, cmd: u8);
+    /// Milliseconds since an arbitrary origin, for timeouts.
+    fn now_ms(&mut self) -> u64;
+}
+
+/// An EC error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// There is no EC (`ec` is not in `lpc-devices`, or did not answer).
+    Absent,
+    /// The EC did not take or deliver a byte in time.
+    Timeout,
+}
+
+fn wait<I: EcIo>(io: &mut I, mask: u8, set: bool) -> Result<(), Error> {
+    let deadline = io.now_ms() + TIMEOUT_MS;
+    loop {
+        if (io.status() & mask != 0) == set {
+            return Ok(());
+        }
+        if io.now_ms() >= deadline {
+            return Err(Error::Timeout);
+        }
+        core::hint::spin_loop();
+    }
+}
+
+fn send_command<I: EcIo>(io: &mut I, cmd: u8) -> Result<(), Error> {
+    wait(io, STATUS_IBF, false)?;
+    io.write_command(cmd);
+    Ok(())
+}
+
+fn send_data<I: EcIo>(io: &mut I, val: u8) -> Result<(), Error> {
+    wait(io, STATUS_IBF, false)?;
+    io.write_data(val);
+    Ok(())
+}
+
+fn recv_data<I: EcIo>(io: &mut I) -> Result<u8, Error> {
+    wait(io, STATUS_OBF, true)?;
+    Ok(io.read_data())
+}
+
+/// Drops a byte left in the output buffer by an aborted transaction.
+fn flush<I: EcIo>(io: &mut I) {
+    if io.status() & STATUS_OBF != 0 {
+        io.read_data();
+    }
+}
+
+/// Reads a byte of EC RAM.
+pub fn read<I: EcIo>(io: &mut I, addr: u8) -> Result<u8, Error> {
+    flush(io);
+    send_command(io, CMD_READ)?;
+    send_data(io, addr)?;
+    recv_data(io)
+}
+
+/// Writes a byte of EC RAM, returning once the EC took it.
+pub fn write<I: EcIo>(io: &mut I, addr: u8, val: u8) -> Result<(), Error> {
+    flush(io);
+    send_command(io, CMD_WRITE)?;
+    send_data(io, addr)?;
+    send_data(io, val)?;
+    wait(io, STATUS_IBF, false)
+}
+
+/// Reads a little-endian 16-bit value from two consecutive EC RAM bytes.
+pub fn read_u16<I: EcIo>(io: &mut I, addr: u8) -> Result<u16, Error> {
+    Ok(u16::from_le_bytes([read(io, addr)?, read(io, addr.wrapping_add(1))?]))
+}
+
+/// Fetches the pending query event, or returns [`None`] if there is none.
+pub fn query<I: EcIo>(io: &mut I) -> Result<Option<u8>, Error> {
+    flush(io);
+    send_command(io, CMD_QUERY)?;
+    let event = recv_data(io)?;
+    Ok((event != 0).then_some(event))
+}
+
+/// A flag bit in EC RAM.
+#[derive(Debug, Clone, Copy)]
+pub struct Bit {
+    pub addr: u8,
+    pub bit: u8,
+}
+
+impl Bit {
+    fn read<I: EcIo>(self, io: &mut I) -> Result<bool, Error> {
+        Ok(read(io, self.addr)? & (1 << self.bit) != 0)
+    }
+}
+
+/// Where the EC firmware keeps the power and lid state.
+///
+/// Firmware-specific: the fields of the machine's ACPI EC operation region
+/// give the offsets, which the `ec-*` config must repeat.
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+    pub ac_online: Bit,
+    pub lid_open: Bit,
+    pub battery_present: Bit,
+    pub battery_charging: Bit,
+    /// Remaining charge in percent.
+    pub battery_percent: u8,
+    /// Battery voltage in mV, 16 bits.
+    pub battery_voltage: u8,
+}
+
+/// Battery state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Battery {
+    pub present: bool,
+    pub charging: bool,
+    /// Remaining charge in percent, at most 100.
+    pub percent: u8,
+    pub voltage_mv: u16,
+}
+
+impl Layout {
+    pub fn ac_online<I: EcIo>(&self, io: &mut I) -> Result<bool, Error> {
+        self.ac_online.read(io)
+    }
+
+    pub fn lid_open<I: EcIo>(&self, io: &mut I) -> Result<bool, Error> {
+        self.lid_open.read(io)
+    }
+
+    pub fn battery<I: EcIo>(&self, io: &mut I) -> Result<Battery, Error> {
+        if !self.battery_present.read(io)? {
+            return Ok(Battery::default());
+        }
+        Ok(Battery {
+            present: true,
+            charging: self.battery_charging.read(io)?,
+            percent: read(io, self.battery_percent)?.min(100),
+            voltage_mv: read_u16(io, self.battery_voltage)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::VecDeque;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Status bit telling the pending input byte is a command.
+    const STATUS_CMD: u8 = 1 << 3;
+
+    /// Status reads the model takes to consume each byte written.
+    const BUSY_READS: usize = 3;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum State {
+        Idle,
+        ReadAddr,
+        WriteAddr,
+        WriteData(u8),
+    }
+
+    /// An ACPI EC as seen through its two ports.
+    struct Model {
+        ram: [u8; 256],
+        state: State,
+        /// Byte written and not yet consumed, with whether it is a command.
+        input: Option<(u8, bool)>,
+        busy: usize,
+        output: Option<u8>,
+        queries: VecDeque<u8>,
+        /// Never consumes input, to exercise timeouts.
+        dead: bool,
+        clock: u64,
+        /// Commands received, for checking.
+        commands: Vec<u8>,
+    }
+
+    impl Model {
+        fn new() -> Self {
+            Self {
+                ram: [0; 256],
+                state: State::Idle,
+                input: None,
+                busy: 0,
+                output: None,
+                queries: VecDeque::new(),
+                dead: false,
+                clock: 0,
+                commands: Vec::new(),
+            }
+        }
+
+        /// Consumes the pending input byte once the model is done being busy.
+        fn step(&mut self) {
+            let Some((byte, command)) = self.input else {
+                return;
+            };
+            if self.dead || self.busy > 0 {
+                self.busy = self.busy.saturating_sub(1);
+                return;
+            }
+            self.input = None;
+            if command {
+                self.commands.push(byte);
+                self.state = State::Idle;
+                match byte {
+                    0x80 => self.state = State::ReadAddr,
+                    0x81 => self.state = State::WriteAddr,
+                    0x84 => self.output = Some(self.queries.pop_front().unwrap_or(0)),
+                    _ => {}
+                }
+                return;
+            }
+            self.state = match self.state {
+                State::ReadAddr => {
+                    self.output = Some(self.ram[byte as usize]);
+                    State::Idle
+                }
+                State::WriteAddr => State::WriteData(byte),
+                State::WriteData(addr) => {
+                    self.ram[addr as usize] = byte;
+                    State::Idle
+                }
+                State::Idle => State::Idle,
+            };
+        }
+
+        fn write(&mut self, byte: u8, command: bool) {
+            assert!(
+                self.input.is_none(),
+                "byte {byte:#x} written while STATUS_IBF set"
+            );
+            self.input = Some((byte, command));
+            self.busy = BUSY_READS;
+        }
+    }
+
+    impl EcIo for Model {
+        fn status(&mut self) -> u8 {
+            self.step();
+            let mut status = 0;
+            if self.output.is_some() {
+                status |= STATUS_OBF;
+            }
+            if let Some((_, command)) = self.input {
+                status |= STATUS_IBF;
+                if command {
+                    status |= STATUS_CMD;
+                }
+            }
+            if !self.queries.is_empty() {
+                status |= STATUS_SCI_EVT;
+            }
+            status
+        }
+
+        fn read_data(&mut self) -> u8 {
+            self.output.take().unwrap_or(0xff)
+        }
+
+        fn write_data(&mut self, val: u8) {
+            self.write(val, false);
+        }
+
+        fn write_command(&mut self, cmd: u8) {
+            self.write(cmd, true);
+        }
+
+        fn now_ms(&mut self) -> u64 {
+            self.clock += 1;
+            self.clock
+        }
+    }
+
+    const LAYOUT: Layout = Layout {
+        ac_online: Bit { addr: 0xa0, bit: 0 },
+        lid_open: Bit { addr: 0xa0, bit: 1 },
+        battery_present: Bit { addr: 0xa1, bit: 0 },
+        battery_charging: Bit { addr: 0xa1, bit: 1 },
+        battery_percent: 0xa2,
+        battery_voltage: 0xa4,
+    };
+
+    #[test]
+    fn read_write() {
+        let mut ec = Model::new();
+        ec.ram[0x10] = 0x5a;
+        assert_eq!(read(&mut ec, 0x10), Ok(0x5a));
+        assert_eq!(write(&mut ec, 0x11, 0xa5), Ok(()));
+        assert_eq!(ec.ram[0x11], 0xa5);
+        assert_eq!(read(&mut ec, 0x11), Ok(0xa5));
+        ec.ram[0x20] = 0x34;
+        ec.ram[0x21] = 0x12;
+        assert_eq!(read_u16(&mut ec, 0x20), Ok(0x1234));
+        assert_eq!(ec.commands, vec![0x80, 0x81, 0x80, 0x80, 0x80]);
+
+        // A stale output byte is dropped.
+        ec.output = Some(0x77);
+        assert_eq!(read(&mut ec, 0x10), Ok(0x5a));
+    }
+
+    #[test]
+    fn queries() {
+        let mut ec = Model::new();
+        assert_eq!(query(&mut ec), Ok(None));
+        ec.queries.extend([0x20, 0x45]);
+        assert_ne!(ec.status() & STATUS_SCI_EVT, 0);
+        assert_eq!(query(&mut ec), Ok(Some(0x20)));
+        assert_eq!(query(&mut ec), Ok(Some(0x45)));
+        assert_eq!(ec.status() & STATUS_SCI_EVT, 0);
+    }
+
+    #[test]
+    fn layout() {
+        let mut ec = Model::new();
+        ec.ram[0xa0] = 0b10;
+        ec.ram[0xa1] = 0b11;
+        ec.ram[0xa2] = 87;
+        ec.ram[0xa4] = 0x4c;
+        ec.ram[0xa5] = 0x2d;
+        assert_eq!(LAYOUT.ac_online(&mut ec), Ok(false));
+        assert_eq!(LAYOUT.lid_open(&mut ec), Ok(true));
+        assert_eq!(
+            LAYOUT.battery(&mut ec),
+            Ok(Battery {
+                present: true,
+                charging: true,
+                percent: 87,
+                voltage_mv: 11596
+            }),
+        );
+        // The percentage is clamped.
+        ec.ram[0xa2] = 180;
+        assert_eq!(LAYOUT.battery(&mut ec).map(|b| b.percent), Ok(100));
+        ec.ram[0xa1] = 0;
+        assert_eq!(LAYOUT.battery(&mut ec), Ok(Battery::default()));
+    }
+
+    #[test]
+    fn dead_ec() {
+        let mut ec = Model::new();
+        ec.dead = true;
+        assert_eq!(read(&mut ec, 0), Err(Error::Timeout));
+        ec.dead = false;
+        ec.output = None;
+        assert_eq!(read(&mut ec, 0), Ok(0));
+    }
+}
@@ -0,0 +1,85 @@
+//! Access to the UEFI system table left behind by the firmware.
+//!
+//! The EFI system table address is published by the EFI stub in the
+//! `/chosen/linux,uefi-system-table` DTB property. Runtime services have not
+//! been remapped with `SetVirtualAddressMap`, so everything here is accessed
+//! at its physical address through the identity map of the boot page table,
+//! and must only be used from [`axplat::init::InitIf::init_early`].
+
+const EFI_SYSTEM_TABLE_SIGNATURE: u64 = 0x5453_5953_2049_4249; // "IBI SYST"
+#[cfg(feature = "rtc")]
+const EFI_RUNTIME_SERVICES_SIGNATURE: u64 = 0x5652_4553_544e_5552; // "RUNTSERV"
+
+/// Offset of `RuntimeServices` in `EFI_SYSTEM_TABLE`.
+#[cfg(feature = "rtc")]
+const SYSTAB_RUNTIME_SERVICES: usize = 88;
+/// Offset of `NumberOfTableEntries` in `EFI_SYSTEM_TABLE`.
+const SYSTAB_NR_TABLES: usize = 104;
+/// Offset of `ConfigurationTable` in `EFI_SYSTEM_TABLE`.
+const SYSTAB_CONFIG_TABLE: usize = 112;
+/// Size of an `EFI_CONFIGURATION_TABLE` entry.
+const CONFIG_TABLE_ENTRY_SIZE: usize = 24;
+
+/// Offset of `GetTime` in `EFI_RUNTIME_SERVICES`.
+#[cfg(feature = "rtc")]
+pub const RT_GET_TIME: usize = 24;
+
+/// `EFI_ACPI_20_TABLE_GUID`, in memory layout.
+#[cfg(feature = "watchdog")]
+pub const ACPI_20_TABLE_GUID: [u8; 16] = [
+    0x71, 0xe8, 0x68, 0x88, 0xf1, 0xe4, 0xd3, 0x11, 0xbc, 0x22, 0x00, 0x80, 0xc7, 0x3c, 0x88,
+    0x81,
+];
+
+/// Reads a table entry, validating the table header signature first.
+///
+/// # Safety
+///
+/// `table` must be identity mapped.
+#[cfg(feature = "rtc")]
+unsafe fn table_entry(table: usize, signature: u64, offset: usize) -> Option<usize> {
+    if table == 0 || unsafe { core::ptr::read_volatile(table as *const u64) } != signature {
+        return None;
+    }
+    let entry = unsafe { core::ptr::read_volatile((table + offset) as *const usize) };
+    (entry != 0).then_some(entry)
+}
+
+/// Returns the physical address of the EFI system table, if any.
+pub fn system_table() -> Option<usize> {
+    let systab = crate::fdt::get()?
+        .find_node("/chosen")?
+        .property_u64("linux,uefi-system-table")? as usize;
+    // SAFETY: firmware tables live in low memory, identity mapped at boot.
+    let signature = unsafe { core::ptr::read_volatile(systab as *const u64) };
+    (signature == EFI_SYSTEM_TABLE_SIGNATURE).then_some(systab)
+}
+
+/// Returns the physical address of the runtime service at `offset` in
+/// `EFI_RUNTIME_SERVICES`.
+#[cfg(feature = "rtc")]
+pub fn runtime_service(offset: usize) -> Option<usize> {
+    let systab = system_table()?;
+    // SAFETY: see `system_table`.
+    unsafe {
+        let rt = table_entry(systab, EFI_SYSTEM_TABLE_SIGNATURE, SYSTAB_RUNTIME_SERVICES)?;
+        table_entry(rt, EFI_RUNTIME_SERVICES_SIGNATURE, offset)
+    }
+}
+
+/// Returns the physical address of the configuration table with the given
+/// vendor GUID.
+pub fn config_table(guid: &[u8; 16]) -> Option<usize> {
+    let systab = system_table()?;
+    // SAFETY: see `system_table`.
+    unsafe {
+        let count = core::ptr::read_volatile((systab + SYSTAB_NR_TABLES) as *const usize);
+        let tables = core::ptr::read_volatile((systab + SYSTAB_CONFIG_TABLE) as *const usize);
+        (0..count).find_map(|i| {
+            let entry = tables + i * CONFIG_TABLE_ENTRY_SIZE;
+            let entry_guid = core::ptr::read_unaligned(entry as *const [u8; 16]);
+            (entry_guid == *guid)
+                .then(|| core::ptr::read_unaligned((entry + 16) as *const usize))
+        })
+    }
+}
@@ -0,0 +1,299 @@
+//! Minimal flattened device tree (DTB) reader.
+//!
+//! Only what the platform needs at boot is supported: looking up nodes by
+//! path or `compatible` string and reading their properties.
+//!
+//! The blob handed over by the bootloader lives in allocatable RAM, so
+//! [`get`] must only be used during platform initialization.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axplat::mem::{pa, phys_to_virt};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+static DTB_VADDR: AtomicUsize = AtomicUsize::new(0);
+
+/// Records the physical address of the DTB passed by the bootloader.
+pub fn init(dtb_paddr: usize) {
+    if dtb_paddr != 0 {
+        DTB_VADDR.store(phys_to_virt(pa!(dtb_paddr)).as_usize(), Ordering::Relaxed);
+    }
+}
+
+/// Returns the boot DTB, or [`None`] if there is none or it is invalid.
+pub fn get() -> Option<Fdt<'static>> {
+    // SAFETY: the address came from the bootloader and the blob stays intact
+    // until platform initialization is done.
+    unsafe { Fdt::from_ptr(DTB_VADDR.load(Ordering::Relaxed)) }
+}
+
+fn be32(bytes: &[u8], off: usize) -> Option<u32> {
+    let b = bytes.get(off..off + 4)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+const fn align4(off: usize) -> usize {
+    (off + 3) & !3
+}
+
+/// A parsed device tree blob.
+#[derive(Clone, Copy)]
+pub struct Fdt<'a> {
+    structs: &'a [u8],
+    strings: &'a [u8],
+}
+
+/// A node inside a [`Fdt`].
+#[derive(Clone, Copy)]
+pub struct FdtNode<'a> {
+    fdt: Fdt<'a>,
+    name: &'a str,
+    /// Offset of the first token after the node name.
+    body: usize,
+}
+
+impl<'a> Fdt<'a> {
+    /// Parses the device tree blob located at the given virtual address.
+    ///
+    /// Returns [`None`] if the address is null or the header is invalid.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must point to readable memory holding the whole blob, which must
+    /// stay valid for `'a`.
+    pub unsafe fn from_ptr(addr: usize) -> Option<Self> {
+        if addr == 0 {
+            return None;
+        }
+        let header = unsafe { core::slice::from_raw_parts(addr as *const u8, 40) };
+        if be32(header, 0)? != FDT_MAGIC {
+            return None;
+        }
+        let total_size = be32(header, 4)? as usize;
+        let data = unsafe { core::slice::from_raw_parts(addr as *const u8, total_size) };
+        Self::new(data)
+    }
+
+    /// Parses a device tree blob held in `data`.
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        if be32(data, 0)? != FDT_MAGIC {
+            return None;
+        }
+        let off_struct = be32(data, 8)? as usize;
+        let off_strings = be32(data, 12)? as usize;
+        let size_strings = be32(data, 32)? as usize;
+        let size_struct = be32(data, 36)? as usize;
+        Some(Self {
+            structs: data.get(off_struct..off_struct + size_struct)?,
+            strings: data.get(off_strings..off_strings + size_strings)?,
+        })
+    }
+
+    fn token(&self, off: usize) -> Option<u32> {
+        be32(self.structs, off)
+    }
+
+    fn cstr(bytes: &'a [u8], off: usize) -> Option<&'a str> {
+        let rest = bytes.get(off..)?;
+        let len = rest.iter().position(|&b| b == 0)?;
+        core::str::from_utf8(&rest[..len]).ok()
+    }
+
+    /// Reads the node starting at `off` (which must hold `FDT_BEGIN_NODE`).
+    fn node_at(&self, off: usize) -> Option<FdtNode<'a>> {
+        if self.token(off)? != FDT_BEGIN_NODE {
+            return None;
+        }
+        let name = Self::cstr(self.structs, off + 4)?;
+        Some(FdtNode {
+            fdt: *self,
+            name,
+            body: align4(off + 4 + name.len() + 1),
+        })
+    }
+
+    /// Returns the offset just past the node starting at `off`.
+    fn skip_node(&self, mut off: usize) -> Option<usize> {
+        let mut depth = 0usize;
+        loop {
+            match self.token(off)? {
+                FDT_BEGIN_NODE => {
+                    let name = Self::cstr(self.structs, off + 4)?;
+                    off = align4(off + 4 + name.len() + 1);
+                    depth += 1;
+                }
+                FDT_END_NODE => {
+                    off += 4;
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(off);
+                    }
+                }
+                FDT_PROP => {
+                    let len = self.token(off + 4)? as usize;
+                    off = align4(off + 12 + len);
+                }
+                FDT_NOP => off += 4,
+                _ => return None,
+            }
+        }
+    }
+
+    /// Returns the root node.
+    pub fn root(&self) -> Option<FdtNode<'a>> {
+        let mut off = 0;
+        while self.token(off)? == FDT_NOP {
+            off += 4;
+        }
+        self.node_at(off)
+    }
+
+    /// Looks up a node by its absolute path, e.g. `/chosen/framebuffer`.
+    ///
+    /// Path components may omit the unit address (`framebuffer` matches
+    /// `framebuffer@ecd20000`).
+    pub fn find_node(&self, path: &str) -> Option<FdtNode<'a>> {
+        let mut node = self.root()?;
+        for comp in path.split('/').filter(|c| !c.is_empty()) {
+            node = node.children().find(|child| child.matches_name(comp))?;
+        }
+        Some(node)
+    }
+
+    /// Returns the first node (depth first) for which `f` is true, along with
+    /// its parent, whose [`cells`](FdtNode::cells) give the layout of its
+    /// `reg`.
+    pub fn find_with_parent(
+        &self,
+        f: impl Fn(&FdtNode<'a>) -> bool,
+    ) -> Option<(FdtNode<'a>, FdtNode<'a>)> {
+        fn find<'a>(
+            parent: FdtNode<'a>,
+            f: &impl Fn(&FdtNode<'a>) -> bool,
+        ) -> Option<(FdtNode<'a>, FdtNode<'a>)> {
+            parent
+                .children()
+                .find_map(|child| if f(&child) { Some((parent, child)) } else { find(child, f) })
+        }
+        find(self.root()?, &f)
+    }
+}
+
+impl<'a> FdtNode<'a> {
+    fn matches_name(&self, name: &str) -> bool {
+        self.name == name || self.name.split('@').next() == Some(name)
+    }
+
+    /// Returns the raw value of the given property.
+    pub fn property(&self, name: &str) -> Option<&'a [u8]> {
+        let fdt = &self.fdt;
+        let mut off = self.body;
+        loop {
+            match fdt.token(off)? {
+                FDT_PROP => {
+                    let len = fdt.token(off + 4)? as usize;
+                    let name_off = fdt.token(off + 8)? as usize;
+                    if Fdt::cstr(fdt.strings, name_off)? == name {
+                        return fdt.structs.get(off + 12..off + 12 + len);
+                    }
+                    off = align4(off + 12 + len);
+                }
+                FDT_NOP => off += 4,
+                // Properties always precede subnodes.
+                _ => return None,
+            }
+        }
+    }
+
+    /// Returns the given property as a NUL-terminated string.
+    pub fn property_str(&self, name: &str) -> Option<&'a str> {
+        Fdt::cstr(self.property(name)?, 0)
+    }
+
+    /// Returns the given property as a big-endian integer of one or two
+    /// cells.
+    pub fn property_u64(&self, name: &str) -> Option<u64> {
+        let value = self.property(name)?;
+        match value.len() {
+            4 => Some(be32(value, 0)? as u64),
+            8 => Some(((be32(value, 0)? as u64) << 32) | be32(value, 4)? as u64),
+            _ => None,
+        }
+    }
+
+    /// Returns the `#address-cells` and `#size-cells` of this node, which
+    /// give the layout of the `reg` property of its children. They default
+    /// to 2 and 1.
+    pub fn cells(&self) -> (usize, usize) {
+        let cells = |name, default| match self.property(name) {
+            Some(value) => be32(value, 0).map_or(default, |n| n as usize),
+            None => default,
+        };
+        (cells("#address-cells", 2), cells("#size-cells", 1))
+    }
+
+    /// Returns the `index`-th `(address, size)` pair of the `reg` property,
+    /// laid out as given by the [`cells`](Self::cells) of the parent node.
+    ///
+    /// Returns [`None`] if either count is above 2.
+    pub fn reg_cells(
+        &self,
+        index: usize,
+        (address_cells, size_cells): (usize, usize),
+    ) -> Option<(u64, u64)> {
+        if address_cells > 2 || size_cells > 2 {
+            return None;
+        }
+        let reg = self.property("reg")?;
+        let mut off = index * (address_cells + size_cells) * 4;
+        let mut read = |cells: usize| {
+            (0..cells).try_fold(0, |acc, _| {
+                let cell = be32(reg, off)?;
+                off += 4;
+                Some((acc << 32) | u64::from(cell))
+            })
+        };
+        Some((read(address_cells)?, read(size_cells)?))
+    }
+
+    /// Returns whether the `compatible` list contains `compat`.
+    pub fn is_compatible(&self, compat: &str) -> bool {
+        self.property("compatible").is_some_and(|list| {
+            list.split(|&b| b == 0)
+                .any(|entry| entry == compat.as_bytes())
+        })
+    }
+
+    /// Iterates over the direct children of this node.
+    pub fn children(&self) -> impl Iterator<Item = FdtNode<'a>> + 'a {
+        let fdt = self.fdt;
+        let mut off = Some(self.body);
+        core::iter::from_fn(move || {
+            loop {
+                let cur = off?;
+                match fdt.token(cur)? {
+                    FDT_PROP => {
+                        let len = fdt.token(cur + 4)? as usize;
+                        off = Some(align4(cur + 12 + len));
+                    }
+                    FDT_NOP => off = Some(cur + 4),
+                    FDT_BEGIN_NODE => {
+                        off = fdt.skip_node(cur);
+                        return fdt.node_at(cur);
+                    }
+                    FDT_END_NODE | FDT_END => {
+                        off = None;
+                        return None;
+                    }
+                    _ => return None,
+                }
+            }
+        })
+    }
+}
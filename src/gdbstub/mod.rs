@@ -0,0 +1,384 @@
+//! GDB remote stub.
+//!
+//! Speaks the GDB Remote Serial Protocol on the console UART, or on a
+//! dedicated PL011 (`gdb-uart-paddr`). The kernel stops in the stub when:
+//!
+//! - a breakpoint set by the debugger, or a compiled-in [`breakpoint`], is hit;
+//! - a single step requested by the debugger completes;
+//! - Ctrl-C arrives on the line (needs the `irq` feature). On a dedicated UART
+//!   the start of a packet also stops the kernel, so GDB can attach to a
+//!   running system; on the console UART, press Ctrl-C in the terminal before
+//!   starting GDB;
+//! - the `wait` mode is selected, once at the end of platform initialization.
+//!
+//! While stopped, the other CPUs spin in the `gdb-ipi-irq` handler. The mode
+//! is set by the `gdb-mode` config and the `gdb=<off|on|wait>` boot argument.
+//!
+//! ```text
+//! $ gdb-multiarch kernel.elf -ex 'target remote /dev/ttyUSB0'
+//! ```
+
+mod patch;
+mod protocol;
+mod vectors;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use axplat::mem::{pa, phys_to_virt};
+use kspin::SpinNoIrq;
+use lazyinit::LazyInit;
+use log::info;
+
+use self::protocol::{Port, Resume, Session, Target};
+use self::vectors::TrapFrame;
+use crate::config::devices::{GDB_MODE, GDB_UART_PADDR, UART_PADDR};
+#[cfg(feature = "irq")]
+use crate::config::devices::{GDB_IPI_IRQ, GDB_UART_IRQ};
+
+/// `BRK` immediates: breakpoints inserted by the debugger, [`breakpoint`],
+/// and Ctrl-C.
+const BRK_IMM_DEBUGGER: u64 = 0x400;
+const BRK_IMM_COMPILED: u64 = 0x401;
+const BRK_IMM_BREAK_IN: u64 = 0x402;
+
+/// Encoding of `BRK #BRK_IMM_DEBUGGER`.
+const BRK_DEBUGGER_INSN: u32 = 0xd420_0000 | (BRK_IMM_DEBUGGER as u32) << 5;
+
+/// Exception classes in `ESR_EL1`.
+const EC_BRK64: u64 = 0x3c;
+const EC_SOFTSTEP_CUR_EL: u64 = 0x33;
+
+/// Signals reported to the debugger.
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// `SPSR_EL1` bits.
+const SPSR_SS: u64 = 1 << 21;
+const SPSR_D: u64 = 1 << 9;
+const SPSR_I: u64 = 1 << 7;
+
+/// `MDSCR_EL1` bits: software step, and debug exceptions at EL1.
+const MDSCR_SS: u64 = 1 << 0;
+const MDSCR_KDE: u64 = 1 << 13;
+
+const MAX_BREAKPOINTS: usize = 32;
+
+/// Ctrl-C.
+#[cfg(feature = "irq")]
+const BREAK_CHAR: u8 = 0x03;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Off,
+    On,
+    Wait,
+}
+
+impl Mode {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "off" => Some(Self::Off),
+            "on" => Some(Self::On),
+            "wait" => Some(Self::Wait),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    addr: usize,
+    /// The instruction replaced by `BRK`.
+    insn: u32,
+}
+
+struct Breakpoints([Option<Breakpoint>; MAX_BREAKPOINTS]);
+
+impl Breakpoints {
+    fn contains(&self, addr: usize) -> bool {
+        self.0.iter().flatten().any(|bp| bp.addr == addr)
+    }
+
+    fn remove_all(&mut self) {
+        for slot in self.0.iter_mut() {
+            if let Some(bp) = slot.take() {
+                patch::write_insn(bp.addr, bp.insn);
+            }
+        }
+    }
+}
+
+impl Target for Breakpoints {
+    fn insert_breakpoint(&mut self, addr: usize) -> bool {
+        if self.contains(addr) {
+            return true;
+        }
+        if !addr.is_multiple_of(4) {
+            return false;
+        }
+        let Some(slot) = self.0.iter_mut().find(|slot| slot.is_none()) else {
+            return false;
+        };
+        let mut bytes = [0u8; 4];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            match vectors::read_u8(addr + i) {
+                Some(val) => *b = val,
+                None => return false,
+            }
+        }
+        if !patch::write_insn(addr, BRK_DEBUGGER_INSN) {
+            return false;
+        }
+        *slot = Some(Breakpoint {
+            addr,
+            insn: u32::from_le_bytes(bytes),
+        });
+        true
+    }
+
+    fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        let slot = self.0.iter_mut().find(|slot| slot.is_some_and(|bp| bp.addr == addr));
+        match slot.and_then(|slot| slot.take()) {
+            Some(bp) => patch::write_insn(bp.addr, bp.insn),
+            None => false,
+        }
+    }
+
+    fn write_memory(&mut self, addr: usize, data: &[u8]) -> bool {
+        for (i, &b) in data.iter().enumerate() {
+            if !patch::write_u8(addr + i, b) {
+                return false;
+            }
+        }
+        // The debugger may have written code.
+        for line in (addr & !63..addr + data.len()).step_by(64) {
+            patch::sync_icache(line);
+        }
+        true
+    }
+}
+
+struct Stub {
+    session: Session,
+    breakpoints: Breakpoints,
+    /// `D` and `I` bits of `SPSR_EL1` to restore after a single step.
+    step_saved: Option<u64>,
+}
+
+static STUB: LazyInit<SpinNoIrq<Stub>> = LazyInit::new();
+/// Whether the other CPUs must stay parked.
+static STOPPED: AtomicBool = AtomicBool::new(false);
+/// Whether Ctrl-C on the console UART breaks into the stub.
+#[cfg(feature = "irq")]
+static CONSOLE_BREAK: AtomicBool = AtomicBool::new(true);
+
+fn mode() -> Mode {
+    let mode = crate::bootargs::get("gdb").and_then(Mode::parse);
+    mode.or_else(|| Mode::parse(GDB_MODE)).unwrap_or(Mode::Off)
+}
+
+fn port() -> Port {
+    let paddr = if GDB_UART_PADDR != 0 {
+        GDB_UART_PADDR
+    } else {
+        UART_PADDR
+    };
+    Port::new(phys_to_virt(pa!(paddr)).as_usize())
+}
+
+fn update_mdscr(set: u64, clear: u64) {
+    // SAFETY: only debug control bits are changed.
+    unsafe {
+        let mut mdscr: u64;
+        core::arch::asm!("mrs {}, mdscr_el1", out(reg) mdscr);
+        mdscr = (mdscr | set) & !clear;
+        core::arch::asm!("msr mdscr_el1, {}", "isb", in(reg) mdscr);
+    }
+}
+
+/// Unlocks the debug registers and installs the vectors on the current CPU.
+fn init_percpu() {
+    // SAFETY: clearing the OS lock only enables debug exceptions.
+    unsafe { core::arch::asm!("msr oslar_el1, xzr", "isb") };
+    vectors::install();
+}
+
+/// Installs the stub on the primary CPU, unless disabled by the mode.
+///
+/// Must be called after `axcpu::init::init_trap`, since exceptions the stub
+/// does not handle are passed to the `axcpu` vectors.
+pub(crate) fn init_early() {
+    if mode() == Mode::Off {
+        return;
+    }
+    STUB.init_once(SpinNoIrq::new(Stub {
+        session: Session::new(port()),
+        breakpoints: Breakpoints([None; MAX_BREAKPOINTS]),
+        step_saved: None,
+    }));
+    init_percpu();
+}
+
+/// Installs the stub on a secondary CPU.
+#[cfg(feature = "smp")]
+pub(crate) fn init_early_secondary() {
+    if STUB.is_inited() {
+        init_percpu();
+    }
+}
+
+/// Sets up break-in and CPU parking interrupts, then waits for the debugger
+/// in `wait` mode.
+pub(crate) fn init_later() {
+    if !STUB.is_inited() {
+        return;
+    }
+    #[cfg(feature = "irq")]
+    {
+        axplat::irq::register(GDB_IPI_IRQ, park);
+        if GDB_UART_PADDR != 0 && GDB_UART_IRQ != 0 {
+            port().enable_rx_irq();
+            axplat::irq::register(GDB_UART_IRQ, uart_irq_handler);
+        }
+    }
+    if GDB_UART_PADDR != 0 {
+        info!("gdbstub: listening on PL011 at {:#x}", GDB_UART_PADDR);
+    } else {
+        info!("gdbstub: listening on the console UART");
+    }
+    if mode() == Mode::Wait {
+        info!("gdbstub: waiting for GDB to attach");
+        breakpoint();
+    }
+}
+
+/// Enables the parking SGI on a secondary CPU, as SGIs are banked per CPU.
+#[cfg(all(feature = "irq", feature = "smp"))]
+pub(crate) fn init_later_secondary() {
+    if STUB.is_inited() {
+        axplat::irq::set_enable(GDB_IPI_IRQ, true);
+    }
+}
+
+/// Returns whether the stub is installed.
+pub fn is_enabled() -> bool {
+    STUB.is_inited()
+}
+
+/// Returns whether Ctrl-C on the console UART is for the stub.
+#[cfg(feature = "irq")]
+pub(crate) fn on_console() -> bool {
+    is_enabled() && GDB_UART_PADDR == 0 && CONSOLE_BREAK.load(Ordering::Relaxed)
+}
+
+/// Turns breaking in with Ctrl-C on the console UART on or off, for users
+/// of the line that send raw bytes. Returns the previous setting.
+#[cfg(all(feature = "irq", feature = "xmodem"))]
+pub(crate) fn set_console_break(enabled: bool) -> bool {
+    CONSOLE_BREAK.swap(enabled, Ordering::Relaxed)
+}
+
+/// Stops in the debugger, as if a breakpoint were hit here.
+///
+/// Does nothing if the stub is not installed.
+pub fn breakpoint() {
+    if is_enabled() {
+        // SAFETY: the stub handles this immediate and skips the instruction.
+        unsafe { core::arch::asm!("brk #{}", const BRK_IMM_COMPILED) };
+    }
+}
+
+/// Stops in the debugger on behalf of a Ctrl-C received on the line.
+#[cfg(feature = "irq")]
+pub(crate) fn break_in() {
+    if is_enabled() {
+        // SAFETY: see `breakpoint`.
+        unsafe { core::arch::asm!("brk #{}", const BRK_IMM_BREAK_IN) };
+    }
+}
+
+/// Keeps a CPU in its interrupt handler while another one is in the stub.
+#[cfg(feature = "irq")]
+fn park() {
+    while STOPPED.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+}
+
+/// Interrupt handler of a dedicated GDB UART.
+#[cfg(feature = "irq")]
+fn uart_irq_handler() {
+    let port = port();
+    port.ack_irq();
+    let mut stop = false;
+    while let Some(c) = port.try_read() {
+        stop |= c == BREAK_CHAR || c == b'$';
+    }
+    if stop {
+        break_in();
+    }
+}
+
+/// Entry from the vectors for `BRK` and software step exceptions.
+///
+/// Returns `false` to let `axcpu` handle the exception instead.
+extern "C" fn handle_exception(tf: &mut TrapFrame, esr: u64) -> bool {
+    let Some(stub) = STUB.get() else {
+        return false;
+    };
+    let mut stub = stub.lock();
+    let imm = esr & 0xffff;
+    let signal = match esr >> 26 {
+        EC_BRK64 if imm == BRK_IMM_COMPILED => SIGTRAP,
+        EC_BRK64 if imm == BRK_IMM_BREAK_IN => SIGINT,
+        EC_BRK64 if imm == BRK_IMM_DEBUGGER => {
+            if !stub.breakpoints.contains(tf.pc as usize) {
+                // Removed while this CPU was on its way here: run the
+                // original instruction again.
+                return true;
+            }
+            SIGTRAP
+        }
+        EC_SOFTSTEP_CUR_EL => {
+            let Some(saved) = stub.step_saved.take() else {
+                return false;
+            };
+            update_mdscr(0, MDSCR_SS | MDSCR_KDE);
+            tf.pstate = (tf.pstate & !(SPSR_SS | SPSR_D | SPSR_I)) | saved;
+            SIGTRAP
+        }
+        _ => return false,
+    };
+
+    STOPPED.store(true, Ordering::Release);
+    #[cfg(all(feature = "irq", feature = "smp"))]
+    crate::gicv3::send_sgi(GDB_IPI_IRQ, None);
+
+    let stop_pc = tf.pc;
+    let Stub {
+        session,
+        breakpoints,
+        ..
+    } = &mut *stub;
+    let resume = session.run(tf, signal, breakpoints);
+
+    // Compiled-in `BRK`s are not removed, so step over them.
+    if esr >> 26 == EC_BRK64 && imm != BRK_IMM_DEBUGGER && tf.pc == stop_pc {
+        tf.pc += 4;
+    }
+    match resume {
+        Resume::Continue => tf.pstate &= !SPSR_SS,
+        Resume::Step => {
+            // Step with IRQs masked, so the step does not land in a handler.
+            stub.step_saved = Some(tf.pstate & (SPSR_D | SPSR_I));
+            tf.pstate = (tf.pstate | SPSR_SS | SPSR_I) & !SPSR_D;
+            update_mdscr(MDSCR_SS | MDSCR_KDE, 0);
+        }
+        Resume::Detach => {
+            stub.breakpoints.remove_all();
+            tf.pstate &= !SPSR_SS;
+        }
+    }
+    STOPPED.store(false, Ordering::Release);
+    true
+}
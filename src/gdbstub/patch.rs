@@ -0,0 +1,111 @@
+//! Writes to memory the kernel maps read-only, such as its own text.
+//!
+//! The leaf descriptor mapping the address in the current page tables is
+//! made writable for the duration of the write. The tables are assumed to use
+//! the 4 KiB granule with 4 levels, as set up by the kernel.
+
+use aarch64_cpu::registers::{Readable, TTBR0_EL1, TTBR1_EL1};
+use axplat::mem::{pa, phys_to_virt};
+
+use super::vectors;
+
+/// Output address bits of table and page descriptors.
+const DESC_ADDR_MASK: u64 = 0x0000_ffff_ffff_f000;
+const DESC_VALID: u64 = 1 << 0;
+/// Set for table descriptors (levels 0-2) and pages (level 3).
+const DESC_TABLE: u64 = 1 << 1;
+/// `AP[2]`: read-only.
+const DESC_AP_RO: u64 = 1 << 7;
+
+/// Returns the leaf descriptor mapping `vaddr`.
+fn leaf_descriptor(vaddr: usize) -> Option<*mut u64> {
+    let ttbr = if vaddr >> 63 != 0 {
+        TTBR1_EL1.get()
+    } else {
+        TTBR0_EL1.get()
+    };
+    let mut table = ttbr & DESC_ADDR_MASK;
+    for level in 0..4 {
+        let index = (vaddr >> (39 - 9 * level)) & 0x1ff;
+        let entry = phys_to_virt(pa!(table as usize)).as_mut_ptr() as *mut u64;
+        let entry = entry.wrapping_add(index);
+        // SAFETY: page tables are in the linear mapping.
+        let desc = unsafe { entry.read_volatile() };
+        if desc & DESC_VALID == 0 {
+            return None;
+        }
+        if level == 3 || desc & DESC_TABLE == 0 {
+            return Some(entry);
+        }
+        table = desc & DESC_ADDR_MASK;
+    }
+    None
+}
+
+fn flush_tlb(vaddr: usize) {
+    // SAFETY: only invalidates translations.
+    unsafe {
+        core::arch::asm!(
+            "dsb ishst",
+            "tlbi vaae1is, {}",
+            "dsb ish",
+            "isb",
+            in(reg) vaddr >> 12,
+        )
+    }
+}
+
+/// Makes instructions written at `addr` visible to instruction fetches.
+pub(super) fn sync_icache(addr: usize) {
+    // SAFETY: cache maintenance by address has no other effect.
+    unsafe {
+        core::arch::asm!(
+            "dc cvau, {0}",
+            "dsb ish",
+            "ic ivau, {0}",
+            "dsb ish",
+            "isb",
+            in(reg) addr,
+        )
+    }
+}
+
+/// Runs `write`, and if it fails, runs it again with the page of `addr`
+/// temporarily writable.
+fn write_with(addr: usize, write: impl Fn() -> bool) -> bool {
+    if write() {
+        return true;
+    }
+    let Some(entry) = leaf_descriptor(addr) else {
+        return false;
+    };
+    // SAFETY: the descriptor is restored right after the write, and the other
+    // CPUs are stopped.
+    unsafe {
+        let desc = entry.read_volatile();
+        if desc & DESC_AP_RO == 0 {
+            return false;
+        }
+        entry.write_volatile(desc & !DESC_AP_RO);
+        flush_tlb(addr);
+        let ok = write();
+        entry.write_volatile(desc);
+        flush_tlb(addr);
+        ok
+    }
+}
+
+/// Writes a byte, even to a read-only page.
+pub(super) fn write_u8(addr: usize, val: u8) -> bool {
+    write_with(addr, || vectors::write_u8(addr, val))
+}
+
+/// Writes an aligned instruction, even to a read-only page, and synchronizes
+/// the instruction cache.
+pub(super) fn write_insn(addr: usize, insn: u32) -> bool {
+    let ok = write_with(addr, || vectors::write_u32(addr, insn));
+    if ok {
+        sync_icache(addr);
+    }
+    ok
+}
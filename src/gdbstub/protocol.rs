@@ -0,0 +1,451 @@
+//! GDB Remote Serial Protocol: packet framing and command handling.
+//!
+//! Supported packets: `?`, `g`/`G`, `p`/`P`, `m`/`M`, `c`, `s`, `Z0`/`z0`,
+//! `D`, `k`, `qSupported`, `qAttached` and `qXfer:features:read` (for the
+//! target description). Anything else gets the empty "unsupported" reply.
+
+use super::vectors::TrapFrame;
+
+/// Maximum packet payload, advertised in `qSupported`.
+const PACKET_SIZE: usize = 0x1000;
+
+/// Times a packet is sent before giving up on the acknowledgement.
+const MAX_RETRIES: usize = 8;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Register numbers of the `org.gnu.gdb.aarch64.core` feature.
+const REG_SP: usize = 31;
+const REG_PC: usize = 32;
+const REG_CPSR: usize = 33;
+
+/// Target description with the core registers only.
+const TARGET_XML: &str = concat!(
+    r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd">"#,
+    r#"<target><architecture>aarch64</architecture>"#,
+    r#"<feature name="org.gnu.gdb.aarch64.core">"#,
+    r#"<reg name="x0" bitsize="64"/><reg name="x1" bitsize="64"/>"#,
+    r#"<reg name="x2" bitsize="64"/><reg name="x3" bitsize="64"/>"#,
+    r#"<reg name="x4" bitsize="64"/><reg name="x5" bitsize="64"/>"#,
+    r#"<reg name="x6" bitsize="64"/><reg name="x7" bitsize="64"/>"#,
+    r#"<reg name="x8" bitsize="64"/><reg name="x9" bitsize="64"/>"#,
+    r#"<reg name="x10" bitsize="64"/><reg name="x11" bitsize="64"/>"#,
+    r#"<reg name="x12" bitsize="64"/><reg name="x13" bitsize="64"/>"#,
+    r#"<reg name="x14" bitsize="64"/><reg name="x15" bitsize="64"/>"#,
+    r#"<reg name="x16" bitsize="64"/><reg name="x17" bitsize="64"/>"#,
+    r#"<reg name="x18" bitsize="64"/><reg name="x19" bitsize="64"/>"#,
+    r#"<reg name="x20" bitsize="64"/><reg name="x21" bitsize="64"/>"#,
+    r#"<reg name="x22" bitsize="64"/><reg name="x23" bitsize="64"/>"#,
+    r#"<reg name="x24" bitsize="64"/><reg name="x25" bitsize="64"/>"#,
+    r#"<reg name="x26" bitsize="64"/><reg name="x27" bitsize="64"/>"#,
+    r#"<reg name="x28" bitsize="64"/><reg name="x29" bitsize="64"/>"#,
+    r#"<reg name="x30" bitsize="64"/>"#,
+    r#"<reg name="sp" bitsize="64" type="data_ptr"/>"#,
+    r#"<reg name="pc" bitsize="64" type="code_ptr"/>"#,
+    r#"<reg name="cpsr" bitsize="32"/>"#,
+    r#"</feature></target>"#,
+);
+
+/// Flag Register, receive FIFO empty and transmit FIFO full bits.
+const UARTFR: usize = 0x018;
+const FR_RXFE: u32 = 1 << 4;
+const FR_TXFF: u32 = 1 << 5;
+/// Interrupt Mask Set/Clear and Interrupt Clear Registers, receive and
+/// receive timeout bits.
+#[cfg(feature = "irq")]
+const UARTIMSC: usize = 0x038;
+#[cfg(feature = "irq")]
+const UARTICR: usize = 0x044;
+#[cfg(feature = "irq")]
+const INT_RX: u32 = 1 << 4;
+#[cfg(feature = "irq")]
+const INT_RT: u32 = 1 << 6;
+
+/// Polled byte I/O on a PL011.
+#[derive(Clone, Copy)]
+pub(super) struct Port {
+    base: usize,
+}
+
+impl Port {
+    pub const fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        // SAFETY: the UART frame is mapped.
+        unsafe { core::ptr::read_volatile((self.base + offset) as *const u32) }
+    }
+
+    /// Returns the next received byte, if any. Line errors are ignored, the
+    /// packet checksum catches them.
+    pub fn try_read(&self) -> Option<u8> {
+        (self.read_reg(UARTFR) & FR_RXFE == 0).then(|| self.read_reg(0) as u8)
+    }
+
+    fn read(&self) -> u8 {
+        loop {
+            if let Some(c) = self.try_read() {
+                return c;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn write_reg(&self, offset: usize, val: u32) {
+        // SAFETY: see `read_reg`.
+        unsafe { core::ptr::write_volatile((self.base + offset) as *mut u32, val) }
+    }
+
+    fn write(&self, c: u8) {
+        while self.read_reg(UARTFR) & FR_TXFF != 0 {
+            core::hint::spin_loop();
+        }
+        self.write_reg(0, c as u32);
+    }
+
+    /// Raises the UART interrupt on received bytes.
+    #[cfg(feature = "irq")]
+    pub fn enable_rx_irq(&self) {
+        self.write_reg(UARTICR, INT_RX | INT_RT);
+        let imsc = self.read_reg(UARTIMSC);
+        self.write_reg(UARTIMSC, imsc | INT_RX | INT_RT);
+    }
+
+    /// Clears the receive interrupts.
+    #[cfg(feature = "irq")]
+    pub fn ack_irq(&self) {
+        self.write_reg(UARTICR, INT_RX | INT_RT);
+    }
+}
+
+/// What to do once the debugger lets the target go.
+pub(super) enum Resume {
+    Continue,
+    Step,
+    /// The debugger is gone: continue with all breakpoints removed.
+    Detach,
+}
+
+/// Hooks into the rest of the stub used by command handling.
+pub(super) trait Target {
+    fn insert_breakpoint(&mut self, addr: usize) -> bool;
+    fn remove_breakpoint(&mut self, addr: usize) -> bool;
+    fn write_memory(&mut self, addr: usize, data: &[u8]) -> bool;
+}
+
+/// Buffers of a debugging session.
+pub(super) struct Session {
+    port: Port,
+    rx: [u8; PACKET_SIZE],
+    tx: [u8; PACKET_SIZE],
+    tx_len: usize,
+    /// Whether a debugger has talked to us, so stops are reported right away.
+    connected: bool,
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn parse_hex(s: &[u8]) -> Option<usize> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter()
+        .try_fold(0usize, |acc, &c| Some(acc << 4 | hex_digit(c)? as usize))
+}
+
+/// Decodes hex pairs into `out`, returning the number of bytes.
+fn decode_hex(s: &[u8], out: &mut [u8]) -> Option<usize> {
+    if !s.len().is_multiple_of(2) || s.len() / 2 > out.len() {
+        return None;
+    }
+    for (dst, pair) in out.iter_mut().zip(s.chunks(2)) {
+        *dst = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+    }
+    Some(s.len() / 2)
+}
+
+/// Splits `addr,len` into its two numbers.
+fn parse_addr_len(s: &[u8]) -> Option<(usize, usize)> {
+    let comma = s.iter().position(|&c| c == b',')?;
+    Some((parse_hex(&s[..comma])?, parse_hex(&s[comma + 1..])?))
+}
+
+impl Session {
+    pub const fn new(port: Port) -> Self {
+        Self {
+            port,
+            rx: [0; PACKET_SIZE],
+            tx: [0; PACKET_SIZE],
+            tx_len: 0,
+            connected: false,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        let n = bytes.len().min(PACKET_SIZE - self.tx_len);
+        self.tx[self.tx_len..self.tx_len + n].copy_from_slice(&bytes[..n]);
+        self.tx_len += n;
+    }
+
+    fn push_hex(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.push(&[HEX_DIGITS[(b >> 4) as usize], HEX_DIGITS[(b & 0xf) as usize]]);
+        }
+    }
+
+    fn reply(&mut self, bytes: &[u8]) {
+        self.tx_len = 0;
+        self.push(bytes);
+    }
+
+    /// Receives a packet into `rx`, acknowledging it, and returns its length.
+    fn recv_packet(&mut self) -> usize {
+        loop {
+            // Anything before the start of a packet (acks, Ctrl-C) is noise.
+            while self.port.read() != b'$' {}
+            let mut len = 0;
+            let mut sum = 0u8;
+            let mut c = self.port.read();
+            while c != b'#' && c != b'$' && len < PACKET_SIZE {
+                self.rx[len] = c;
+                sum = sum.wrapping_add(c);
+                len += 1;
+                c = self.port.read();
+            }
+            if c != b'#' {
+                self.port.write(b'-');
+                continue;
+            }
+            let hi = hex_digit(self.port.read());
+            let lo = hex_digit(self.port.read());
+            if let (Some(hi), Some(lo)) = (hi, lo)
+                && hi << 4 | lo == sum
+            {
+                self.port.write(b'+');
+                return len;
+            }
+            self.port.write(b'-');
+        }
+    }
+
+    /// Sends the reply in `tx` until the debugger acknowledges it.
+    fn send_packet(&mut self) {
+        let sum = self.tx[..self.tx_len]
+            .iter()
+            .fold(0u8, |sum, &c| sum.wrapping_add(c));
+        for _ in 0..MAX_RETRIES {
+            self.port.write(b'$');
+            for &c in &self.tx[..self.tx_len] {
+                self.port.write(c);
+            }
+            self.port.write(b'#');
+            self.port.write(HEX_DIGITS[(sum >> 4) as usize]);
+            self.port.write(HEX_DIGITS[(sum & 0xf) as usize]);
+            if self.port.read() == b'+' {
+                return;
+            }
+        }
+    }
+
+    fn push_stop_reply(&mut self, signal: u8) {
+        self.reply(b"S");
+        self.push_hex(&[signal]);
+    }
+
+    fn push_reg(&mut self, tf: &TrapFrame, reg: usize) -> bool {
+        match reg {
+            0..=30 => self.push_hex(&tf.x[reg].to_le_bytes()),
+            REG_SP => self.push_hex(&tf.sp.to_le_bytes()),
+            REG_PC => self.push_hex(&tf.pc.to_le_bytes()),
+            REG_CPSR => self.push_hex(&(tf.pstate as u32).to_le_bytes()),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Sets a register from its little-endian bytes. The stack pointer of the
+    /// stopped context cannot be changed.
+    fn set_reg(tf: &mut TrapFrame, reg: usize, bytes: &[u8]) -> bool {
+        let mut val = [0u8; 8];
+        let n = bytes.len().min(8);
+        val[..n].copy_from_slice(&bytes[..n]);
+        let val = u64::from_le_bytes(val);
+        match reg {
+            0..=30 => tf.x[reg] = val,
+            REG_PC => tf.pc = val,
+            REG_CPSR => tf.pstate = (tf.pstate & !0xffff_ffff) | (val & 0xffff_ffff),
+            _ => return false,
+        }
+        true
+    }
+
+    fn read_registers(&mut self, tf: &TrapFrame) {
+        self.tx_len = 0;
+        for reg in 0..=REG_CPSR {
+            self.push_reg(tf, reg);
+        }
+    }
+
+    fn write_registers(&mut self, tf: &mut TrapFrame, len: usize) {
+        let mut regs = [0u8; 33 * 8 + 4];
+        match decode_hex(&self.rx[1..len], &mut regs) {
+            Some(n) if n == regs.len() => {
+                for reg in (0..=30).chain([REG_PC]) {
+                    Self::set_reg(tf, reg, &regs[reg * 8..reg * 8 + 8]);
+                }
+                Self::set_reg(tf, REG_CPSR, &regs[33 * 8..]);
+                self.reply(b"OK");
+            }
+            _ => self.reply(b"E01"),
+        }
+    }
+
+    fn read_memory(&mut self, len: usize) {
+        let Some((addr, count)) = parse_addr_len(&self.rx[1..len]) else {
+            return self.reply(b"E01");
+        };
+        self.tx_len = 0;
+        for i in 0..count.min(PACKET_SIZE / 2) {
+            match super::vectors::read_u8(addr.wrapping_add(i)) {
+                Some(b) => self.push_hex(&[b]),
+                None => break,
+            }
+        }
+        if self.tx_len == 0 && count != 0 {
+            self.reply(b"E14");
+        }
+    }
+
+    fn write_memory(&mut self, target: &mut impl Target, len: usize) {
+        let packet = &self.rx[1..len];
+        let Some(colon) = packet.iter().position(|&c| c == b':') else {
+            return self.reply(b"E01");
+        };
+        let mut data = [0u8; PACKET_SIZE / 2];
+        let ok = match (
+            parse_addr_len(&packet[..colon]),
+            decode_hex(&packet[colon + 1..], &mut data),
+        ) {
+            (Some((addr, count)), Some(n)) if n == count => target.write_memory(addr, &data[..n]),
+            _ => return self.reply(b"E01"),
+        };
+        self.reply(if ok { b"OK" } else { b"E14" });
+    }
+
+    fn breakpoint(&mut self, target: &mut impl Target, len: usize, insert: bool) {
+        let packet = &self.rx[1..len];
+        // Only software breakpoints (`Z0,addr,kind`) are supported.
+        if !packet.starts_with(b"0,") {
+            return self.reply(b"");
+        }
+        let Some((addr, _kind)) = parse_addr_len(&packet[2..]) else {
+            return self.reply(b"E01");
+        };
+        let ok = if insert {
+            target.insert_breakpoint(addr)
+        } else {
+            target.remove_breakpoint(addr)
+        };
+        self.reply(if ok { b"OK" } else { b"E0e" });
+    }
+
+    fn query(&mut self, len: usize) {
+        let packet = &self.rx[..len];
+        if packet.starts_with(b"qSupported") {
+            self.reply(b"PacketSize=");
+            self.push_hex(&(PACKET_SIZE as u16).to_be_bytes());
+            self.push(b";qXfer:features:read+");
+        } else if packet == b"qAttached" {
+            self.reply(b"1");
+        } else if let Some(args) = packet.strip_prefix(b"qXfer:features:read:target.xml:") {
+            let Some((offset, count)) = parse_addr_len(args) else {
+                return self.reply(b"E01");
+            };
+            let xml = TARGET_XML.as_bytes();
+            let start = offset.min(xml.len());
+            let end = start + count.min(PACKET_SIZE - 1).min(xml.len() - start);
+            self.reply(if end == xml.len() { b"l" } else { b"m" });
+            self.push(&xml[start..end]);
+        } else {
+            self.reply(b"");
+        }
+    }
+
+    /// Talks to the debugger until it resumes the target.
+    ///
+    /// `signal` is the reason of the stop, reported to the debugger.
+    pub fn run(&mut self, tf: &mut TrapFrame, signal: u8, target: &mut impl Target) -> Resume {
+        if self.connected {
+            self.push_stop_reply(signal);
+            self.send_packet();
+        }
+        loop {
+            let len = self.recv_packet();
+            self.connected = true;
+            let Some(&cmd) = self.rx.first().filter(|_| len > 0) else {
+                self.reply(b"");
+                self.send_packet();
+                continue;
+            };
+            match cmd {
+                b'?' => self.push_stop_reply(signal),
+                b'g' => self.read_registers(tf),
+                b'G' => self.write_registers(tf, len),
+                b'p' => {
+                    let reg = parse_hex(&self.rx[1..len]).unwrap_or(usize::MAX);
+                    self.tx_len = 0;
+                    if !self.push_reg(tf, reg) {
+                        self.reply(b"E01");
+                    }
+                }
+                b'P' => {
+                    let packet = &self.rx[1..len];
+                    let mut val = [0u8; 8];
+                    let ok = packet.iter().position(|&c| c == b'=').is_some_and(|eq| {
+                        let reg = parse_hex(&packet[..eq]);
+                        let n = decode_hex(&packet[eq + 1..], &mut val);
+                        matches!((reg, n), (Some(reg), Some(n)) if Self::set_reg(tf, reg, &val[..n]))
+                    });
+                    self.reply(if ok { b"OK" } else { b"E01" });
+                }
+                b'm' => self.read_memory(len),
+                b'M' => self.write_memory(target, len),
+                b'c' | b's' => {
+                    if let Some(addr) = parse_hex(&self.rx[1..len]) {
+                        tf.pc = addr as u64;
+                    }
+                    return if cmd == b'c' {
+                        Resume::Continue
+                    } else {
+                        Resume::Step
+                    };
+                }
+                b'Z' => self.breakpoint(target, len, true),
+                b'z' => self.breakpoint(target, len, false),
+                b'D' => {
+                    self.reply(b"OK");
+                    self.send_packet();
+                    self.connected = false;
+                    return Resume::Detach;
+                }
+                b'k' => {
+                    // There is nothing to kill, the debugger just goes away.
+                    self.connected = false;
+                    return Resume::Detach;
+                }
+                b'q' => self.query(len),
+                b'H' => self.reply(b"OK"),
+                _ => self.reply(b""),
+            }
+            self.send_packet();
+        }
+    }
+}
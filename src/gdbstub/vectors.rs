@@ -0,0 +1,269 @@
+//! Exception vectors in front of the `axcpu` ones.
+//!
+//! Only synchronous exceptions taken from EL1 are looked at: `BRK`, software
+//! step and data aborts of [`probe_access`]. Everything else, and every
+//! exception the stub declines, continues at the same entry of the `axcpu`
+//! vector table with all registers intact.
+
+use aarch64_cpu::registers::{VBAR_EL1, Writeable};
+
+/// Registers of the interrupted context, saved by [`sync_entry`].
+#[repr(C)]
+pub(super) struct TrapFrame {
+    pub x: [u64; 31],
+    /// Stack pointer of the interrupted context (read-only).
+    pub sp: u64,
+    /// `ELR_EL1`.
+    pub pc: u64,
+    /// `SPSR_EL1`.
+    pub pstate: u64,
+}
+
+/// Size of [`TrapFrame`].
+const FRAME_SIZE: usize = 34 * 8;
+
+/// Offset of the synchronous, current EL with `SP_ELx` entry.
+const SYNC_CURRENT_EL_SPX: usize = 0x200;
+
+unsafe extern "C" {
+    /// Vector table installed by `axcpu::init::init_trap`.
+    fn exception_vector_base();
+}
+
+/// Saves the caller-saved FP/SIMD registers, which the Rust handler may use.
+#[cfg(feature = "fp-simd")]
+macro_rules! save_fp_regs {
+    () => {
+        "
+        sub     sp, sp, #(34 * 16)
+        stp     q0, q1, [sp, #(0 * 32)]
+        stp     q2, q3, [sp, #(1 * 32)]
+        stp     q4, q5, [sp, #(2 * 32)]
+        stp     q6, q7, [sp, #(3 * 32)]
+        stp     q8, q9, [sp, #(4 * 32)]
+        stp     q10, q11, [sp, #(5 * 32)]
+        stp     q12, q13, [sp, #(6 * 32)]
+        stp     q14, q15, [sp, #(7 * 32)]
+        stp     q16, q17, [sp, #(8 * 32)]
+        stp     q18, q19, [sp, #(9 * 32)]
+        stp     q20, q21, [sp, #(10 * 32)]
+        stp     q22, q23, [sp, #(11 * 32)]
+        stp     q24, q25, [sp, #(12 * 32)]
+        stp     q26, q27, [sp, #(13 * 32)]
+        stp     q28, q29, [sp, #(14 * 32)]
+        stp     q30, q31, [sp, #(15 * 32)]
+        mrs     x9, fpcr
+        mrs     x10, fpsr
+        str     x9, [sp, #(16 * 32)]
+        str     x10, [sp, #(16 * 32 + 8)]
+        add     x0, sp, #(34 * 16)
+        "
+    };
+}
+
+#[cfg(feature = "fp-simd")]
+macro_rules! restore_fp_regs {
+    () => {
+        "
+        ldr     x9, [sp, #(16 * 32)]
+        ldr     x10, [sp, #(16 * 32 + 8)]
+        msr     fpcr, x9
+        msr     fpsr, x10
+        ldp     q0, q1, [sp, #(0 * 32)]
+        ldp     q2, q3, [sp, #(1 * 32)]
+        ldp     q4, q5, [sp, #(2 * 32)]
+        ldp     q6, q7, [sp, #(3 * 32)]
+        ldp     q8, q9, [sp, #(4 * 32)]
+        ldp     q10, q11, [sp, #(5 * 32)]
+        ldp     q12, q13, [sp, #(6 * 32)]
+        ldp     q14, q15, [sp, #(7 * 32)]
+        ldp     q16, q17, [sp, #(8 * 32)]
+        ldp     q18, q19, [sp, #(9 * 32)]
+        ldp     q20, q21, [sp, #(10 * 32)]
+        ldp     q22, q23, [sp, #(11 * 32)]
+        ldp     q24, q25, [sp, #(12 * 32)]
+        ldp     q26, q27, [sp, #(13 * 32)]
+        ldp     q28, q29, [sp, #(14 * 32)]
+        ldp     q30, q31, [sp, #(15 * 32)]
+        add     sp, sp, #(34 * 16)
+        "
+    };
+}
+
+#[cfg(not(feature = "fp-simd"))]
+macro_rules! save_fp_regs {
+    () => {
+        "mov     x0, sp"
+    };
+}
+
+#[cfg(not(feature = "fp-simd"))]
+macro_rules! restore_fp_regs {
+    () => {
+        ""
+    };
+}
+
+/// The vector table. Must stay the first thing in its section, which the
+/// `.balign` raises to 2 KiB alignment.
+#[unsafe(naked)]
+unsafe extern "C" fn vectors() {
+    core::arch::naked_asm!("
+        .balign 0x800
+        .irp    idx, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15
+        .balign 0x80
+        .if     \\idx * 0x80 == {sync_spx}
+        stp     x0, x1, [sp, #-16]!
+        b       {sync_entry}
+        .else
+        b       {base} + \\idx * 0x80
+        .endif
+        .endr",
+        sync_spx = const SYNC_CURRENT_EL_SPX,
+        sync_entry = sym sync_entry,
+        base = sym exception_vector_base,
+    )
+}
+
+/// Sorts out synchronous exceptions from EL1, with `x0`/`x1` pushed.
+#[unsafe(naked)]
+unsafe extern "C" fn sync_entry() {
+    core::arch::naked_asm!("
+        mrs     x0, esr_el1
+        lsr     x0, x0, #26
+        cmp     x0, #0x3c               // BRK
+        b.eq    2f
+        cmp     x0, #0x33               // software step, same EL
+        b.eq    2f
+        cmp     x0, #0x25               // data abort, same EL
+        b.ne    1f
+
+        // A fault in `probe_access` returns -1 from it.
+        mrs     x0, elr_el1
+        adrp    x1, {probe}
+        add     x1, x1, :lo12:{probe}
+        sub     x0, x0, x1
+        cmp     x0, #{probe_size}
+        b.hs    1f
+        add     x1, x1, #4
+        msr     elr_el1, x1
+        ldp     x0, x1, [sp], #16
+        eret
+
+    1:  ldp     x0, x1, [sp], #16
+        b       {base} + {sync_spx}
+
+    2:  ldp     x0, x1, [sp], #16
+        sub     sp, sp, #{frame_size}
+        stp     x0, x1, [sp, #(0 * 16)]
+        stp     x2, x3, [sp, #(1 * 16)]
+        stp     x4, x5, [sp, #(2 * 16)]
+        stp     x6, x7, [sp, #(3 * 16)]
+        stp     x8, x9, [sp, #(4 * 16)]
+        stp     x10, x11, [sp, #(5 * 16)]
+        stp     x12, x13, [sp, #(6 * 16)]
+        stp     x14, x15, [sp, #(7 * 16)]
+        stp     x16, x17, [sp, #(8 * 16)]
+        stp     x18, x19, [sp, #(9 * 16)]
+        stp     x20, x21, [sp, #(10 * 16)]
+        stp     x22, x23, [sp, #(11 * 16)]
+        stp     x24, x25, [sp, #(12 * 16)]
+        stp     x26, x27, [sp, #(13 * 16)]
+        stp     x28, x29, [sp, #(14 * 16)]
+        add     x0, sp, #{frame_size}
+        stp     x30, x0, [sp, #(15 * 16)]
+        mrs     x0, elr_el1
+        mrs     x1, spsr_el1
+        stp     x0, x1, [sp, #(16 * 16)]
+        ",
+        save_fp_regs!(),
+        "
+        mrs     x1, esr_el1
+        bl      {handler}               // handler(frame, esr) -> handled
+        ",
+        restore_fp_regs!(),
+        "
+        ldp     x1, x2, [sp, #(16 * 16)]
+        msr     elr_el1, x1
+        msr     spsr_el1, x2
+        cmp     x0, #0                  // flags survive the loads below
+        ldr     x30, [sp, #(15 * 16)]
+        ldp     x28, x29, [sp, #(14 * 16)]
+        ldp     x26, x27, [sp, #(13 * 16)]
+        ldp     x24, x25, [sp, #(12 * 16)]
+        ldp     x22, x23, [sp, #(11 * 16)]
+        ldp     x20, x21, [sp, #(10 * 16)]
+        ldp     x18, x19, [sp, #(9 * 16)]
+        ldp     x16, x17, [sp, #(8 * 16)]
+        ldp     x14, x15, [sp, #(7 * 16)]
+        ldp     x12, x13, [sp, #(6 * 16)]
+        ldp     x10, x11, [sp, #(5 * 16)]
+        ldp     x8, x9, [sp, #(4 * 16)]
+        ldp     x6, x7, [sp, #(3 * 16)]
+        ldp     x4, x5, [sp, #(2 * 16)]
+        ldp     x2, x3, [sp, #(1 * 16)]
+        ldp     x0, x1, [sp, #(0 * 16)]
+        add     sp, sp, #{frame_size}
+        b.ne    3f
+        b       {base} + {sync_spx}
+    3:  eret",
+        probe = sym probe_access,
+        probe_size = const PROBE_SIZE,
+        frame_size = const FRAME_SIZE,
+        sync_spx = const SYNC_CURRENT_EL_SPX,
+        handler = sym super::handle_exception,
+        base = sym exception_vector_base,
+    )
+}
+
+/// Bytes of [`probe_access`] in which a data abort is recovered.
+const PROBE_SIZE: usize = 0x40;
+
+/// Performs one memory access that may fault.
+///
+/// `op` 0 reads the byte at `addr`, 1 writes the byte `val`, 2 writes the
+/// word `val`. Returns the byte read or 0, or -1 if the access faulted.
+#[unsafe(naked)]
+unsafe extern "C" fn probe_access(op: usize, addr: usize, val: u32) -> isize {
+    core::arch::naked_asm!("
+        b       1f
+        mov     x0, #-1                 // a fault resumes here
+        ret
+    1:  cbz     x0, 2f
+        cmp     x0, #1
+        b.eq    3f
+        str     w2, [x1]
+        mov     x0, #0
+        ret
+    2:  ldrb    w0, [x1]
+        ret
+    3:  strb    w2, [x1]
+        mov     x0, #0
+        ret",
+    )
+}
+
+/// Reads a byte, or returns [`None`] if the address is not readable.
+pub(super) fn read_u8(addr: usize) -> Option<u8> {
+    // SAFETY: faults are caught by `sync_entry`.
+    let val = unsafe { probe_access(0, addr, 0) };
+    (val >= 0).then_some(val as u8)
+}
+
+/// Writes a byte, returning `false` if the address is not writable.
+pub(super) fn write_u8(addr: usize, val: u8) -> bool {
+    // SAFETY: faults are caught by `sync_entry`.
+    unsafe { probe_access(1, addr, val as u32) == 0 }
+}
+
+/// Writes an aligned word, returning `false` if the address is not writable.
+pub(super) fn write_u32(addr: usize, val: u32) -> bool {
+    // SAFETY: faults are caught by `sync_entry`.
+    unsafe { probe_access(2, addr, val) == 0 }
+}
+
+/// Points `VBAR_EL1` of the current CPU to the stub vectors.
+pub(super) fn install() {
+    VBAR_EL1.set(vectors as *const () as usize as u64);
+    aarch64_cpu::asm::barrier::isb(aarch64_cpu::asm::barrier::SY);
+}
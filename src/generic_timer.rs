@@ -1,10 +1,25 @@
 //! ARM Generic Timer.
 
-use aarch64_cpu::registers::{CNTFRQ_EL0, CNTP_TVAL_EL0, CNTPCT_EL0};
+use aarch64_cpu::registers::{CNTFRQ_EL0, CNTP_TVAL_EL0};
 use aarch64_cpu::registers::{Readable, Writeable};
 use axplat::time::TimeIf;
 use int_ratio::Ratio;
 
+use crate::config::plat::{TIMER_EL0_ACCESS, TIMER_EVTSTRM_PERIOD_US};
+use crate::timer_errata::read_counter;
+
+/// `CNTKCTL_EL1` bits.
+const CNTKCTL_EL0PCTEN: u64 = 1 << 0;
+const CNTKCTL_EL0VCTEN: u64 = 1 << 1;
+const CNTKCTL_EVNTEN: u64 = 1 << 2;
+const CNTKCTL_EVNTI_SHIFT: u64 = 4;
+const CNTKCTL_EVNTI_MASK: u64 = 0xf << CNTKCTL_EVNTI_SHIFT;
+const CNTKCTL_EL0VTEN: u64 = 1 << 8;
+const CNTKCTL_EL0PTEN: u64 = 1 << 9;
+
+/// Fixed-point shift of [`VdsoTimeData::mult`].
+const VDSO_SHIFT: u32 = 32;
+
 static mut CNTPCT_TO_NANOS_RATIO: Ratio = Ratio::zero();
 static mut NANOS_TO_CNTPCT_RATIO: Ratio = Ratio::zero();
 struct TimeIfImpl;
@@ -13,7 +28,7 @@ struct TimeIfImpl;
 #[impl_plat_interface]
 impl TimeIf for TimeIfImpl {
     fn current_ticks() -> u64 {
-        CNTPCT_EL0.get()
+        read_counter()
     }
 
     /// Converts hardware ticks to nanoseconds.
@@ -30,7 +45,7 @@ impl TimeIf for TimeIfImpl {
     ///
     /// A timer interrupt will be triggered at the specified monotonic time deadline (in nanoseconds).
     fn set_oneshot_timer(deadline_ns: u64) {
-        let cnptct = CNTPCT_EL0.get();
+        let cnptct = read_counter();
         let cnptct_deadline = Self::nanos_to_ticks(deadline_ns);
         if cnptct < cnptct_deadline {
             let interval = cnptct_deadline - cnptct;
@@ -44,17 +59,91 @@ impl TimeIf for TimeIfImpl {
     /// Returns the offset (in nanoseconds) between the epoch of the timer and
     /// the Unix epoch (1970-01-01 00:00:00 UTC).
     fn epochoffset_nanos() -> u64 {
-        0
+        #[cfg(feature = "rtc")]
+        let offset = crate::rtc::epochoffset_nanos();
+        #[cfg(not(feature = "rtc"))]
+        let offset = 0;
+        offset
     }
 }
 
-/// Early stage initialization: stores the timer frequency.
+/// Early stage initialization: stores the timer frequency and selects the
+/// counter read workaround.
 pub fn init_early() {
+    crate::timer_errata::init();
     let freq = CNTFRQ_EL0.get();
     unsafe {
         CNTPCT_TO_NANOS_RATIO = Ratio::new(axplat::time::NANOS_PER_SEC as u32, freq as u32);
         NANOS_TO_CNTPCT_RATIO = CNTPCT_TO_NANOS_RATIO.inverse();
     }
+    init_percpu();
+}
+
+/// Returns the `EVNTI` value giving an event stream period closest to
+/// `period_us`. Events fire each time the selected counter bit flips, i.e.
+/// every `2^(EVNTI + 1)` ticks.
+fn evtstrm_divider(period_us: u64) -> u64 {
+    let ticks = (CNTFRQ_EL0.get() * period_us / 1_000_000).max(2);
+    let mut lsb = 63 - ticks.leading_zeros() as u64;
+    // Round to the nearest power of two.
+    if lsb > 0 && (ticks >> (lsb - 1)) & 1 != 0 {
+        lsb += 1;
+    }
+    (lsb - 1).min(15)
+}
+
+/// Configures EL0 counter access and the event stream on the current CPU.
+///
+/// `CNTKCTL_EL1` is banked per CPU, so it should be called on all CPUs.
+pub fn init_percpu() {
+    let mut cntkctl: u64;
+    unsafe { core::arch::asm!("mrs {}, cntkctl_el1", out(reg) cntkctl) };
+    // EL0 never gets access to the timer registers themselves.
+    cntkctl &= !(CNTKCTL_EL0VTEN | CNTKCTL_EL0PTEN);
+    if TIMER_EL0_ACCESS {
+        cntkctl |= CNTKCTL_EL0PCTEN | CNTKCTL_EL0VCTEN;
+    } else {
+        cntkctl &= !(CNTKCTL_EL0PCTEN | CNTKCTL_EL0VCTEN);
+    }
+    cntkctl &= !(CNTKCTL_EVNTEN | CNTKCTL_EVNTI_MASK);
+    if TIMER_EVTSTRM_PERIOD_US != 0 {
+        let divider = evtstrm_divider(TIMER_EVTSTRM_PERIOD_US as u64);
+        cntkctl |= CNTKCTL_EVNTEN | (divider << CNTKCTL_EVNTI_SHIFT);
+    }
+    unsafe {
+        core::arch::asm!("msr cntkctl_el1, {}", "isb", in(reg) cntkctl);
+    }
+}
+
+/// Time keeping parameters to be published in a vDSO data page.
+///
+/// User space converts a counter value `cnt` read from `CNTVCT_EL0` to
+/// nanoseconds since the Unix epoch as
+/// `((cnt as u128 * mult as u128) >> shift) as u64 + epoch_offset_ns`.
+#[derive(Debug, Clone, Copy)]
+pub struct VdsoTimeData {
+    /// Counter frequency in Hz.
+    pub freq: u64,
+    /// Multiplier converting ticks to nanoseconds.
+    pub mult: u64,
+    /// Right shift applied after multiplying by `mult`.
+    pub shift: u32,
+    /// Offset between the monotonic clock and the Unix epoch, in nanoseconds.
+    pub epoch_offset_ns: u64,
+    /// Whether EL0 is allowed to read the counter at all.
+    pub el0_access: bool,
+}
+
+/// Returns the data a vDSO needs to read the time from user space.
+pub fn vdso_data() -> VdsoTimeData {
+    let freq = CNTFRQ_EL0.get();
+    VdsoTimeData {
+        freq,
+        mult: ((axplat::time::NANOS_PER_SEC as u128) << VDSO_SHIFT).div_ceil(freq as u128) as u64,
+        shift: VDSO_SHIFT,
+        epoch_offset_ns: TimeIfImpl::epochoffset_nanos(),
+        el0_access: TIMER_EL0_ACCESS,
+    }
 }
 
 /// Enable timer interrupts.
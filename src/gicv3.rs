@@ -3,16 +3,24 @@ use alloc::boxed::Box;
 use arm_gic_driver::DriverGeneric;
 use arm_gic_driver::Interface;
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use kspin::SpinNoIrq;
 
 use axplat::irq::{HandlerTable, IrqHandler, IrqIf};
+
+use crate::config::plat::MAX_CPU_NUM;
 use log::{debug, error, info, trace, warn};
 
 /// The maximum number of IRQs.
-const MAX_IRQ_COUNT: usize = 1024;
+pub(crate) const MAX_IRQ_COUNT: usize = 1024;
 
 static IRQ_HANDLER_TABLE: HandlerTable<MAX_IRQ_COUNT> = HandlerTable::new();
 
+/// Number of times each IRQ was taken, on all CPUs.
+static IRQ_COUNTS: [AtomicUsize; MAX_IRQ_COUNT] = [const { AtomicUsize::new(0) }; MAX_IRQ_COUNT];
+/// Number of IRQs taken by each CPU.
+static CPU_IRQ_COUNTS: [AtomicUsize; MAX_CPU_NUM] = [const { AtomicUsize::new(0) }; MAX_CPU_NUM];
+
 static GICD: SpinNoIrq<Option<arm_gic_driver::v3::Gic>> = SpinNoIrq::new(None);
 static GICR: SpinNoIrq<Option<Box<dyn arm_gic_driver::local::Interface>>> = SpinNoIrq::new(None);
 
@@ -48,10 +56,22 @@ pub(crate) fn init_current_cpu() {
     debug!("Initialized GICR for current CPU {}", current_cpu());
 }
 
-fn current_cpu() -> usize {
+pub(crate) fn current_cpu() -> usize {
     MPIDR_EL1.get() as usize & 0xffffff
 }
 
+/// Returns how many times `irq_num` was taken since boot, on all CPUs.
+#[cfg(feature = "sysrq")]
+pub(crate) fn irq_count(irq_num: usize) -> usize {
+    IRQ_COUNTS.get(irq_num).map_or(0, |c| c.load(Ordering::Relaxed))
+}
+
+/// Returns how many IRQs `cpu` took since boot.
+#[cfg(feature = "sysrq")]
+pub(crate) fn cpu_irq_count(cpu: usize) -> usize {
+    CPU_IRQ_COUNTS.get(cpu).map_or(0, |c| c.load(Ordering::Relaxed))
+}
+
 pub(crate) fn set_enable(irq_num: usize, enabled: bool) {
     use arm_gic_driver::local::cap::ConfigLocalIrq;
 
@@ -76,6 +96,35 @@ pub(crate) fn set_enable(irq_num: usize, enabled: bool) {
     }
 }
 
+/// Generates SGI `sgi_num` for the CPU with the given ID (`MPIDR_EL1`
+/// affinity), or for all other CPUs if `cpu_id` is [`None`].
+pub(crate) fn send_sgi(sgi_num: usize, cpu_id: Option<usize>) {
+    const SGI1R_INTID_SHIFT: u64 = 24;
+    const SGI1R_AFF1_SHIFT: u64 = 16;
+    const SGI1R_AFF2_SHIFT: u64 = 32;
+    const SGI1R_IRM: u64 = 1 << 40;
+
+    trace!("send SGI {} to {:?}", sgi_num, cpu_id);
+    let intid = ((sgi_num as u64) & 0xf) << SGI1R_INTID_SHIFT;
+    let val = match cpu_id {
+        Some(id) => {
+            let aff0 = id & 0xff;
+            if aff0 >= 16 {
+                warn!("SGI target CPU {:#x} out of TargetList range", id);
+                return;
+            }
+            intid
+                | (((id >> 8) & 0xff) as u64) << SGI1R_AFF1_SHIFT
+                | (((id >> 16) & 0xff) as u64) << SGI1R_AFF2_SHIFT
+                | 1 << aff0
+        }
+        None => intid | SGI1R_IRM,
+    };
+    unsafe {
+        core::arch::asm!("msr icc_sgi1r_el1, {}", "isb", in(reg) val);
+    }
+}
+
 #[impl_plat_interface]
 impl IrqIf for IrqIfImpl {
     /// Enables or disables the given IRQ.
@@ -118,6 +167,16 @@ impl IrqIf for IrqIfImpl {
         let Some(irq) = GICR.lock().as_mut().unwrap().ack() else {
             return;
         };
+        if let Some(count) = IRQ_COUNTS.get(usize::from(irq)) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(count) = CPU_IRQ_COUNTS.get(current_cpu()) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+        #[cfg(feature = "lockup-detector")]
+        if usize::from(irq) == crate::config::devices::TIMER_IRQ {
+            crate::lockup::heartbeat();
+        }
         if !IRQ_HANDLER_TABLE.handle(irq.into()) {
             warn!("Unhandled IRQ {:?}", irq);
         }
@@ -129,7 +188,13 @@ impl IrqIf for IrqIfImpl {
     }
 
     /// Sends an inter-processor interrupt (IPI) to the specified target CPU or all CPUs.
-    fn send_ipi(_irq_num: usize, _target: axplat::irq::IpiTarget) {
-        todo!("send_ipi");
+    fn send_ipi(irq_num: usize, target: axplat::irq::IpiTarget) {
+        use axplat::irq::IpiTarget;
+        match target {
+            IpiTarget::Current { cpu_id } | IpiTarget::Other { cpu_id } => {
+                send_sgi(irq_num, Some(cpu_id))
+            }
+            IpiTarget::AllExceptCurrent { .. } => send_sgi(irq_num, None),
+        }
     }
 }
\ No newline at end of file
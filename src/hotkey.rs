@@ -0,0 +1,336 @@
+//! Laptop hotkeys and other platform events.
+//!
+//! Special keys on the PS/2 keyboard (`E0`-prefixed multimedia and ACPI
+//! keys, plus the keys listed in `hotkey-keys`) and events of the embedded
+//! controller (AC, lid, battery, and the queries listed in
+//! `hotkey-ec-queries`) are turned into named [`Event`]s. Hotkeys are not
+//! passed to the console as input.
+//!
+//! Each event first runs its default [`Action`], from `hotkey-actions` or a
+//! `hotkey.<event>=<action>` boot argument, then is handed to every
+//! [`subscribe`]d callback. Events are delivered when the keyboard or the
+//! EC is read, from their interrupts when they have one, and by [`poll`].
+//!
+//! The power button reports [`Event::PowerLong`] instead of [`Event::Power`]
+//! when released after `power-button-long-press-ms`.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use axplat::time::{NANOS_PER_MILLIS, monotonic_time_nanos};
+use kspin::SpinNoIrq;
+use log::{info, warn};
+
+use crate::config::devices::{
+    HOTKEY_ACTIONS, HOTKEY_EC_QUERIES, HOTKEY_KEYS, POWER_BUTTON_LONG_PRESS_MS,
+};
+use crate::ps2::keyboard::KeyCode;
+use crate::ringbuf::RingBuffer;
+
+/// Maximum number of subscribers.
+const MAX_SUBSCRIBERS: usize = 8;
+/// Size of the queue of events waiting for delivery.
+const EVENT_BUF_SIZE: usize = 32;
+
+/// A platform event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Power button, released before the long press delay.
+    Power,
+    /// Power button, held for the long press delay.
+    PowerLong,
+    Sleep,
+    Wake,
+    LidOpen,
+    LidClose,
+    AcOnline,
+    AcOffline,
+    /// The battery state changed, see [`crate::ec::battery`].
+    Battery,
+    BrightnessUp,
+    BrightnessDown,
+    VolumeUp,
+    VolumeDown,
+    Mute,
+    MicMute,
+    /// Wi-Fi / radio toggle.
+    Wireless,
+    /// External display switch.
+    Display,
+    TouchpadToggle,
+}
+
+impl Event {
+    /// Every event, in declaration order.
+    pub const ALL: [Self; 18] = [
+        Self::Power,
+        Self::PowerLong,
+        Self::Sleep,
+        Self::Wake,
+        Self::LidOpen,
+        Self::LidClose,
+        Self::AcOnline,
+        Self::AcOffline,
+        Self::Battery,
+        Self::BrightnessUp,
+        Self::BrightnessDown,
+        Self::VolumeUp,
+        Self::VolumeDown,
+        Self::Mute,
+        Self::MicMute,
+        Self::Wireless,
+        Self::Display,
+        Self::TouchpadToggle,
+    ];
+
+    /// Returns the name used in the config and boot arguments.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Power => "power",
+            Self::PowerLong => "power-long",
+            Self::Sleep => "sleep",
+            Self::Wake => "wake",
+            Self::LidOpen => "lid-open",
+            Self::LidClose => "lid-close",
+            Self::AcOnline => "ac-online",
+            Self::AcOffline => "ac-offline",
+            Self::Battery => "battery",
+            Self::BrightnessUp => "brightness-up",
+            Self::BrightnessDown => "brightness-down",
+            Self::VolumeUp => "volume-up",
+            Self::VolumeDown => "volume-down",
+            Self::Mute => "mute",
+            Self::MicMute => "mic-mute",
+            Self::Wireless => "wireless",
+            Self::Display => "display",
+            Self::TouchpadToggle => "touchpad-toggle",
+        }
+    }
+
+    /// Looks up an event by name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|e| e.name() == name)
+    }
+}
+
+/// What to do on an event before the subscribers see it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    None,
+    /// Calls the handler set with [`set_suspend_handler`].
+    Suspend,
+    /// Powers the system off.
+    PowerOff,
+    /// Resets the system.
+    Reboot,
+}
+
+impl Action {
+    /// Looks up an action by its config name: `none`, `suspend`,
+    /// `poweroff` or `reboot`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Self::None),
+            "suspend" => Some(Self::Suspend),
+            "poweroff" => Some(Self::PowerOff),
+            "reboot" => Some(Self::Reboot),
+            _ => None,
+        }
+    }
+}
+
+/// A callback registered with [`subscribe`].
+type Subscriber = fn(Event);
+
+/// A subscription returned by [`subscribe`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subscription(usize);
+
+/// Keys that are hotkeys on every keyboard.
+const STANDARD_KEYS: &[(KeyCode, Event)] = &[
+    (KeyCode::MUTE, Event::Mute),
+    (KeyCode::VOLUME_DOWN, Event::VolumeDown),
+    (KeyCode::VOLUME_UP, Event::VolumeUp),
+    (KeyCode::POWER, Event::Power),
+    (KeyCode::SLEEP, Event::Sleep),
+    (KeyCode::WAKE, Event::Wake),
+];
+
+static ACTIONS: SpinNoIrq<[Action; Event::ALL.len()]> =
+    SpinNoIrq::new([Action::None; Event::ALL.len()]);
+static SUBSCRIBERS: SpinNoIrq<[Option<Subscriber>; MAX_SUBSCRIBERS]> =
+    SpinNoIrq::new([None; MAX_SUBSCRIBERS]);
+static PENDING: SpinNoIrq<RingBuffer<Event, EVENT_BUF_SIZE>> = SpinNoIrq::new(RingBuffer::new());
+static SUSPEND: SpinNoIrq<Option<fn()>> = SpinNoIrq::new(None);
+/// When the power button went down, in nanoseconds, 0 if it is up.
+static POWER_PRESSED_AT: AtomicU64 = AtomicU64::new(0);
+
+fn queue(event: Event) {
+    if !PENDING.lock().push(event) {
+        warn!("hotkey: queue full, dropping {}", event.name());
+    }
+}
+
+/// Returns the event of a key, if it is a hotkey.
+fn key_event(code: KeyCode) -> Option<Event> {
+    HOTKEY_KEYS
+        .iter()
+        .find(|&&(key, _)| key == code.0 as usize)
+        .and_then(|&(_, name)| Event::from_name(name))
+        .or_else(|| STANDARD_KEYS.iter().find(|&&(key, _)| key == code).map(|&(_, e)| e))
+}
+
+/// Takes a key press or release from the keyboard.
+///
+/// Returns whether the key is a hotkey, which then is not console input.
+pub(crate) fn key(code: KeyCode, pressed: bool, repeat: bool) -> bool {
+    let Some(event) = key_event(code) else {
+        return false;
+    };
+    if event == Event::Power {
+        let now = monotonic_time_nanos().max(1);
+        if pressed {
+            if !repeat {
+                POWER_PRESSED_AT.store(now, Ordering::Relaxed);
+            }
+            return true;
+        }
+        let pressed_at = POWER_PRESSED_AT.swap(0, Ordering::Relaxed);
+        if pressed_at == 0 {
+            return true;
+        }
+        let held_ms = (now - pressed_at) / NANOS_PER_MILLIS;
+        queue(if held_ms >= POWER_BUTTON_LONG_PRESS_MS as u64 {
+            Event::PowerLong
+        } else {
+            Event::Power
+        });
+    } else if pressed && !repeat {
+        queue(event);
+    }
+    true
+}
+
+/// Takes an event of the embedded controller.
+pub(crate) fn ec_event(event: crate::ec::Event) {
+    use crate::ec::Event as Ec;
+    let event = match event {
+        Ec::Ac(true) => Event::AcOnline,
+        Ec::Ac(false) => Event::AcOffline,
+        Ec::Lid(true) => Event::LidOpen,
+        Ec::Lid(false) => Event::LidClose,
+        Ec::Battery => Event::Battery,
+        Ec::Query(query) => {
+            let Some(event) = HOTKEY_EC_QUERIES
+                .iter()
+                .find(|&&(q, _)| q == query as usize)
+                .and_then(|&(_, name)| Event::from_name(name))
+            else {
+                return;
+            };
+            event
+        }
+    };
+    queue(event);
+}
+
+fn run_action(event: Event) {
+    let action = ACTIONS.lock()[event as usize];
+    match action {
+        Action::None => {}
+        Action::Suspend => {
+            let suspend = *SUSPEND.lock();
+            match suspend {
+                Some(suspend) => suspend(),
+                None => warn!("hotkey: {}: no suspend handler", event.name()),
+            }
+        }
+        Action::PowerOff => {
+            info!("hotkey: {}: powering off", event.name());
+            crate::power::system_off();
+        }
+        Action::Reboot => {
+            info!("hotkey: {}: rebooting", event.name());
+            crate::power::system_reset();
+        }
+    }
+}
+
+/// Runs the actions of the queued events and hands them to the subscribers.
+///
+/// Called with no driver lock held, as actions and subscribers may use the
+/// drivers.
+pub(crate) fn dispatch() {
+    loop {
+        let Some(event) = PENDING.lock().pop() else {
+            return;
+        };
+        run_action(event);
+        let subscribers = *SUBSCRIBERS.lock();
+        for subscriber in subscribers.iter().flatten() {
+            subscriber(event);
+        }
+    }
+}
+
+/// Reads pending keyboard and EC input and delivers the events found.
+///
+/// Only needed when nothing else reads the keyboard or the EC, and they
+/// have no interrupt.
+pub fn poll() {
+    crate::ps2::keyboard::process();
+    crate::ec::poll();
+    dispatch();
+}
+
+/// Registers a callback invoked with every event, after its default action.
+///
+/// Callbacks may run in interrupt context. Returns [`None`] if there is no
+/// room left.
+pub fn subscribe(callback: Subscriber) -> Option<Subscription> {
+    let mut subscribers = SUBSCRIBERS.lock();
+    let index = subscribers.iter().position(|s| s.is_none())?;
+    subscribers[index] = Some(callback);
+    Some(Subscription(index))
+}
+
+/// Removes a callback registered with [`subscribe`].
+pub fn unsubscribe(subscription: Subscription) {
+    SUBSCRIBERS.lock()[subscription.0] = None;
+}
+
+/// Returns the default action of an event.
+pub fn action(event: Event) -> Action {
+    ACTIONS.lock()[event as usize]
+}
+
+/// Sets the default action of an event.
+pub fn set_action(event: Event, action: Action) {
+    ACTIONS.lock()[event as usize] = action;
+}
+
+/// Sets how to suspend the system, for [`Action::Suspend`].
+pub fn set_suspend_handler(handler: Option<fn()>) {
+    *SUSPEND.lock() = handler;
+}
+
+fn set_action_by_name(event: &str, action: &str) {
+    match (Event::from_name(event), Action::from_name(action)) {
+        (Some(event), Some(action)) => set_action(event, action),
+        _ => warn!("hotkey: bad action {:?} for event {:?}", action, event),
+    }
+}
+
+/// Sets the default actions from the config and the boot arguments.
+pub(crate) fn init() {
+    for &(event, action) in HOTKEY_ACTIONS.iter() {
+        set_action_by_name(event, action);
+    }
+    for arg in crate::bootargs::cmdline().split_ascii_whitespace() {
+        let Some((key, action)) = arg.split_once('=') else {
+            continue;
+        };
+        if let Some(event) = key.strip_prefix("hotkey.") {
+            set_action_by_name(event, action);
+        }
+    }
+}
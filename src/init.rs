@@ -1,7 +1,7 @@
 use axplat::init::InitIf;
 
 #[allow(unused_imports)]
-use crate::config::devices::{GICD_PADDR, GICR_PADDR, RTC_PADDR, TIMER_IRQ, UART_IRQ, UART_PADDR, PS2_KEYBOARD_PADDR, SIMPLEFB_PADDR};
+use crate::config::devices::{GICD_PADDR, GICR_PADDR, TIMER_IRQ, UART_IRQ, UART_PADDR};
 use crate::config::plat::PSCI_METHOD;
 use axplat::mem::{pa, phys_to_virt};
 
@@ -14,19 +14,34 @@ impl InitIf for InitIfImpl {
     /// This function should be called immediately after the kernel has booted,
     /// and performed earliest platform configuration and initialization (e.g.,
     /// early console, clocking).
-    fn init_early(_cpu_id: usize, _dtb: usize) {
+    fn init_early(_cpu_id: usize, dtb: usize) {
         axcpu::init::init_trap();
+        crate::fdt::init(dtb);
+        crate::bootargs::init();
+        #[cfg(feature = "gdbstub")]
+        crate::gdbstub::init_early();
         crate::pl011::init_early(phys_to_virt(pa!(UART_PADDR)));
+        #[cfg(feature = "serial-mux")]
+        crate::serial_mux::init();
+        crate::console::init_early();
         axplat_aarch64_peripherals::psci::init(PSCI_METHOD);
         crate::generic_timer::init_early();
+        crate::lpc::init_early();
+        crate::ns16550::init_early();
+        crate::simplefb::probe();
         #[cfg(feature = "rtc")]
-        axplat_aarch64_peripherals::pl031::init_early(phys_to_virt(pa!(RTC_PADDR)));
+        crate::rtc::init_early();
+        #[cfg(feature = "watchdog")]
+        crate::watchdog::probe();
     }
 
     /// Initializes the platform at the early stage for secondary cores.
     #[cfg(feature = "smp")]
     fn init_early_secondary(_cpu_id: usize) {
         axcpu::init::init_trap();
+        #[cfg(feature = "gdbstub")]
+        crate::gdbstub::init_early_secondary();
+        crate::generic_timer::init_percpu();
     }
 
     /// Initializes the platform at the later stage for the primary core.
@@ -35,9 +50,9 @@ impl InitIf for InitIfImpl {
     /// initialization (e.g, logging, memory management), and finalized the rest of
     /// platform configuration and initialization.
     fn init_later(_cpu_id: usize, _dtb: usize) {
-        // Initialize PS/2 Keyboard (Polling Mode)
-        // PIO base is 0x1000_0000 (LPC Base), mapped at 0xffff_0000_1000_0000
-        ps2_keyboard::init(phys_to_virt(pa!(PS2_KEYBOARD_PADDR)).as_usize());
+        crate::hotkey::init();
+        crate::ps2::init();
+        crate::ec::init();
 
         #[cfg(feature = "irq")]
         {
@@ -49,16 +64,28 @@ impl InitIf for InitIfImpl {
 
             // enable UART IRQs
             axplat::irq::register(UART_IRQ, crate::pl011::irq_handler);
+            crate::pl011::init_irq();
+            crate::ns16550::init_irq();
+            crate::ps2::init_irq();
+            crate::ec::init_irq();
+
+            #[cfg(feature = "lockup-detector")]
+            crate::lockup::init();
+            #[cfg(feature = "sysrq")]
+            crate::sysrq::init();
         }
 
+        crate::timer_errata::init_later();
+        #[cfg(feature = "rtc")]
+        crate::rtc::init_later();
+        #[cfg(feature = "watchdog")]
+        crate::watchdog::init();
+
         // Initialize SimpleFb console with font height 16 (16x16 pixels)
-        // Framebuffer is mapped at 0xffff_0000_ecd2_0000
-        crate::simplefb::init(simplefb::FramebufferConfig {
-            base_addr: phys_to_virt(pa!(SIMPLEFB_PADDR)).as_usize(),
-            width: 1920,
-            height: 1200,
-            font_height: 16,
-        });
+        crate::simplefb::init(16);
+        crate::console::init_later();
+        #[cfg(feature = "gdbstub")]
+        crate::gdbstub::init_later();
     }
 
     /// Initializes the platform at the later stage for secondary cores.
@@ -68,6 +95,12 @@ impl InitIf for InitIfImpl {
         {
             crate::gicv3::init_current_cpu();
             crate::generic_timer::enable_irqs(TIMER_IRQ);
+            #[cfg(feature = "lockup-detector")]
+            crate::lockup::init_secondary();
+            #[cfg(feature = "sysrq")]
+            crate::sysrq::init_secondary();
+            #[cfg(feature = "gdbstub")]
+            crate::gdbstub::init_later_secondary();
         }
     }
 }
@@ -5,15 +5,50 @@ extern crate axplat;
 
 extern crate alloc;
 
+#[cfg(feature = "watchdog")]
+mod acpi;
+#[cfg(any(feature = "lockup-detector", feature = "sysrq"))]
+mod backtrace;
 mod boot;
+mod bootargs;
+pub mod console;
+#[cfg(any(feature = "serial-mux", feature = "xmodem"))]
+mod crc16;
+mod earlycon;
+pub mod ec;
+mod efi;
+mod fdt;
+#[cfg(feature = "gdbstub")]
+pub mod gdbstub;
+pub mod hotkey;
 mod init;
+#[cfg(feature = "lockup-detector")]
+mod lockup;
 mod mem;
+pub mod lpc;
+pub mod ns16550;
 mod power;
+pub mod ps2;
 #[cfg(feature = "irq")]
 mod gicv3;
-mod pl011;
+pub mod pl011;
 mod generic_timer;
-mod simplefb;
+#[cfg(feature = "rtc")]
+mod rtc;
+mod ringbuf;
+#[cfg(feature = "serial-mux")]
+pub mod serial_mux;
+pub mod simplefb;
+#[cfg(feature = "sysrq")]
+pub mod sysrq;
+mod timer_errata;
+mod uart;
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
+#[cfg(feature = "xmodem")]
+pub mod xmodem;
+
+pub use generic_timer::{VdsoTimeData, vdso_data};
 
 pub mod config {
     //! Platform configuration module.
@@ -0,0 +1,151 @@
+//! Per-CPU hard-lockup detector.
+//!
+//! Every CPU records a heartbeat from its generic timer interrupt, and checks
+//! the heartbeats of the other CPUs at the same time. A CPU whose heartbeat is
+//! older than `lockup-threshold-ms` is spinning with IRQs masked (or is
+//! otherwise wedged) and is reported through the emergency console path,
+//! which does not wait for locks the stuck CPU may hold:
+//!
+//! - if the CPU's CoreSight external debug frame is known (`cpu-debug-paddrs`),
+//!   its PC is sampled from `EDPCSR`, which works even with IRQs masked;
+//! - a backtrace request SGI is sent to it, so it dumps its PC and stack as
+//!   soon as it unmasks IRQs again.
+//!
+//! The PMU overflow interrupt is not used as a watchdog source: without GIC
+//! pseudo-NMI support it is masked by `DAIF` just like the timer.
+//!
+//! CPUs are numbered as the `cpu` nodes under `/cpus` in the DTB, which is
+//! also the order of `cpu-debug-paddrs`. Without a DTB, the `MPIDR_EL1`
+//! affinity is used as the number.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use aarch64_cpu::registers::{CNTFRQ_EL0, ELR_EL1, Readable};
+use axplat::mem::{pa, phys_to_virt};
+
+use crate::config::devices::{CPU_DEBUG_PADDRS, LOCKUP_IPI_IRQ};
+use crate::config::plat::{LOCKUP_THRESHOLD_MS, MAX_CPU_NUM};
+use crate::console::EmergencyWriter as Console;
+use crate::gicv3::current_cpu;
+
+/// External debug: PC sample register, low half (reading it samples the PC).
+const EDPCSR_LO: usize = 0x0a0;
+/// External debug: PC sample register, high half.
+const EDPCSR_HI: usize = 0x0ac;
+
+/// Counter value of the last timer interrupt on each CPU, 0 if never seen.
+static HEARTBEATS: [AtomicU64; MAX_CPU_NUM] = [const { AtomicU64::new(0) }; MAX_CPU_NUM];
+/// Whether a lockup has already been reported for each CPU.
+static REPORTED: [AtomicBool; MAX_CPU_NUM] = [const { AtomicBool::new(false) }; MAX_CPU_NUM];
+/// `MPIDR_EL1` affinity of each CPU, [`NO_CPU`] for unused numbers.
+static AFFINITIES: [AtomicUsize; MAX_CPU_NUM] = [const { AtomicUsize::new(NO_CPU) }; MAX_CPU_NUM];
+const NO_CPU: usize = usize::MAX;
+
+/// Returns the number of the CPU with the given affinity.
+fn cpu_index(affinity: usize) -> Option<usize> {
+    AFFINITIES.iter().position(|a| a.load(Ordering::Relaxed) == affinity)
+}
+
+/// Numbers the CPUs from the DTB, or by affinity without one.
+fn init_affinities() {
+    let cpus = crate::fdt::get().and_then(|fdt| fdt.find_node("/cpus"));
+    let Some(cpus) = cpus else {
+        for (cpu, affinity) in AFFINITIES.iter().enumerate() {
+            affinity.store(cpu, Ordering::Relaxed);
+        }
+        return;
+    };
+    let affinities = cpus
+        .children()
+        .filter(|node| node.property_str("device_type") == Some("cpu"))
+        .filter_map(|node| node.property_u64("reg"));
+    for (slot, affinity) in AFFINITIES.iter().zip(affinities) {
+        slot.store(affinity as usize & 0xffffff, Ordering::Relaxed);
+    }
+}
+
+fn threshold_ticks() -> u64 {
+    CNTFRQ_EL0.get() * LOCKUP_THRESHOLD_MS as u64 / 1000
+}
+
+/// Samples the PC of `cpu` through its external debug interface.
+fn sample_pc(cpu: usize) -> Option<u64> {
+    let base = *CPU_DEBUG_PADDRS.get(cpu)?;
+    let base = phys_to_virt(pa!(base)).as_usize();
+    // SAFETY: the debug frames are part of the MMIO ranges.
+    let (lo, hi) = unsafe {
+        (
+            core::ptr::read_volatile((base + EDPCSR_LO) as *const u32),
+            core::ptr::read_volatile((base + EDPCSR_HI) as *const u32),
+        )
+    };
+    // All ones means the sample is not available (e.g. the core is halted).
+    (lo != u32::MAX).then_some(((hi as u64) << 32) | lo as u64)
+}
+
+/// Records a heartbeat for the current CPU and checks the other CPUs.
+///
+/// Called from the timer interrupt.
+pub fn heartbeat() {
+    if LOCKUP_THRESHOLD_MS == 0 {
+        return;
+    }
+    let Some(cpu) = cpu_index(current_cpu()) else {
+        return;
+    };
+    let now = crate::timer_errata::read_counter();
+    HEARTBEATS[cpu].store(now, Ordering::Relaxed);
+    REPORTED[cpu].store(false, Ordering::Relaxed);
+
+    let threshold = threshold_ticks();
+    for (other, beat) in HEARTBEATS.iter().enumerate() {
+        let last = beat.load(Ordering::Relaxed);
+        if other == cpu || last == 0 || now.saturating_sub(last) < threshold {
+            continue;
+        }
+        if REPORTED[other].swap(true, Ordering::Relaxed) {
+            continue;
+        }
+        let stuck_ms = (now - last) * 1000 / CNTFRQ_EL0.get();
+        let _ = writeln!(
+            Console,
+            "\nLOCKUP: CPU {} stuck for {} ms (detected by CPU {})",
+            other, stuck_ms, cpu
+        );
+        if let Some(pc) = sample_pc(other) {
+            let _ = writeln!(Console, "LOCKUP: CPU {} PC sample {:#018x}", other, pc);
+        }
+        crate::gicv3::send_sgi(LOCKUP_IPI_IRQ, Some(AFFINITIES[other].load(Ordering::Relaxed)));
+    }
+}
+
+/// Prints the interrupted PC and a frame-pointer backtrace of the current CPU.
+fn dump_current() {
+    let _ = writeln!(
+        Console,
+        "LOCKUP: CPU {} resumed, interrupted at PC {:#018x}, backtrace:",
+        cpu_index(current_cpu()).unwrap_or(current_cpu()),
+        ELR_EL1.get()
+    );
+    crate::backtrace::write_interrupted(&mut Console);
+}
+
+/// Registers the backtrace request handler.
+pub fn init() {
+    init_affinities();
+    if LOCKUP_THRESHOLD_MS != 0 {
+        axplat::irq::register(LOCKUP_IPI_IRQ, dump_current);
+    }
+}
+
+/// Enables the backtrace request SGI on a secondary CPU.
+///
+/// SGIs are banked per CPU, so the enable done by [`init`] only covers the
+/// primary CPU.
+#[cfg(feature = "smp")]
+pub fn init_secondary() {
+    if LOCKUP_THRESHOLD_MS != 0 {
+        axplat::irq::set_enable(LOCKUP_IPI_IRQ, true);
+    }
+}
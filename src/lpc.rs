@@ -0,0 +1,215 @@
+//! LPC bus I/O port window.
+//!
+//! The LPC bridge maps the 64 KiB legacy I/O port space at `lpc-paddr`, so
+//! port accesses are plain MMIO accesses at `window + port`. Drivers reserve
+//! the ports they use with [`reserve`], which keeps two drivers from claiming
+//! the same device.
+//!
+//! The legacy devices to look for are listed in the `lpc-devices` config.
+//! [`init_early`] reserves the ports of each listed device and probes it;
+//! drivers check [`is_present`] before touching their device.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use axplat::mem::{pa, phys_to_virt};
+use kspin::SpinNoIrq;
+use log::{info, warn};
+
+use crate::config::devices::{LPC_DEVICES, LPC_PADDR};
+
+/// Size of the I/O port space.
+const PORT_SPACE: u32 = 0x1_0000;
+/// Maximum number of reserved port ranges.
+const MAX_RESERVATIONS: usize = 16;
+
+/// A range of reserved ports.
+#[derive(Debug, Clone, Copy)]
+pub struct Reservation {
+    /// Name of the owner.
+    pub name: &'static str,
+    pub start: u16,
+    pub len: u16,
+}
+
+impl Reservation {
+    fn overlaps(&self, start: u16, len: u16) -> bool {
+        (start as u32) < self.start as u32 + self.len as u32
+            && (self.start as u32) < start as u32 + len as u32
+    }
+}
+
+/// A port reservation error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// There is no LPC window (`lpc-paddr` is 0).
+    Absent,
+    /// The range is empty or goes past the end of the port space.
+    BadRange,
+    /// Part of the range is already reserved by the named owner.
+    Busy(&'static str),
+    /// The reservation table is full.
+    TableFull,
+}
+
+/// A legacy device that can be listed in `lpc-devices`.
+struct LegacyDevice {
+    name: &'static str,
+    /// Port ranges, as `(start, len)`.
+    ports: &'static [(u16, u16)],
+    /// Returns whether the device answers.
+    probe: fn() -> bool,
+}
+
+static LEGACY_DEVICES: &[LegacyDevice] = &[
+    LegacyDevice {
+        name: "i8042",
+        ports: &[(0x60, 1), (0x64, 1)],
+        probe: crate::ps2::probe,
+    },
+    LegacyDevice {
+        name: "ec",
+        ports: &[(0x62, 1), (0x66, 1)],
+        probe: crate::ec::probe,
+    },
+    LegacyDevice {
+        name: "ns16550",
+        ports: &[(crate::config::devices::NS16550_PORT as u16, 8)],
+        probe: crate::ns16550::probe,
+    },
+    #[cfg(feature = "rtc")]
+    LegacyDevice {
+        name: "cmos-rtc",
+        ports: &[(crate::config::devices::CMOS_RTC_PORT as u16, 2)],
+        probe: crate::rtc::cmos::probe,
+    },
+];
+
+static RESERVATIONS: SpinNoIrq<[Option<Reservation>; MAX_RESERVATIONS]> =
+    SpinNoIrq::new([None; MAX_RESERVATIONS]);
+/// Devices of [`LEGACY_DEVICES`] found by [`init_early`], one bit each.
+static PRESENT: AtomicU32 = AtomicU32::new(0);
+
+fn addr(port: u16) -> usize {
+    phys_to_virt(pa!(LPC_PADDR)).as_usize() + port as usize
+}
+
+/// Reads a byte from an I/O port.
+pub fn inb(port: u16) -> u8 {
+    // SAFETY: the LPC window is in the MMIO ranges and mapped as device memory.
+    unsafe { core::ptr::read_volatile(addr(port) as *const u8) }
+}
+
+/// Writes a byte to an I/O port.
+pub fn outb(port: u16, val: u8) {
+    // SAFETY: the LPC window is in the MMIO ranges and mapped as device memory.
+    unsafe { core::ptr::write_volatile(addr(port) as *mut u8, val) }
+}
+
+/// Reads a 16-bit word from an I/O port.
+pub fn inw(port: u16) -> u16 {
+    // SAFETY: the LPC window is in the MMIO ranges and mapped as device memory.
+    unsafe { core::ptr::read_volatile(addr(port) as *const u16) }
+}
+
+/// Writes a 16-bit word to an I/O port.
+pub fn outw(port: u16, val: u16) {
+    // SAFETY: the LPC window is in the MMIO ranges and mapped as device memory.
+    unsafe { core::ptr::write_volatile(addr(port) as *mut u16, val) }
+}
+
+/// Reads a 32-bit word from an I/O port.
+pub fn inl(port: u16) -> u32 {
+    // SAFETY: the LPC window is in the MMIO ranges and mapped as device memory.
+    unsafe { core::ptr::read_volatile(addr(port) as *const u32) }
+}
+
+/// Writes a 32-bit word to an I/O port.
+pub fn outl(port: u16, val: u32) {
+    // SAFETY: the LPC window is in the MMIO ranges and mapped as device memory.
+    unsafe { core::ptr::write_volatile(addr(port) as *mut u32, val) }
+}
+
+/// Returns whether there is an LPC window at all.
+pub fn has_window() -> bool {
+    LPC_PADDR != 0
+}
+
+/// Reserves `len` ports from `start` for `name`.
+pub fn reserve(name: &'static str, start: u16, len: u16) -> Result<(), Error> {
+    if !has_window() {
+        return Err(Error::Absent);
+    }
+    if len == 0 || start as u32 + len as u32 > PORT_SPACE {
+        return Err(Error::BadRange);
+    }
+    let mut table = RESERVATIONS.lock();
+    if let Some(other) = table.iter().flatten().find(|r| r.overlaps(start, len)) {
+        return Err(Error::Busy(other.name));
+    }
+    let slot = table.iter_mut().find(|r| r.is_none()).ok_or(Error::TableFull)?;
+    *slot = Some(Reservation { name, start, len });
+    Ok(())
+}
+
+/// Releases the reservation of `name` starting at `start`.
+///
+/// Returns `false` if there is no such reservation.
+pub fn release(name: &str, start: u16) -> bool {
+    let mut table = RESERVATIONS.lock();
+    match table
+        .iter_mut()
+        .find(|r| r.is_some_and(|r| r.name == name && r.start == start))
+    {
+        Some(slot) => {
+            *slot = None;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Calls `f` with every reservation, in no particular order.
+pub fn for_each_reservation(mut f: impl FnMut(&Reservation)) {
+    let table = *RESERVATIONS.lock();
+    table.iter().flatten().for_each(&mut f);
+}
+
+/// Returns whether the legacy device `name` was listed in `lpc-devices` and
+/// answered its probe.
+pub fn is_present(name: &str) -> bool {
+    LEGACY_DEVICES
+        .iter()
+        .position(|d| d.name == name)
+        .is_some_and(|i| PRESENT.load(Ordering::Relaxed) & (1 << i) != 0)
+}
+
+/// Reserves and probes the legacy devices listed in `lpc-devices`.
+pub(crate) fn init_early() {
+    if !has_window() {
+        return;
+    }
+    for &name in LPC_DEVICES.iter() {
+        let Some(index) = LEGACY_DEVICES.iter().position(|d| d.name == name) else {
+            warn!("LPC: no driver for legacy device {:?}", name);
+            continue;
+        };
+        let device = &LEGACY_DEVICES[index];
+        let failed = device.ports.iter().position(|&(start, len)| {
+            reserve(device.name, start, len)
+                .inspect_err(|e| warn!("LPC: {}: cannot reserve ports {:#x}+{}: {:?}", name, start, len, e))
+                .is_err()
+        });
+        if let Some(failed) = failed {
+            for &(start, _) in &device.ports[..failed] {
+                release(device.name, start);
+            }
+            continue;
+        }
+        if (device.probe)() {
+            info!("LPC: found {}", name);
+            PRESENT.fetch_or(1 << index, Ordering::Relaxed);
+        } else {
+            info!("LPC: {} not responding", name);
+        }
+    }
+}
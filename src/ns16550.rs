@@ -0,0 +1,333 @@
+//! NS16550-compatible UART on the LPC bus (`ns16550` in `lpc-devices`),
+//! usually the first UART of a Super I/O chip. It is the `ttyS0` console.
+//!
+//! Buffering is shared with the PL011 (see `crate::uart`): with the `irq`
+//! feature and `ns16550-irq` set, received bytes are moved by the interrupt
+//! into a ring buffer, and output is queued in a TX ring buffer drained by
+//! the transmit interrupt. Before that, and in synchronous mode, output is
+//! written directly with busy-waiting, and input is polled.
+
+use crate::config::devices::{NS16550_BAUD, NS16550_CLOCK_HZ, NS16550_PORT, UART_RX_ECHO};
+use crate::console::{ConsoleSink, ConsoleSource};
+use crate::lpc;
+use crate::pl011::{LineError, LineErrors, LineSettings, Parity, RxOverruns, StopBits};
+use crate::uart::{BufferedUart, RX_BREAK, RX_FRAMING, RX_OVERRUN, RX_PARITY, UartRegs};
+
+/// Receive Buffer (read) / Transmit Holding (write) Register, or Divisor
+/// Latch low byte with LCR.DLAB.
+const RBR: u16 = 0;
+const THR: u16 = 0;
+const DLL: u16 = 0;
+/// Interrupt Enable Register, or Divisor Latch high byte with LCR.DLAB.
+const IER: u16 = 1;
+const DLM: u16 = 1;
+/// FIFO Control Register (write).
+const FCR: u16 = 2;
+/// Line Control Register.
+const LCR: u16 = 3;
+/// Modem Control Register.
+const MCR: u16 = 4;
+/// Line Status Register.
+const LSR: u16 = 5;
+/// Scratch Register.
+const SCR: u16 = 7;
+
+/// IER bits: received data, transmit holding register empty, line status.
+#[cfg(feature = "irq")]
+const IER_ERBFI: u8 = 1 << 0;
+const IER_ETBEI: u8 = 1 << 1;
+#[cfg(feature = "irq")]
+const IER_ELSI: u8 = 1 << 2;
+/// FCR bits: enable and clear the FIFOs, RX trigger level of 8 bytes.
+const FCR_ENABLE: u8 = 1 << 0;
+const FCR_CLEAR_RX: u8 = 1 << 1;
+const FCR_CLEAR_TX: u8 = 1 << 2;
+const FCR_TRIGGER_8: u8 = 2 << 6;
+/// LCR bits.
+const LCR_STB: u8 = 1 << 2;
+const LCR_PEN: u8 = 1 << 3;
+const LCR_EPS: u8 = 1 << 4;
+const LCR_STICK: u8 = 1 << 5;
+const LCR_DLAB: u8 = 1 << 7;
+/// MCR bits. OUT2 gates the interrupt line on PC-style UARTs, AFE enables
+/// RTS/CTS flow control on the chips that have it (16750, most Super I/O).
+const MCR_DTR: u8 = 1 << 0;
+const MCR_RTS: u8 = 1 << 1;
+const MCR_OUT2: u8 = 1 << 3;
+const MCR_AFE: u8 = 1 << 5;
+/// LSR bits.
+const LSR_DR: u8 = 1 << 0;
+const LSR_OE: u8 = 1 << 1;
+const LSR_PE: u8 = 1 << 2;
+const LSR_FE: u8 = 1 << 3;
+const LSR_BI: u8 = 1 << 4;
+const LSR_THRE: u8 = 1 << 5;
+const LSR_TEMT: u8 = 1 << 6;
+
+/// Bytes the TX FIFO takes once it is empty.
+const TX_FIFO_SIZE: usize = 16;
+
+static PORT: BufferedUart<Ns16550Regs> = BufferedUart::new(Ns16550Regs, UART_RX_ECHO);
+
+fn read_reg(reg: u16) -> u8 {
+    lpc::inb(NS16550_PORT as u16 + reg)
+}
+
+fn write_reg(reg: u16, val: u8) {
+    lpc::outb(NS16550_PORT as u16 + reg, val)
+}
+
+/// Register access for [`BufferedUart`].
+struct Ns16550Regs;
+
+impl UartRegs for Ns16550Regs {
+    fn read_rx(&self) -> Option<(u8, u8)> {
+        // Reading LSR clears its error bits, which belong to the byte at the
+        // head of the FIFO.
+        let lsr = read_reg(LSR);
+        if lsr & LSR_DR == 0 {
+            return None;
+        }
+        let c = read_reg(RBR);
+        let mut errors = 0;
+        for (bit, error) in
+            [(LSR_OE, RX_OVERRUN), (LSR_BI, RX_BREAK), (LSR_FE, RX_FRAMING), (LSR_PE, RX_PARITY)]
+        {
+            if lsr & bit != 0 {
+                errors |= error;
+            }
+        }
+        Some((c, errors))
+    }
+
+    fn tx_room(&self) -> usize {
+        // The UART only tells when the FIFO is empty, not how full it is.
+        if read_reg(LSR) & LSR_THRE != 0 { TX_FIFO_SIZE } else { 0 }
+    }
+
+    fn write_tx(&self, c: u8) {
+        write_reg(THR, c);
+    }
+
+    fn set_tx_irq(&self, enabled: bool) {
+        let ier = read_reg(IER);
+        write_reg(IER, if enabled { ier | IER_ETBEI } else { ier & !IER_ETBEI });
+    }
+
+    fn tx_idle(&self) -> bool {
+        read_reg(LSR) & LSR_TEMT != 0
+    }
+}
+
+/// Reads a byte from the UART, or returns [`None`] if no input is available.
+///
+/// Bytes already buffered by the interrupt come first, then the UART is
+/// polled.
+pub fn getchar() -> Option<u8> {
+    PORT.getchar()
+}
+
+/// Write a slice of bytes to the UART.
+pub fn write_bytes(bytes: &[u8]) {
+    PORT.write(bytes, true);
+}
+
+/// Writes binary data to the UART, without newline translation.
+pub fn write_raw(bytes: &[u8]) {
+    PORT.write(bytes, false);
+}
+
+/// The UART as a console output device.
+pub static CONSOLE_SINK: ConsoleSink = ConsoleSink::new("ttyS0", write_bytes);
+/// The UART as a console input device.
+pub static CONSOLE_SOURCE: ConsoleSource = ConsoleSource::new("ttyS0", getchar);
+
+/// Returns whether the UART was found and set up.
+pub fn is_present() -> bool {
+    PORT.is_present()
+}
+
+/// Switches output to synchronous mode, bypassing the TX interrupt.
+///
+/// Meant for panic and shutdown paths, where interrupts may never be taken
+/// again. Pending bytes are flushed first.
+pub fn set_synchronous(enabled: bool) {
+    PORT.set_synchronous(enabled);
+}
+
+/// Writes bytes directly to the UART, flushing queued output first.
+///
+/// Used by the panic path: the TX buffer lock is broken if its holder does
+/// not release it, and the TX interrupt is turned off for good.
+pub fn emergency_write(bytes: &[u8]) {
+    PORT.emergency_write(bytes);
+}
+
+/// Waits until all queued bytes have been transmitted.
+pub fn flush() {
+    PORT.flush();
+}
+
+/// Enables or disables echoing received bytes back to the UART.
+pub fn set_echo(enabled: bool) {
+    PORT.set_echo(enabled);
+}
+
+/// Returns whether received bytes are echoed back to the UART.
+pub fn echo_enabled() -> bool {
+    PORT.echo_enabled()
+}
+
+/// Sets the callback invoked from the interrupt after new bytes have been
+/// buffered, e.g. to wake up a reader blocked on the console.
+pub fn set_rx_wakeup(callback: Option<fn()>) {
+    PORT.set_rx_wakeup(callback);
+}
+
+/// Returns the receive overrun counters.
+pub fn rx_overruns() -> RxOverruns {
+    PORT.rx_overruns()
+}
+
+/// Returns the line error counters.
+pub fn line_errors() -> LineErrors {
+    PORT.line_errors()
+}
+
+/// Computes the divisor latch value for `baud` from the input clock.
+fn baud_divisor(baud: u32) -> Option<u16> {
+    if baud == 0 {
+        return None;
+    }
+    // Input clock / (16 * baud), rounded.
+    let div = (NS16550_CLOCK_HZ as u64 + 8 * baud as u64) / (16 * baud as u64);
+    match div {
+        1..=0xffff => Some(div as u16),
+        _ => None,
+    }
+}
+
+/// Reprograms the serial line.
+///
+/// Queued output is flushed first. Returns the baud rate actually achieved,
+/// which may differ slightly from the requested one.
+pub fn set_line(settings: &LineSettings) -> Result<u32, LineError> {
+    if !(5..=8).contains(&settings.data_bits) {
+        return Err(LineError::UnsupportedDataBits);
+    }
+    let div = baud_divisor(settings.baud).ok_or(LineError::UnsupportedBaud)?;
+
+    let mut lcr = settings.data_bits - 5;
+    lcr |= match settings.parity {
+        Parity::None => 0,
+        Parity::Odd => LCR_PEN,
+        Parity::Even => LCR_PEN | LCR_EPS,
+        Parity::Mark => LCR_PEN | LCR_STICK,
+        Parity::Space => LCR_PEN | LCR_EPS | LCR_STICK,
+    };
+    if settings.stop_bits == StopBits::Two {
+        lcr |= LCR_STB;
+    }
+    let mut mcr = MCR_DTR | MCR_RTS | MCR_OUT2;
+    if settings.flow_control {
+        mcr |= MCR_AFE;
+    }
+
+    PORT.reprogram(|| {
+        write_reg(LCR, LCR_DLAB);
+        write_reg(DLL, div as u8);
+        write_reg(DLM, (div >> 8) as u8);
+        write_reg(LCR, lcr);
+        write_reg(FCR, FCR_ENABLE | FCR_CLEAR_RX | FCR_CLEAR_TX | FCR_TRIGGER_8);
+        write_reg(MCR, mcr);
+    });
+
+    Ok((NS16550_CLOCK_HZ as u64 / (16 * div as u64)) as u32)
+}
+
+/// Returns the current serial line settings.
+pub fn line() -> LineSettings {
+    let (lcr, div) = PORT.with_line(|| {
+        let lcr = read_reg(LCR);
+        write_reg(LCR, lcr | LCR_DLAB);
+        let div = read_reg(DLL) as u64 | (read_reg(DLM) as u64) << 8;
+        write_reg(LCR, lcr);
+        (lcr, div)
+    });
+    let parity = match (lcr & LCR_PEN != 0, lcr & LCR_EPS != 0, lcr & LCR_STICK != 0) {
+        (false, _, _) => Parity::None,
+        (true, false, false) => Parity::Odd,
+        (true, true, false) => Parity::Even,
+        (true, false, true) => Parity::Mark,
+        (true, true, true) => Parity::Space,
+    };
+    LineSettings {
+        baud: (NS16550_CLOCK_HZ as u64).checked_div(16 * div).unwrap_or(0) as u32,
+        data_bits: (lcr & 0x3) + 5,
+        parity,
+        stop_bits: if lcr & LCR_STB != 0 {
+            StopBits::Two
+        } else {
+            StopBits::One
+        },
+        flow_control: read_reg(MCR) & MCR_AFE != 0,
+    }
+}
+
+/// Returns the line settings requested by `console=ttyS0,<options>` or the
+/// `ns16550-baud` config, or [`None`] to keep what the firmware set up.
+fn requested_line() -> Option<LineSettings> {
+    let from_bootargs = crate::bootargs::values("console")
+        .filter_map(|arg| arg.strip_prefix("ttyS0,"))
+        .last()
+        .and_then(LineSettings::parse);
+    from_bootargs.or((NS16550_BAUD != 0).then(|| LineSettings {
+        baud: NS16550_BAUD as u32,
+        ..LineSettings::default()
+    }))
+}
+
+/// Returns whether a UART answers at the configured ports: its scratch
+/// register keeps what is written to it.
+pub(crate) fn probe() -> bool {
+    write_reg(SCR, 0x5a);
+    let first = read_reg(SCR);
+    write_reg(SCR, 0xa5);
+    first == 0x5a && read_reg(SCR) == 0xa5
+}
+
+/// Sets up the UART if the LPC bus has one, and registers its consoles.
+pub(crate) fn init_early() {
+    if !lpc::is_present("ns16550") {
+        return;
+    }
+    write_reg(IER, 0);
+    PORT.set_present();
+    // Keep the firmware settings if they make sense, else fall back to
+    // 115200 8N1.
+    let settings = requested_line().unwrap_or_else(line);
+    if set_line(&settings).is_err() {
+        let _ = set_line(&LineSettings::default());
+    }
+    crate::console::register_sink(&CONSOLE_SINK);
+    crate::console::register_source(&CONSOLE_SOURCE);
+}
+
+/// Enables the UART interrupts if `ns16550-irq` is set.
+#[cfg(feature = "irq")]
+pub(crate) fn init_irq() {
+    use crate::config::devices::NS16550_IRQ;
+    if !PORT.is_present() || NS16550_IRQ == 0 {
+        return;
+    }
+    axplat::irq::register(NS16550_IRQ, irq_handler);
+    PORT.enable_irq(|| write_reg(IER, IER_ERBFI | IER_ELSI));
+}
+
+/// UART IRQ handler. Reading LSR and RBR, and filling or masking the
+/// transmitter, clears every interrupt source.
+#[cfg(feature = "irq")]
+fn irq_handler() {
+    PORT.receive(|_| false);
+    PORT.transmit();
+}
@@ -1,93 +1,495 @@
 //! PL011 UART.
+//!
+//! With the `irq` feature, received bytes are moved by the RX interrupt into a
+//! ring buffer that [`getchar`] consumes, so input arriving while nobody
+//! polls is not lost.
+//!
+//! Output is queued in a TX ring buffer and drained by the TX interrupt, so
+//! writers only wait when the buffer is full. Before the interrupt is set up,
+//! and in synchronous mode (see [`set_synchronous`]), output is written
+//! directly with busy-waiting instead. The buffering is shared with the
+//! 16550 driver, see `crate::uart`.
+//!
+//! With the `serial-mux` feature, the line may carry framed channels instead
+//! of plain text; see [`crate::serial_mux`].
+
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use arm_pl011::Pl011Uart;
 use axplat::mem::VirtAddr;
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
 
+use crate::console::{ConsoleSink, ConsoleSource};
+use crate::config::devices::{UART_BAUD, UART_CLOCK_HZ, UART_RX_ECHO};
+use crate::uart::{BufferedUart, RX_BREAK, RX_FRAMING, RX_OVERRUN, RX_PARITY, UartRegs};
+
+pub use crate::uart::{LineErrors, RxOverruns};
+
+/// Data Register.
+const UARTDR: usize = 0x000;
+/// Receive Status / Error Clear Register.
+const UARTRSR: usize = 0x004;
+/// Flag Register.
+const UARTFR: usize = 0x018;
+/// Integer Baud Rate Register.
+const UARTIBRD: usize = 0x024;
+/// Fractional Baud Rate Register.
+const UARTFBRD: usize = 0x028;
+/// Line Control Register.
+const UARTLCR_H: usize = 0x02c;
+/// Control Register.
+const UARTCR: usize = 0x030;
+/// Interrupt Mask Set/Clear Register.
+const UARTIMSC: usize = 0x038;
+/// Masked Interrupt Status Register.
+#[cfg(feature = "irq")]
+const UARTMIS: usize = 0x040;
+/// Interrupt Clear Register.
+#[cfg(feature = "irq")]
+const UARTICR: usize = 0x044;
+
+/// DR: framing, parity, break and overrun error flags of the received byte.
+const DR_FE: u32 = 1 << 8;
+const DR_PE: u32 = 1 << 9;
+const DR_BE: u32 = 1 << 10;
+const DR_OE: u32 = 1 << 11;
+/// FR: UART busy transmitting.
+const FR_BUSY: u32 = 1 << 3;
+/// FR: receive FIFO empty.
+const FR_RXFE: u32 = 1 << 4;
+/// FR: transmit FIFO full.
+const FR_TXFF: u32 = 1 << 5;
+/// LCR_H bits.
+const LCR_H_PEN: u32 = 1 << 1;
+const LCR_H_EPS: u32 = 1 << 2;
+const LCR_H_STP2: u32 = 1 << 3;
+const LCR_H_FEN: u32 = 1 << 4;
+const LCR_H_WLEN_SHIFT: u32 = 5;
+const LCR_H_SPS: u32 = 1 << 7;
+/// CR bits.
+const CR_UARTEN: u32 = 1 << 0;
+const CR_RTSEN: u32 = 1 << 14;
+const CR_CTSEN: u32 = 1 << 15;
+/// Interrupt bits: receive, transmit and receive timeout.
+#[cfg(feature = "irq")]
+const INT_RX: u32 = 1 << 4;
+const INT_TX: u32 = 1 << 5;
+#[cfg(feature = "irq")]
+const INT_RT: u32 = 1 << 6;
+
 static UART: LazyInit<SpinNoIrq<Pl011Uart>> = LazyInit::new();
+static UART_BASE: AtomicUsize = AtomicUsize::new(0);
+
+static PORT: BufferedUart<Pl011Regs> = BufferedUart::new(Pl011Regs, UART_RX_ECHO);
+
+/// Parity bit setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    /// Parity bit always 1.
+    Mark,
+    /// Parity bit always 0.
+    Space,
+}
 
-fn do_putchar(uart: &mut Pl011Uart, c: u8) {
-    match c {
-        b'\n' => {
-            uart.putchar(b'\r');
-            uart.putchar(b'\n');
+/// Number of stop bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// Serial line settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineSettings {
+    /// Baud rate in bits per second.
+    pub baud: u32,
+    /// Data bits per character, 5 to 8.
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    /// RTS/CTS hardware flow control.
+    pub flow_control: bool,
+}
+
+impl Default for LineSettings {
+    /// 115200 baud, 8N1, no flow control.
+    fn default() -> Self {
+        Self {
+            baud: 115_200,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: false,
         }
-        c => uart.putchar(c),
     }
 }
 
-/// Reads a byte from the console, or returns [`None`] if no input is available.
-pub fn getchar() -> Option<u8> {
-    if let Some(c) = UART.lock().getchar() {
-        return Some(c);
+impl LineSettings {
+    /// Parses Linux-style console options `<baud>[<parity>[<bits>[<flow>]]]`,
+    /// e.g. `115200n8` or `9600e7r`. Parity is one of `n`, `o`, `e`, `m`, `s`;
+    /// `r` enables RTS/CTS flow control.
+    pub fn parse(options: &str) -> Option<Self> {
+        let digits = options.find(|c: char| !c.is_ascii_digit()).unwrap_or(options.len());
+        let (baud, rest) = options.split_at(digits);
+        let mut settings = Self {
+            baud: baud.parse().ok()?,
+            ..Self::default()
+        };
+        let mut rest = rest.bytes();
+        if let Some(p) = rest.next() {
+            settings.parity = match p {
+                b'n' => Parity::None,
+                b'o' => Parity::Odd,
+                b'e' => Parity::Even,
+                b'm' => Parity::Mark,
+                b's' => Parity::Space,
+                _ => return None,
+            };
+        }
+        if let Some(bits) = rest.next() {
+            settings.data_bits = match bits {
+                b'5'..=b'8' => bits - b'0',
+                _ => return None,
+            };
+        }
+        match rest.next() {
+            Some(b'r') => settings.flow_control = true,
+            Some(_) => return None,
+            None => {}
+        }
+        Some(settings)
     }
-    // Try keyboard
-    if let Some(c) = ps2_keyboard::read_byte() {
-        return Some(c);
+}
+
+/// Errors from [`set_line`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineError {
+    /// The baud rate cannot be derived from the UART reference clock.
+    UnsupportedBaud,
+    /// Data bits outside 5 to 8.
+    UnsupportedDataBits,
+}
+
+fn reg(offset: usize) -> *mut u32 {
+    (UART_BASE.load(Ordering::Relaxed) + offset) as *mut u32
+}
+
+fn read_reg(offset: usize) -> u32 {
+    // SAFETY: all offsets used are within the mapped UART frame.
+    unsafe { reg(offset).read_volatile() }
+}
+
+fn write_reg(offset: usize, val: u32) {
+    // SAFETY: see `read_reg`.
+    unsafe { reg(offset).write_volatile(val) }
+}
+
+/// Register access for [`BufferedUart`].
+struct Pl011Regs;
+
+impl UartRegs for Pl011Regs {
+    fn read_rx(&self) -> Option<(u8, u8)> {
+        if read_reg(UARTFR) & FR_RXFE != 0 {
+            return None;
+        }
+        let dr = read_reg(UARTDR);
+        let mut errors = 0;
+        if dr & (DR_FE | DR_PE | DR_BE | DR_OE) != 0 {
+            // Clear the sticky error flags.
+            write_reg(UARTRSR, 0);
+            for (bit, error) in
+                [(DR_OE, RX_OVERRUN), (DR_BE, RX_BREAK), (DR_FE, RX_FRAMING), (DR_PE, RX_PARITY)]
+            {
+                if dr & bit != 0 {
+                    errors |= error;
+                }
+            }
+        }
+        Some((dr as u8, errors))
+    }
+
+    fn tx_room(&self) -> usize {
+        // Only "full" is known, not how much room is left.
+        (read_reg(UARTFR) & FR_TXFF == 0) as usize
+    }
+
+    fn write_tx(&self, c: u8) {
+        write_reg(UARTDR, c as u32);
+    }
+
+    fn set_tx_irq(&self, enabled: bool) {
+        let imsc = read_reg(UARTIMSC);
+        write_reg(UARTIMSC, if enabled { imsc | INT_TX } else { imsc & !INT_TX });
+    }
+
+    fn tx_idle(&self) -> bool {
+        read_reg(UARTFR) & FR_BUSY == 0
     }
-    None
 }
 
-/// Write a slice of bytes to the console.
-/// Also outputs to SimpleFb console if initialized.
+/// Reads a byte from the UART, or returns [`None`] if no input is available.
+///
+/// Bytes already buffered by the RX interrupt come first, then the UART is
+/// polled.
+pub fn getchar() -> Option<u8> {
+    #[cfg(feature = "serial-mux")]
+    if crate::serial_mux::is_enabled() {
+        PORT.with_line(|| {
+            while let Some(c) = PORT.read_rx() {
+                crate::serial_mux::receive(c);
+            }
+        });
+        PORT.run_sysrq();
+        return crate::serial_mux::getchar(crate::serial_mux::CONSOLE);
+    }
+    PORT.getchar()
+}
+
+/// Write a slice of bytes to the UART.
 pub fn write_bytes(bytes: &[u8]) {
-    let mut uart = UART.lock();
-    for c in bytes {
-        do_putchar(&mut uart, *c);
+    #[cfg(feature = "serial-mux")]
+    if crate::serial_mux::is_enabled() {
+        crate::serial_mux::write(crate::serial_mux::CONSOLE, bytes);
+        return;
     }
-    // Also output to SimpleFb console
-    crate::simplefb::write_bytes(bytes);
+    PORT.write(bytes, true);
 }
 
-/// Reads bytes from the console into the given mutable slice.
-/// Returns the number of bytes read.
-pub fn read_bytes(bytes: &mut [u8]) -> usize {
-    let mut read_len = 0;
-    while read_len < bytes.len() {
-        if let Some(c) = getchar() {
-            bytes[read_len] = c;
-        } else {
-            break;
+/// Writes binary data to the UART, without newline translation.
+pub fn write_raw(bytes: &[u8]) {
+    PORT.write(bytes, false);
+}
+
+/// The UART as a console output device.
+pub static CONSOLE_SINK: ConsoleSink = ConsoleSink::new("ttyAMA0", write_bytes);
+/// The UART as a console input device.
+pub static CONSOLE_SOURCE: ConsoleSource = ConsoleSource::new("ttyAMA0", getchar);
+
+/// Switches output to synchronous mode, bypassing the TX interrupt.
+///
+/// Meant for panic and shutdown paths, where interrupts may never be taken
+/// again. Pending bytes are flushed first.
+pub fn set_synchronous(enabled: bool) {
+    PORT.set_synchronous(enabled);
+}
+
+/// Writes bytes directly to the UART, flushing queued output first.
+///
+/// Used by the panic path: the TX buffer lock is broken if its holder does
+/// not release it, and the TX interrupt is turned off for good.
+pub fn emergency_write(bytes: &[u8]) {
+    #[cfg(feature = "serial-mux")]
+    if crate::serial_mux::is_enabled() {
+        if PORT.take_over() && !bytes.is_empty() {
+            crate::serial_mux::emergency_write(crate::serial_mux::CONSOLE, bytes, |c| {
+                PORT.putchar_sync(c)
+            });
         }
-        read_len += 1;
+        return;
+    }
+    PORT.emergency_write(bytes);
+}
+
+/// Waits until all queued bytes have been transmitted.
+pub fn flush() {
+    PORT.flush();
+}
+
+/// Enables or disables echoing received bytes back to the UART.
+pub fn set_echo(enabled: bool) {
+    PORT.set_echo(enabled);
+}
+
+/// Returns whether received bytes are echoed back to the UART.
+pub fn echo_enabled() -> bool {
+    PORT.echo_enabled()
+}
+
+/// Sets the callback invoked from the RX interrupt after new bytes have been
+/// buffered, e.g. to wake up a reader blocked on the console.
+pub fn set_rx_wakeup(callback: Option<fn()>) {
+    PORT.set_rx_wakeup(callback);
+}
+
+/// Returns the receive overrun counters.
+pub fn rx_overruns() -> RxOverruns {
+    PORT.rx_overruns()
+}
+
+/// Returns the line error counters.
+pub fn line_errors() -> LineErrors {
+    PORT.line_errors()
+}
+
+/// Computes the `(IBRD, FBRD)` divisor for `baud` from the reference clock.
+fn baud_divisor(baud: u32) -> Option<(u32, u32)> {
+    if baud == 0 {
+        return None;
+    }
+    // Divisor in 1/64 units: UARTCLK / (16 * baud) * 64, rounded.
+    let div = (UART_CLOCK_HZ as u64 * 4 + baud as u64 / 2) / baud as u64;
+    let (ibrd, fbrd) = ((div >> 6) as u32, (div & 0x3f) as u32);
+    match ibrd {
+        0 => None,
+        1..0xffff => Some((ibrd, fbrd)),
+        0xffff if fbrd == 0 => Some((ibrd, fbrd)),
+        _ => None,
     }
-    read_len
+}
+
+/// Reprograms the serial line.
+///
+/// Queued output is flushed first. Returns the baud rate actually achieved,
+/// which may differ slightly from the requested one.
+pub fn set_line(settings: &LineSettings) -> Result<u32, LineError> {
+    if !(5..=8).contains(&settings.data_bits) {
+        return Err(LineError::UnsupportedDataBits);
+    }
+    let (ibrd, fbrd) = baud_divisor(settings.baud).ok_or(LineError::UnsupportedBaud)?;
+
+    let mut lcr_h = LCR_H_FEN | ((settings.data_bits as u32 - 5) << LCR_H_WLEN_SHIFT);
+    lcr_h |= match settings.parity {
+        Parity::None => 0,
+        Parity::Odd => LCR_H_PEN,
+        Parity::Even => LCR_H_PEN | LCR_H_EPS,
+        Parity::Mark => LCR_H_PEN | LCR_H_SPS,
+        Parity::Space => LCR_H_PEN | LCR_H_EPS | LCR_H_SPS,
+    };
+    if settings.stop_bits == StopBits::Two {
+        lcr_h |= LCR_H_STP2;
+    }
+
+    PORT.reprogram(|| program_line(ibrd, fbrd, lcr_h, settings.flow_control));
+    Ok((UART_CLOCK_HZ as u64 * 4 / ((ibrd as u64) << 6 | fbrd as u64)) as u32)
+}
+
+fn program_line(ibrd: u32, fbrd: u32, lcr_h: u32, flow_control: bool) {
+    let cr = read_reg(UARTCR);
+    write_reg(UARTCR, cr & !CR_UARTEN);
+    while read_reg(UARTFR) & FR_BUSY != 0 {
+        core::hint::spin_loop();
+    }
+    // Disabling the FIFOs flushes them.
+    write_reg(UARTLCR_H, read_reg(UARTLCR_H) & !LCR_H_FEN);
+    write_reg(UARTIBRD, ibrd);
+    write_reg(UARTFBRD, fbrd);
+    // Writing LCR_H latches the divisor too.
+    write_reg(UARTLCR_H, lcr_h);
+    let cr = if flow_control { cr | CR_RTSEN | CR_CTSEN } else { cr & !(CR_RTSEN | CR_CTSEN) };
+    write_reg(UARTCR, cr | CR_UARTEN);
+}
+
+/// Returns the current serial line settings.
+pub fn line() -> LineSettings {
+    let lcr_h = read_reg(UARTLCR_H);
+    let div = (read_reg(UARTIBRD) as u64) << 6 | read_reg(UARTFBRD) as u64;
+    let parity = match (lcr_h & LCR_H_PEN != 0, lcr_h & LCR_H_EPS != 0, lcr_h & LCR_H_SPS != 0) {
+        (false, _, _) => Parity::None,
+        (true, false, false) => Parity::Odd,
+        (true, true, false) => Parity::Even,
+        (true, false, true) => Parity::Mark,
+        (true, true, true) => Parity::Space,
+    };
+    LineSettings {
+        baud: (UART_CLOCK_HZ as u64 * 4).checked_div(div).unwrap_or(0) as u32,
+        data_bits: ((lcr_h >> LCR_H_WLEN_SHIFT) & 0x3) as u8 + 5,
+        parity,
+        stop_bits: if lcr_h & LCR_H_STP2 != 0 {
+            StopBits::Two
+        } else {
+            StopBits::One
+        },
+        flow_control: read_reg(UARTCR) & CR_CTSEN != 0,
+    }
+}
+
+/// Returns the line settings requested by `console=ttyAMA0,<options>` or the
+/// `uart-baud` config, or [`None`] to keep what the firmware set up.
+fn requested_line() -> Option<LineSettings> {
+    let from_bootargs = crate::bootargs::values("console")
+        .filter_map(|arg| arg.strip_prefix("ttyAMA0,"))
+        .last()
+        .and_then(LineSettings::parse);
+    from_bootargs.or((UART_BAUD != 0).then(|| LineSettings {
+        baud: UART_BAUD as u32,
+        ..LineSettings::default()
+    }))
 }
 
 /// Early stage initialization of the PL011 UART driver.
-pub fn init_early(uart_base: VirtAddr) {
+pub(crate) fn init_early(uart_base: VirtAddr) {
+    UART_BASE.store(uart_base.as_usize(), Ordering::Relaxed);
+    // Let the early console output drain before the UART gets reset.
+    while read_reg(UARTFR) & FR_BUSY != 0 {
+        core::hint::spin_loop();
+    }
     UART.init_once(SpinNoIrq::new(Pl011Uart::new(uart_base.as_mut_ptr())));
     UART.lock().init();
+    PORT.set_present();
+    if let Some(settings) = requested_line() {
+        // Nothing can be printed if this goes wrong, the line is garbled.
+        let _ = set_line(&settings);
+    }
+}
+
+/// Enables the RX interrupts and the TX interrupt path once [`irq_handler`]
+/// is registered.
+///
+/// The receive timeout interrupt covers input that stays below the RX FIFO
+/// trigger level, such as a single typed byte.
+#[cfg(feature = "irq")]
+pub(crate) fn init_irq() {
+    PORT.enable_irq(|| write_reg(UARTIMSC, read_reg(UARTIMSC) | INT_RX | INT_RT));
 }
 
 /// UART IRQ Handler
 #[cfg(feature = "irq")]
-pub fn irq_handler() {
-    let is_receive_interrupt = UART.lock().is_receive_interrupt();
-    UART.lock().ack_interrupts();
-    if is_receive_interrupt {
-        while let Some(c) = getchar() {
-            do_putchar(&mut UART.lock(), c);
-        }
+pub(crate) fn irq_handler() {
+    let status = read_reg(UARTMIS);
+    write_reg(UARTICR, status);
+    if status & (INT_RX | INT_RT) != 0 {
+        receive();
+    }
+    if status & INT_TX != 0 {
+        PORT.transmit();
     }
 }
 
-/// Default implementation of [`axplat::console::ConsoleIf`] using the
-/// PL011 UART.
-struct ConsoleIfImpl;
-
-#[axplat::impl_plat_interface]
-impl axplat::console::ConsoleIf for ConsoleIfImpl {
-    /// Writes given bytes to the console.
-    fn write_bytes(bytes: &[u8]) {
-        write_bytes(bytes);
+/// Moves the bytes in the RX FIFO into the RX ring buffer, or into the
+/// channel buffers when the line is multiplexed.
+#[cfg(feature = "irq")]
+fn receive() {
+    #[cfg(feature = "serial-mux")]
+    if crate::serial_mux::is_enabled() {
+        let received = PORT.with_line(|| {
+            let mut received = false;
+            while let Some(c) = PORT.read_rx() {
+                received |= crate::serial_mux::receive(c);
+            }
+            received
+        });
+        PORT.run_sysrq();
+        if received {
+            PORT.wake_reader();
+        }
+        return;
     }
-
-    /// Reads bytes from the console into the given mutable slice.
-    ///
-    /// Returns the number of bytes read.
-    fn read_bytes(bytes: &mut [u8]) -> usize {
-        read_bytes(bytes)
+    #[cfg(feature = "gdbstub")]
+    let mut break_in = false;
+    PORT.receive(|_c| {
+        #[cfg(feature = "gdbstub")]
+        if _c == 0x03 && crate::gdbstub::on_console() {
+            break_in = true;
+            return true;
+        }
+        false
+    });
+    #[cfg(feature = "gdbstub")]
+    if break_in {
+        crate::gdbstub::break_in();
     }
 }
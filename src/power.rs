@@ -1,7 +1,34 @@
 use axplat::power::PowerIf;
 
+use crate::config::plat::PSCI_METHOD;
+
+/// PSCI `SYSTEM_RESET` function ID.
+const PSCI_SYSTEM_RESET: u32 = 0x8400_0009;
+
 struct PowerImpl;
 
+/// Resets the whole system immediately, through PSCI `SYSTEM_RESET`.
+pub(crate) fn system_reset() -> ! {
+    // SAFETY: the call does not return on success.
+    unsafe {
+        match PSCI_METHOD {
+            "smc" => core::arch::asm!("smc #0", inout("x0") PSCI_SYSTEM_RESET as usize => _),
+            _ => core::arch::asm!("hvc #0", inout("x0") PSCI_SYSTEM_RESET as usize => _),
+        }
+    }
+    log::error!("PSCI SYSTEM_RESET failed");
+    loop {
+        aarch64_cpu::asm::wfi();
+    }
+}
+
+/// Flushes the UARTs and powers the system off.
+pub(crate) fn system_off() -> ! {
+    crate::pl011::flush();
+    crate::ns16550::flush();
+    axplat_aarch64_peripherals::psci::system_off()
+}
+
 #[impl_plat_interface]
 impl PowerIf for PowerImpl {
     /// Bootstraps the given CPU core with the given initial stack (in physical
@@ -18,7 +45,7 @@ impl PowerIf for PowerImpl {
 
     /// Shutdown the whole system.
     fn system_off() -> ! {
-        axplat_aarch64_peripherals::psci::system_off()
+        crate::power::system_off()
     }
 
     /// Get the number of CPU cores available on this platform.
@@ -0,0 +1,217 @@
+//! i8042 controller commands and bring-up.
+//!
+//! Every wait for the controller is bounded by a timeout, so a missing or
+//! wedged controller cannot hang the caller.
+
+use axplat::time::{NANOS_PER_MILLIS, monotonic_time_nanos};
+use log::{debug, warn};
+
+use super::{DATA_PORT, STATUS_IBF, STATUS_OBF, STATUS_PORT};
+use crate::lpc::{inb, outb};
+
+/// Controller commands.
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_AUX: u8 = 0xa7;
+const CMD_ENABLE_AUX: u8 = 0xa8;
+const CMD_TEST_AUX: u8 = 0xa9;
+const CMD_SELF_TEST: u8 = 0xaa;
+const CMD_TEST_KBD: u8 = 0xab;
+const CMD_DISABLE_KBD: u8 = 0xad;
+const CMD_ENABLE_KBD: u8 = 0xae;
+/// Sends the next data byte to the aux device instead of the keyboard.
+const CMD_WRITE_AUX: u8 = 0xd4;
+
+/// Reply to a successful [`CMD_SELF_TEST`].
+const SELF_TEST_OK: u8 = 0x55;
+/// Reply to a successful port test.
+const PORT_TEST_OK: u8 = 0x00;
+
+/// Configuration byte bits.
+pub(super) const CONFIG_KBD_INT: u8 = 1 << 0;
+pub(super) const CONFIG_AUX_INT: u8 = 1 << 1;
+const CONFIG_KBD_CLOCK_OFF: u8 = 1 << 4;
+const CONFIG_AUX_CLOCK_OFF: u8 = 1 << 5;
+/// Translate keyboard scancodes to set 1.
+const CONFIG_TRANSLATE: u8 = 1 << 6;
+
+/// Device commands and replies.
+const DEV_RESET: u8 = 0xff;
+const DEV_ACK: u8 = 0xfa;
+const DEV_SELF_TEST_OK: u8 = 0xaa;
+
+/// Timeout of a controller command or a device reply.
+const COMMAND_TIMEOUT_MS: u64 = 20;
+/// Timeout of a device self test after reset.
+const RESET_TIMEOUT_MS: u64 = 1000;
+
+/// An i8042 error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The controller did not take a write or did not reply in time.
+    Timeout,
+    /// The controller self test failed, with the reply.
+    SelfTest(u8),
+    /// The keyboard port test failed, with the reply.
+    PortTest(u8),
+    /// A device answered a command with something else than `ACK`.
+    NoAck(u8),
+}
+
+/// The ports found working by [`init`].
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Ports {
+    pub aux: bool,
+}
+
+fn wait_for(status_mask: u8, set: bool, timeout_ms: u64) -> Result<(), Error> {
+    let deadline = monotonic_time_nanos() + timeout_ms * NANOS_PER_MILLIS;
+    loop {
+        if (inb(STATUS_PORT) & status_mask != 0) == set {
+            return Ok(());
+        }
+        if monotonic_time_nanos() >= deadline {
+            return Err(Error::Timeout);
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Writes a byte to a port once the controller can take it.
+fn write(port: u16, val: u8) -> Result<(), Error> {
+    wait_for(STATUS_IBF, false, COMMAND_TIMEOUT_MS)?;
+    outb(port, val);
+    Ok(())
+}
+
+/// Reads the next byte from the output buffer.
+fn read(timeout_ms: u64) -> Result<u8, Error> {
+    wait_for(STATUS_OBF, true, timeout_ms)?;
+    Ok(inb(DATA_PORT))
+}
+
+/// Discards whatever waits in the output buffer.
+fn flush() {
+    for _ in 0..64 {
+        if inb(STATUS_PORT) & STATUS_OBF == 0 {
+            return;
+        }
+        inb(DATA_PORT);
+    }
+}
+
+fn command(cmd: u8) -> Result<(), Error> {
+    write(STATUS_PORT, cmd)
+}
+
+fn command_read(cmd: u8) -> Result<u8, Error> {
+    command(cmd)?;
+    read(COMMAND_TIMEOUT_MS)
+}
+
+fn command_write(cmd: u8, val: u8) -> Result<(), Error> {
+    command(cmd)?;
+    write(DATA_PORT, val)
+}
+
+/// Sends a byte to the keyboard.
+pub(super) fn send_keyboard(val: u8) -> Result<(), Error> {
+    write(DATA_PORT, val)
+}
+
+/// Sends a byte to the aux device.
+pub(super) fn send_aux(val: u8) -> Result<(), Error> {
+    command(CMD_WRITE_AUX)?;
+    write(DATA_PORT, val)
+}
+
+/// Resets a device and waits for its self test to pass.
+fn reset_device(send: fn(u8) -> Result<(), Error>) -> Result<(), Error> {
+    send(DEV_RESET)?;
+    match read(COMMAND_TIMEOUT_MS)? {
+        DEV_ACK => {}
+        reply => return Err(Error::NoAck(reply)),
+    }
+    match read(RESET_TIMEOUT_MS)? {
+        DEV_SELF_TEST_OK => {}
+        reply => return Err(Error::NoAck(reply)),
+    }
+    // A mouse follows with its device ID.
+    let _ = read(COMMAND_TIMEOUT_MS);
+    Ok(())
+}
+
+/// Brings the controller and its devices up from whatever state they are in.
+///
+/// Both ports are disabled while the controller is tested, then enabled with
+/// the interrupts in `interrupts` (`CONFIG_*_INT` bits). Scancode translation
+/// to set 1 is turned on. The aux port is left off if it does not work.
+pub(super) fn init(interrupts: u8) -> Result<Ports, Error> {
+    command(CMD_DISABLE_KBD)?;
+    command(CMD_DISABLE_AUX)?;
+    flush();
+
+    let mut config = command_read(CMD_READ_CONFIG)?;
+    config &= !(CONFIG_KBD_INT | CONFIG_AUX_INT);
+    config |= CONFIG_TRANSLATE;
+    command_write(CMD_WRITE_CONFIG, config)?;
+
+    match command_read(CMD_SELF_TEST)? {
+        SELF_TEST_OK => {}
+        reply => return Err(Error::SelfTest(reply)),
+    }
+    // Some controllers reset the configuration during the self test.
+    command_write(CMD_WRITE_CONFIG, config)?;
+
+    // A single-port controller does not clear the aux clock bit.
+    command(CMD_ENABLE_AUX)?;
+    let mut aux = command_read(CMD_READ_CONFIG)? & CONFIG_AUX_CLOCK_OFF == 0;
+    command(CMD_DISABLE_AUX)?;
+
+    match command_read(CMD_TEST_KBD)? {
+        PORT_TEST_OK => {}
+        reply => return Err(Error::PortTest(reply)),
+    }
+    if aux {
+        aux = command_read(CMD_TEST_AUX)? == PORT_TEST_OK;
+    }
+
+    command(CMD_ENABLE_KBD)?;
+    config &= !CONFIG_KBD_CLOCK_OFF;
+    if aux {
+        command(CMD_ENABLE_AUX)?;
+        config &= !CONFIG_AUX_CLOCK_OFF;
+    }
+    command_write(CMD_WRITE_CONFIG, config)?;
+
+    if let Err(e) = reset_device(send_keyboard) {
+        warn!("PS/2: keyboard reset failed: {:?}", e);
+    }
+    if aux && let Err(e) = reset_device(send_aux) {
+        debug!("PS/2: no aux device: {:?}", e);
+        command(CMD_DISABLE_AUX)?;
+        config |= CONFIG_AUX_CLOCK_OFF;
+        aux = false;
+    }
+    flush();
+
+    set_interrupts(config, interrupts, aux)?;
+    Ok(Ports { aux })
+}
+
+/// Enables the interrupts in `interrupts`, leaving out the aux one if the
+/// aux port is off.
+fn set_interrupts(config: u8, interrupts: u8, aux: bool) -> Result<(), Error> {
+    let mut mask = interrupts & (CONFIG_KBD_INT | CONFIG_AUX_INT);
+    if !aux {
+        mask &= !CONFIG_AUX_INT;
+    }
+    command_write(CMD_WRITE_CONFIG, (config & !(CONFIG_KBD_INT | CONFIG_AUX_INT)) | mask)
+}
+
+/// Changes the enabled interrupts.
+#[cfg(feature = "irq")]
+pub(super) fn enable_interrupts(interrupts: u8, aux: bool) -> Result<(), Error> {
+    let config = command_read(CMD_READ_CONFIG)?;
+    set_interrupts(config, interrupts, aux)
+}
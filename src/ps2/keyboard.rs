@@ -0,0 +1,575 @@
+//! Key events decoded from scancode set 1, and their translation into
+//! console input.
+//!
+//! Every key press and release becomes a [`KeyEvent`], queued for
+//! [`read_event`]. Presses are also translated into bytes for the console:
+//! characters of the current [`Keymap`] (UTF-8 encoded), control characters
+//! with Ctrl, an `ESC` prefix with Alt, and xterm-style escape sequences for
+//! the cursor, editing and function keys. The Caps, Num and Scroll Lock
+//! states are mirrored on the keyboard LEDs. Hotkeys go to
+//! [`crate::hotkey`] instead of the console.
+
+use kspin::SpinNoIrq;
+use log::warn;
+
+use super::keymap::{self, Keymap};
+use crate::config::devices::PS2_SCANCODE_BUF_SIZE;
+use crate::ringbuf::RingBuffer;
+
+/// Prefix of extended scancodes.
+const EXTENDED: u8 = 0xe0;
+/// Prefix of the Pause key sequence, followed by five more bytes.
+const PAUSE_PREFIX: u8 = 0xe1;
+/// Set in break (key release) codes.
+const BREAK: u8 = 0x80;
+
+/// Keyboard replies.
+const REPLY_ACK: u8 = 0xfa;
+const REPLY_RESEND: u8 = 0xfe;
+const REPLY_ERROR: [u8; 2] = [0x00, 0xff];
+
+/// Keyboard command setting the LEDs, followed by the LED mask.
+const CMD_SET_LEDS: u8 = 0xed;
+const LED_SCROLL_LOCK: u8 = 1 << 0;
+const LED_NUM_LOCK: u8 = 1 << 1;
+const LED_CAPS_LOCK: u8 = 1 << 2;
+
+/// Number of key events kept for [`read_event`].
+const EVENT_BUF_SIZE: usize = 64;
+/// Room for console input decoded before anybody reads it, e.g. by the
+/// interrupt handler looking for hotkeys.
+const CHAR_BUF_SIZE: usize = PS2_SCANCODE_BUF_SIZE;
+
+/// A key, identified by its set 1 make code, with bit 7 set for extended
+/// (`E0`-prefixed) keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyCode(pub u8);
+
+impl KeyCode {
+    pub const ESC: Self = Self(0x01);
+    pub const BACKSPACE: Self = Self(0x0e);
+    pub const TAB: Self = Self(0x0f);
+    pub const ENTER: Self = Self(0x1c);
+    pub const LEFT_CTRL: Self = Self(0x1d);
+    pub const LEFT_SHIFT: Self = Self(0x2a);
+    pub const RIGHT_SHIFT: Self = Self(0x36);
+    pub const KP_ASTERISK: Self = Self(0x37);
+    pub const LEFT_ALT: Self = Self(0x38);
+    pub const SPACE: Self = Self(0x39);
+    pub const CAPS_LOCK: Self = Self(0x3a);
+    pub const F1: Self = Self(0x3b);
+    pub const F2: Self = Self(0x3c);
+    pub const F3: Self = Self(0x3d);
+    pub const F4: Self = Self(0x3e);
+    pub const F5: Self = Self(0x3f);
+    pub const F6: Self = Self(0x40);
+    pub const F7: Self = Self(0x41);
+    pub const F8: Self = Self(0x42);
+    pub const F9: Self = Self(0x43);
+    pub const F10: Self = Self(0x44);
+    pub const NUM_LOCK: Self = Self(0x45);
+    pub const SCROLL_LOCK: Self = Self(0x46);
+    pub const KP_7: Self = Self(0x47);
+    pub const KP_8: Self = Self(0x48);
+    pub const KP_9: Self = Self(0x49);
+    pub const KP_MINUS: Self = Self(0x4a);
+    pub const KP_4: Self = Self(0x4b);
+    pub const KP_5: Self = Self(0x4c);
+    pub const KP_6: Self = Self(0x4d);
+    pub const KP_PLUS: Self = Self(0x4e);
+    pub const KP_1: Self = Self(0x4f);
+    pub const KP_2: Self = Self(0x50);
+    pub const KP_3: Self = Self(0x51);
+    pub const KP_0: Self = Self(0x52);
+    pub const KP_DOT: Self = Self(0x53);
+    pub const ISO: Self = Self(0x56);
+    pub const F11: Self = Self(0x57);
+    pub const F12: Self = Self(0x58);
+    pub const KP_ENTER: Self = Self(0x9c);
+    pub const RIGHT_CTRL: Self = Self(0x9d);
+    pub const MUTE: Self = Self(0xa0);
+    pub const VOLUME_DOWN: Self = Self(0xae);
+    pub const VOLUME_UP: Self = Self(0xb0);
+    pub const KP_SLASH: Self = Self(0xb5);
+    pub const PRINT_SCREEN: Self = Self(0xb7);
+    /// Right Alt, or AltGr on layouts that have one.
+    pub const RIGHT_ALT: Self = Self(0xb8);
+    /// Pause, which only has a press event.
+    pub const PAUSE: Self = Self(0xc5);
+    pub const HOME: Self = Self(0xc7);
+    pub const UP: Self = Self(0xc8);
+    pub const PAGE_UP: Self = Self(0xc9);
+    pub const LEFT: Self = Self(0xcb);
+    pub const RIGHT: Self = Self(0xcd);
+    pub const END: Self = Self(0xcf);
+    pub const DOWN: Self = Self(0xd0);
+    pub const PAGE_DOWN: Self = Self(0xd1);
+    pub const INSERT: Self = Self(0xd2);
+    pub const DELETE: Self = Self(0xd3);
+    pub const LEFT_META: Self = Self(0xdb);
+    pub const RIGHT_META: Self = Self(0xdc);
+    pub const MENU: Self = Self(0xdd);
+    /// ACPI power, sleep and wake keys.
+    pub const POWER: Self = Self(0xde);
+    pub const SLEEP: Self = Self(0xdf);
+    pub const WAKE: Self = Self(0xe3);
+
+    /// Returns whether the key sends `E0`-prefixed scancodes.
+    pub const fn is_extended(self) -> bool {
+        self.0 & 0x80 != 0
+    }
+}
+
+/// Modifier keys held and lock states.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const SHIFT: Self = Self(1 << 0);
+    pub const CTRL: Self = Self(1 << 1);
+    /// Left Alt, or right Alt on layouts without AltGr characters.
+    pub const ALT: Self = Self(1 << 2);
+    /// Right Alt.
+    pub const ALTGR: Self = Self(1 << 3);
+    pub const META: Self = Self(1 << 4);
+    pub const CAPS_LOCK: Self = Self(1 << 5);
+    pub const NUM_LOCK: Self = Self(1 << 6);
+    pub const SCROLL_LOCK: Self = Self(1 << 7);
+
+    /// Returns whether all modifiers in `other` are set.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the raw bits.
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+}
+
+/// A key press or release.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub pressed: bool,
+    /// Whether this press is an autorepeat of a key already held.
+    pub repeat: bool,
+    /// Modifier state after the event.
+    pub modifiers: Modifiers,
+}
+
+struct Keyboard {
+    extended: bool,
+    /// Bytes of the Pause sequence still to skip.
+    pause_skip: u8,
+    /// Keys held down, one bit per keycode.
+    held: [u64; 4],
+    /// Lock states, as [`Modifiers`] bits.
+    locks: u8,
+    /// LED mask to send once the keyboard acknowledges [`CMD_SET_LEDS`].
+    led_pending: Option<u8>,
+    keymap: &'static Keymap,
+    chars: RingBuffer<u8, CHAR_BUF_SIZE>,
+    /// Controller resets seen, see [`super::resets`].
+    resets: usize,
+}
+
+static KEYBOARD: SpinNoIrq<Keyboard> = SpinNoIrq::new(Keyboard {
+    extended: false,
+    pause_skip: 0,
+    held: [0; 4],
+    locks: 0,
+    led_pending: None,
+    keymap: &keymap::US,
+    chars: RingBuffer::new(),
+    resets: 0,
+});
+static EVENTS: SpinNoIrq<RingBuffer<KeyEvent, EVENT_BUF_SIZE>> = SpinNoIrq::new(RingBuffer::new());
+
+impl Keyboard {
+    /// Forgets the decoding state if the controller was reinitialized, and
+    /// restores the LEDs.
+    fn check_resets(&mut self) {
+        let resets = super::resets();
+        if self.resets != resets {
+            self.resets = resets;
+            self.reset();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.extended = false;
+        self.pause_skip = 0;
+        self.held = [0; 4];
+        self.led_pending = None;
+        self.chars.clear();
+        self.update_leds();
+    }
+
+    fn is_held(&self, code: KeyCode) -> bool {
+        self.held[code.0 as usize / 64] & (1 << (code.0 % 64)) != 0
+    }
+
+    fn set_held(&mut self, code: KeyCode, held: bool) {
+        let word = &mut self.held[code.0 as usize / 64];
+        if held {
+            *word |= 1 << (code.0 % 64);
+        } else {
+            *word &= !(1 << (code.0 % 64));
+        }
+    }
+
+    fn modifiers(&self) -> Modifiers {
+        let mut bits = self.locks;
+        let mut set = |keys: &[KeyCode], modifier: Modifiers| {
+            if keys.iter().any(|&k| self.is_held(k)) {
+                bits |= modifier.0;
+            }
+        };
+        set(&[KeyCode::LEFT_SHIFT, KeyCode::RIGHT_SHIFT], Modifiers::SHIFT);
+        set(&[KeyCode::LEFT_CTRL, KeyCode::RIGHT_CTRL], Modifiers::CTRL);
+        set(&[KeyCode::LEFT_ALT], Modifiers::ALT);
+        set(&[KeyCode::RIGHT_ALT], Modifiers::ALTGR);
+        set(&[KeyCode::LEFT_META, KeyCode::RIGHT_META], Modifiers::META);
+        Modifiers(bits)
+    }
+
+    /// Feeds one byte received from the keyboard.
+    fn feed(&mut self, code: u8) {
+        match code {
+            REPLY_ACK => {
+                if let Some(mask) = self.led_pending.take() {
+                    super::send_data(mask);
+                }
+                return;
+            }
+            REPLY_RESEND => return,
+            c if REPLY_ERROR.contains(&c) => return,
+            _ => {}
+        }
+        if self.pause_skip > 0 {
+            self.pause_skip -= 1;
+            if self.pause_skip == 0 {
+                self.key(KeyCode::PAUSE, true);
+            }
+            return;
+        }
+        match code {
+            PAUSE_PREFIX => self.pause_skip = 5,
+            EXTENDED => self.extended = true,
+            _ => {
+                let extended = core::mem::take(&mut self.extended);
+                let make = code & !BREAK;
+                // Print Screen and the cursor keys wrap themselves in fake
+                // Shift presses and releases.
+                if extended && (make == KeyCode::LEFT_SHIFT.0 || make == KeyCode::RIGHT_SHIFT.0) {
+                    return;
+                }
+                let key = KeyCode(make | if extended { 0x80 } else { 0 });
+                self.key(key, code & BREAK == 0);
+            }
+        }
+    }
+
+    fn key(&mut self, code: KeyCode, pressed: bool) {
+        let repeat = pressed && self.is_held(code);
+        if code != KeyCode::PAUSE {
+            self.set_held(code, pressed);
+        }
+        if pressed && !repeat {
+            let lock = match code {
+                KeyCode::CAPS_LOCK => Modifiers::CAPS_LOCK,
+                KeyCode::NUM_LOCK => Modifiers::NUM_LOCK,
+                KeyCode::SCROLL_LOCK => Modifiers::SCROLL_LOCK,
+                _ => Modifiers(0),
+            };
+            if lock.0 != 0 {
+                self.locks ^= lock.0;
+                self.update_leds();
+            }
+        }
+        let event = KeyEvent {
+            code,
+            pressed,
+            repeat,
+            modifiers: self.modifiers(),
+        };
+        EVENTS.lock().push_overwrite(event);
+        if crate::hotkey::key(code, pressed, repeat) {
+            return;
+        }
+        if pressed {
+            self.translate(event);
+        }
+    }
+
+    fn update_leds(&mut self) {
+        let locks = Modifiers(self.locks);
+        let mut mask = 0;
+        if locks.contains(Modifiers::SCROLL_LOCK) {
+            mask |= LED_SCROLL_LOCK;
+        }
+        if locks.contains(Modifiers::NUM_LOCK) {
+            mask |= LED_NUM_LOCK;
+        }
+        if locks.contains(Modifiers::CAPS_LOCK) {
+            mask |= LED_CAPS_LOCK;
+        }
+        if super::send_data(CMD_SET_LEDS) {
+            self.led_pending = Some(mask);
+        }
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        #[cfg(feature = "sysrq")]
+        if let [c] = *bytes
+            && crate::sysrq::filter_keyboard(c)
+        {
+            return;
+        }
+        if self.chars.free() >= bytes.len() {
+            bytes.iter().for_each(|&b| {
+                self.chars.push(b);
+            });
+        }
+    }
+
+    /// Pushes a CSI sequence with the xterm modifier parameter, e.g.
+    /// `ESC [ 1 ; 5 A` for Ctrl-Up.
+    fn push_csi(&mut self, number: u8, last: u8, modifiers: Modifiers) {
+        let mut param = 1;
+        if modifiers.contains(Modifiers::SHIFT) {
+            param += 1;
+        }
+        if modifiers.contains(Modifiers::ALT) {
+            param += 2;
+        }
+        if modifiers.contains(Modifiers::CTRL) {
+            param += 4;
+        }
+        let mut buf = [0; 8];
+        let mut len = 0;
+        let mut put = |b: u8| {
+            buf[len] = b;
+            len += 1;
+        };
+        put(0x1b);
+        put(b'[');
+        if number != 1 || param != 1 {
+            if number >= 10 {
+                put(b'0' + number / 10);
+            }
+            put(b'0' + number % 10);
+        }
+        if param != 1 {
+            put(b';');
+            put(b'0' + param);
+        }
+        put(last);
+        self.push_bytes(&buf[..len]);
+    }
+
+    fn translate(&mut self, event: KeyEvent) {
+        let mods = event.modifiers;
+        let num_lock = mods.contains(Modifiers::NUM_LOCK) && !mods.contains(Modifiers::SHIFT);
+        // Sequences of the cursor, editing and function keys: `ESC [ <n> ~`,
+        // or `ESC [ <letter>` when the number is 1.
+        let csi = match event.code {
+            KeyCode::UP => Some((1, b'A')),
+            KeyCode::DOWN => Some((1, b'B')),
+            KeyCode::RIGHT => Some((1, b'C')),
+            KeyCode::LEFT => Some((1, b'D')),
+            KeyCode::HOME => Some((1, b'H')),
+            KeyCode::END => Some((1, b'F')),
+            KeyCode::INSERT => Some((2, b'~')),
+            KeyCode::DELETE => Some((3, b'~')),
+            KeyCode::PAGE_UP => Some((5, b'~')),
+            KeyCode::PAGE_DOWN => Some((6, b'~')),
+            KeyCode::F1 => Some((1, b'P')),
+            KeyCode::F2 => Some((1, b'Q')),
+            KeyCode::F3 => Some((1, b'R')),
+            KeyCode::F4 => Some((1, b'S')),
+            KeyCode::F5 => Some((15, b'~')),
+            KeyCode::F6 => Some((17, b'~')),
+            KeyCode::F7 => Some((18, b'~')),
+            KeyCode::F8 => Some((19, b'~')),
+            KeyCode::F9 => Some((20, b'~')),
+            KeyCode::F10 => Some((21, b'~')),
+            KeyCode::F11 => Some((23, b'~')),
+            KeyCode::F12 => Some((24, b'~')),
+            KeyCode::KP_7 if !num_lock => Some((1, b'H')),
+            KeyCode::KP_8 if !num_lock => Some((1, b'A')),
+            KeyCode::KP_9 if !num_lock => Some((5, b'~')),
+            KeyCode::KP_4 if !num_lock => Some((1, b'D')),
+            KeyCode::KP_6 if !num_lock => Some((1, b'C')),
+            KeyCode::KP_1 if !num_lock => Some((1, b'F')),
+            KeyCode::KP_2 if !num_lock => Some((1, b'B')),
+            KeyCode::KP_3 if !num_lock => Some((6, b'~')),
+            KeyCode::KP_0 if !num_lock => Some((2, b'~')),
+            KeyCode::KP_DOT if !num_lock => Some((3, b'~')),
+            _ => None,
+        };
+        if let Some((number, last)) = csi {
+            if (KeyCode::F1.0..=KeyCode::F4.0).contains(&event.code.0) && mods.bits() & 0x7 == 0 {
+                // F1-F4 without modifiers are `ESC O <letter>`.
+                self.push_bytes(&[0x1b, b'O', last]);
+            } else {
+                self.push_csi(number, last, mods);
+            }
+            return;
+        }
+        let c = match event.code {
+            KeyCode::KP_7 => '7',
+            KeyCode::KP_8 => '8',
+            KeyCode::KP_9 => '9',
+            KeyCode::KP_4 => '4',
+            KeyCode::KP_5 => '5',
+            KeyCode::KP_6 => '6',
+            KeyCode::KP_1 => '1',
+            KeyCode::KP_2 => '2',
+            KeyCode::KP_3 => '3',
+            KeyCode::KP_0 => '0',
+            KeyCode::KP_DOT => '.',
+            KeyCode::KP_MINUS => '-',
+            KeyCode::KP_PLUS => '+',
+            KeyCode::KP_SLASH => '/',
+            KeyCode::KP_ENTER => '\r',
+            code if code.is_extended() => return,
+            code => {
+                let Some(chars) = self.keymap.chars(code.0) else {
+                    return;
+                };
+                self.main_char(chars, mods)
+            }
+        };
+        if c == '\0' {
+            return;
+        }
+        let mut buf = [0; 5];
+        let mut len = 0;
+        // Alt sends an ESC prefix, so does right Alt when the layout has no
+        // character for it.
+        let altgr = mods.contains(Modifiers::ALTGR) && self.altgr_applies(event.code);
+        if mods.contains(Modifiers::ALT) || (mods.contains(Modifiers::ALTGR) && !altgr) {
+            buf[0] = 0x1b;
+            len = 1;
+        }
+        let c = if mods.contains(Modifiers::CTRL) && !altgr {
+            ctrl_char(c)
+        } else {
+            c
+        };
+        len += c.encode_utf8(&mut buf[len..]).len();
+        self.push_bytes(&buf[..len]);
+    }
+
+    fn altgr_applies(&self, code: KeyCode) -> bool {
+        self.keymap.chars(code.0).is_some_and(|chars| chars[2] != '\0')
+    }
+
+    /// Picks the character of a main block key.
+    fn main_char(&self, [plain, shifted, altgr]: keymap::KeyChars, mods: Modifiers) -> char {
+        if mods.contains(Modifiers::ALTGR) && altgr != '\0' {
+            return altgr;
+        }
+        let mut shift = mods.contains(Modifiers::SHIFT);
+        if mods.contains(Modifiers::CAPS_LOCK) && plain.is_alphabetic() {
+            shift = !shift;
+        }
+        if shift { shifted } else { plain }
+    }
+}
+
+/// Returns the control character typed with Ctrl and `c`.
+fn ctrl_char(c: char) -> char {
+    match c {
+        '?' => '\x7f',
+        ' ' | '2' => '\0',
+        c if ('@'..='_').contains(&c.to_ascii_uppercase()) => {
+            (c.to_ascii_uppercase() as u8 & 0x1f) as char
+        }
+        c => c,
+    }
+}
+
+/// Reinitializes the controller if needed, then decodes the scancodes
+/// received so far.
+///
+/// Not for interrupt context, as the reset waits for the devices.
+pub(crate) fn process() {
+    super::recover();
+    decode();
+}
+
+/// Decodes the scancodes received so far, turning them into key events,
+/// hotkeys, SysRq commands and console input.
+pub(super) fn decode() {
+    {
+        let mut keyboard = KEYBOARD.lock();
+        keyboard.check_resets();
+        while let Some(code) = super::read_scancode() {
+            keyboard.feed(code);
+        }
+    }
+    #[cfg(feature = "sysrq")]
+    crate::sysrq::run_keyboard();
+}
+
+/// Reads the next key event, or returns [`None`] if there is none.
+///
+/// Events are kept whether or not anybody reads them; the oldest ones are
+/// dropped when the queue is full.
+pub fn read_event() -> Option<KeyEvent> {
+    process();
+    crate::hotkey::dispatch();
+    EVENTS.lock().pop()
+}
+
+/// Reads a byte of console input, or returns [`None`] if there is none.
+pub(super) fn read_byte() -> Option<u8> {
+    super::recover();
+    let c = {
+        let mut keyboard = KEYBOARD.lock();
+        keyboard.check_resets();
+        loop {
+            if let Some(c) = keyboard.chars.pop() {
+                break Some(c);
+            }
+            let Some(code) = super::read_scancode() else {
+                break None;
+            };
+            keyboard.feed(code);
+        }
+    };
+    #[cfg(feature = "sysrq")]
+    crate::sysrq::run_keyboard();
+    crate::hotkey::dispatch();
+    c
+}
+
+/// Returns the current modifier and lock state.
+pub fn modifiers() -> Modifiers {
+    KEYBOARD.lock().modifiers()
+}
+
+/// Returns the layout in use.
+pub fn keymap() -> &'static Keymap {
+    KEYBOARD.lock().keymap
+}
+
+/// Switches to another layout.
+pub fn set_keymap(keymap: &'static Keymap) {
+    KEYBOARD.lock().keymap = keymap;
+}
+
+/// Selects the layout from the `keymap=` boot argument or the config, and
+/// turns the LEDs off to match the lock states.
+pub(super) fn init() {
+    let name = crate::bootargs::get("keymap").unwrap_or(crate::config::devices::KEYMAP);
+    let mut keyboard = KEYBOARD.lock();
+    match keymap::find(name) {
+        Some(map) => keyboard.keymap = map,
+        None => warn!("PS/2: unknown keymap {:?}, using {:?}", name, keyboard.keymap.name),
+    }
+    keyboard.update_leds();
+}
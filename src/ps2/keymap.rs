@@ -0,0 +1,122 @@
+//! Keyboard layouts.
+//!
+//! A [`Keymap`] gives the characters of the main block keys. Keys outside the
+//! main block (function keys, navigation, keypad) do not depend on the layout.
+//! Layouts other than the built-in ones can be defined as statics and
+//! installed with [`super::keyboard::set_keymap`].
+
+/// No character.
+const N: char = '\0';
+
+/// Characters of a key: plain, with Shift, with AltGr.
+pub type KeyChars = [char; 3];
+
+const fn k(plain: char, shift: char) -> KeyChars {
+    [plain, shift, N]
+}
+
+const fn k3(plain: char, shift: char, altgr: char) -> KeyChars {
+    [plain, shift, altgr]
+}
+
+/// Number of main block keycodes, `0x00..0x3a`.
+pub const MAIN_KEYS: usize = 0x3a;
+
+/// A keyboard layout.
+pub struct Keymap {
+    /// Name used by the `keymap` config and boot argument.
+    pub name: &'static str,
+    /// Characters of the main block keys, indexed by keycode.
+    pub keys: [KeyChars; MAIN_KEYS],
+    /// Characters of the extra ISO key left of `Z` (keycode `0x56`).
+    pub iso_key: KeyChars,
+}
+
+impl Keymap {
+    /// Returns the characters of a non-extended keycode, if it belongs to the
+    /// main block.
+    pub fn chars(&self, code: u8) -> Option<KeyChars> {
+        match code {
+            0x56 => Some(self.iso_key),
+            _ => self.keys.get(code as usize).copied(),
+        }
+    }
+}
+
+/// US QWERTY.
+#[rustfmt::skip]
+pub static US: Keymap = Keymap {
+    name: "us",
+    keys: [
+        k(N, N), k('\x1b', '\x1b'),
+        k('1', '!'), k('2', '@'), k('3', '#'), k('4', '$'), k('5', '%'), k('6', '^'),
+        k('7', '&'), k('8', '*'), k('9', '('), k('0', ')'), k('-', '_'), k('=', '+'),
+        k('\x7f', '\x7f'), k('\t', '\t'),
+        k('q', 'Q'), k('w', 'W'), k('e', 'E'), k('r', 'R'), k('t', 'T'), k('y', 'Y'),
+        k('u', 'U'), k('i', 'I'), k('o', 'O'), k('p', 'P'), k('[', '{'), k(']', '}'),
+        k('\r', '\r'), k(N, N),
+        k('a', 'A'), k('s', 'S'), k('d', 'D'), k('f', 'F'), k('g', 'G'), k('h', 'H'),
+        k('j', 'J'), k('k', 'K'), k('l', 'L'), k(';', ':'), k('\'', '"'), k('`', '~'),
+        k(N, N), k('\\', '|'),
+        k('z', 'Z'), k('x', 'X'), k('c', 'C'), k('v', 'V'), k('b', 'B'), k('n', 'N'),
+        k('m', 'M'), k(',', '<'), k('.', '>'), k('/', '?'),
+        k(N, N), k('*', '*'), k(N, N), k(' ', ' '),
+    ],
+    iso_key: k('\\', '|'),
+};
+
+/// UK QWERTY.
+#[rustfmt::skip]
+pub static UK: Keymap = Keymap {
+    name: "uk",
+    keys: [
+        k(N, N), k('\x1b', '\x1b'),
+        k('1', '!'), k('2', '"'), k('3', '£'), k3('4', '$', '€'), k('5', '%'), k('6', '^'),
+        k('7', '&'), k('8', '*'), k('9', '('), k('0', ')'), k('-', '_'), k('=', '+'),
+        k('\x7f', '\x7f'), k('\t', '\t'),
+        k('q', 'Q'), k('w', 'W'), k3('e', 'E', 'é'), k('r', 'R'), k('t', 'T'), k('y', 'Y'),
+        k3('u', 'U', 'ú'), k3('i', 'I', 'í'), k3('o', 'O', 'ó'), k('p', 'P'), k('[', '{'),
+        k(']', '}'),
+        k('\r', '\r'), k(N, N),
+        k3('a', 'A', 'á'), k('s', 'S'), k('d', 'D'), k('f', 'F'), k('g', 'G'), k('h', 'H'),
+        k('j', 'J'), k('k', 'K'), k('l', 'L'), k(';', ':'), k('\'', '@'), k3('`', '¬', '¦'),
+        k(N, N), k('#', '~'),
+        k('z', 'Z'), k('x', 'X'), k('c', 'C'), k('v', 'V'), k('b', 'B'), k('n', 'N'),
+        k('m', 'M'), k(',', '<'), k('.', '>'), k('/', '?'),
+        k(N, N), k('*', '*'), k(N, N), k(' ', ' '),
+    ],
+    iso_key: k('\\', '|'),
+};
+
+/// German QWERTZ. The accent keys produce the accent itself, there are no
+/// dead keys.
+#[rustfmt::skip]
+pub static DE: Keymap = Keymap {
+    name: "de",
+    keys: [
+        k(N, N), k('\x1b', '\x1b'),
+        k('1', '!'), k3('2', '"', '²'), k3('3', '§', '³'), k('4', '$'), k('5', '%'),
+        k('6', '&'), k3('7', '/', '{'), k3('8', '(', '['), k3('9', ')', ']'),
+        k3('0', '=', '}'), k3('ß', '?', '\\'), k('´', '`'),
+        k('\x7f', '\x7f'), k('\t', '\t'),
+        k3('q', 'Q', '@'), k('w', 'W'), k3('e', 'E', '€'), k('r', 'R'), k('t', 'T'),
+        k('z', 'Z'), k('u', 'U'), k('i', 'I'), k('o', 'O'), k('p', 'P'), k('ü', 'Ü'),
+        k3('+', '*', '~'),
+        k('\r', '\r'), k(N, N),
+        k('a', 'A'), k('s', 'S'), k('d', 'D'), k('f', 'F'), k('g', 'G'), k('h', 'H'),
+        k('j', 'J'), k('k', 'K'), k('l', 'L'), k('ö', 'Ö'), k('ä', 'Ä'), k('^', '°'),
+        k(N, N), k('#', '\''),
+        k('y', 'Y'), k('x', 'X'), k('c', 'C'), k('v', 'V'), k('b', 'B'), k('n', 'N'),
+        k3('m', 'M', 'µ'), k(',', ';'), k('.', ':'), k('-', '_'),
+        k(N, N), k('*', '*'), k(N, N), k(' ', ' '),
+    ],
+    iso_key: k3('<', '>', '|'),
+};
+
+/// Built-in layouts.
+pub static KEYMAPS: &[&Keymap] = &[&US, &UK, &DE];
+
+/// Returns the built-in layout with the given name.
+pub fn find(name: &str) -> Option<&'static Keymap> {
+    KEYMAPS.iter().copied().find(|map| map.name == name)
+}
@@ -0,0 +1,267 @@
+//! PS/2 keyboard and aux (touchpad) ports of the i8042-compatible controller
+//! on the LPC bus (`i8042` in `lpc-devices`).
+//!
+//! Received bytes are moved from the controller into one ring buffer per
+//! port, by the port interrupts when `ps2-keyboard-irq` / `ps2-aux-irq` are
+//! set (and the `irq` feature is on), and whenever input is read. The
+//! [`keyboard`] layer turns the buffered scancodes into key events and
+//! console input, and the [`mouse`] driver turns aux bytes into pointer
+//! events.
+//!
+//! Bytes received with a parity or timeout error are dropped. When errors
+//! keep coming, or the controller stops taking commands, it is brought up
+//! again from scratch by the next reader (or [`crate::hotkey::poll`]),
+//! never from the interrupt handlers.
+
+mod i8042;
+pub mod keyboard;
+pub mod keymap;
+pub mod mouse;
+
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+use kspin::SpinNoIrq;
+use log::{info, warn};
+
+use crate::config::devices::PS2_SCANCODE_BUF_SIZE;
+use crate::lpc;
+use crate::ringbuf::RingBuffer;
+
+pub use i8042::Error;
+
+/// Data port.
+const DATA_PORT: u16 = 0x60;
+/// Status (read) and command (write) port.
+const STATUS_PORT: u16 = 0x64;
+
+/// Status: output buffer full, a byte is waiting in the data port.
+const STATUS_OBF: u8 = 1 << 0;
+/// Status: input buffer full, the controller has not taken the last write.
+const STATUS_IBF: u8 = 1 << 1;
+/// Status: the waiting byte comes from the aux port.
+const STATUS_AUX: u8 = 1 << 5;
+/// Status: timeout and parity errors on the waiting byte.
+const STATUS_TIMEOUT: u8 = 1 << 6;
+const STATUS_PARITY: u8 = 1 << 7;
+
+/// Most bytes read in one go; more means the output buffer is stuck full.
+const MAX_DRAIN: usize = 64;
+/// Errors in a row after which the controller is reinitialized.
+const MAX_CONSECUTIVE_ERRORS: usize = 8;
+
+/// Whether the controller passed its bring-up.
+static PRESENT: AtomicBool = AtomicBool::new(false);
+static SCANCODES: SpinNoIrq<RingBuffer<u8, PS2_SCANCODE_BUF_SIZE>> =
+    SpinNoIrq::new(RingBuffer::new());
+static AUX_BYTES: SpinNoIrq<RingBuffer<u8, PS2_SCANCODE_BUF_SIZE>> =
+    SpinNoIrq::new(RingBuffer::new());
+static OVERRUNS: AtomicUsize = AtomicUsize::new(0);
+static AUX_OVERRUNS: AtomicUsize = AtomicUsize::new(0);
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+static CONSECUTIVE_ERRORS: AtomicUsize = AtomicUsize::new(0);
+static RESETS: AtomicUsize = AtomicUsize::new(0);
+static NEEDS_RESET: AtomicBool = AtomicBool::new(false);
+static AUX_PRESENT: AtomicBool = AtomicBool::new(false);
+/// Controller interrupts to enable, as `i8042::CONFIG_*_INT` bits.
+static INTERRUPTS: AtomicU8 = AtomicU8::new(0);
+static WAKEUP: SpinNoIrq<Option<fn()>> = SpinNoIrq::new(None);
+
+/// Counts a controller error, asking for a reset when there are too many in
+/// a row.
+fn note_error() {
+    ERRORS.fetch_add(1, Ordering::Relaxed);
+    if CONSECUTIVE_ERRORS.fetch_add(1, Ordering::Relaxed) + 1 >= MAX_CONSECUTIVE_ERRORS {
+        NEEDS_RESET.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Moves the bytes waiting in the controller into the port buffers.
+///
+/// Returns whether anything was queued.
+fn drain() -> bool {
+    if !PRESENT.load(Ordering::Relaxed) {
+        return false;
+    }
+    let mut scancodes = SCANCODES.lock();
+    let mut aux_bytes = AUX_BYTES.lock();
+    let mut queued = false;
+    for _ in 0..MAX_DRAIN {
+        let status = lpc::inb(STATUS_PORT);
+        if status & STATUS_OBF == 0 {
+            return queued;
+        }
+        let byte = lpc::inb(DATA_PORT);
+        if status & (STATUS_TIMEOUT | STATUS_PARITY) != 0 {
+            note_error();
+            continue;
+        }
+        CONSECUTIVE_ERRORS.store(0, Ordering::Relaxed);
+        let (queue, overruns) = if status & STATUS_AUX != 0 {
+            (&mut aux_bytes, &AUX_OVERRUNS)
+        } else {
+            (&mut scancodes, &OVERRUNS)
+        };
+        if queue.push(byte) {
+            queued = true;
+        } else {
+            overruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    NEEDS_RESET.store(true, Ordering::Relaxed);
+    queued
+}
+
+/// Brings the controller up, discarding buffered input.
+fn reset_controller() -> Result<(), Error> {
+    let mut scancodes = SCANCODES.lock();
+    let mut aux_bytes = AUX_BYTES.lock();
+    let result = i8042::init(INTERRUPTS.load(Ordering::Relaxed));
+    scancodes.clear();
+    aux_bytes.clear();
+    CONSECUTIVE_ERRORS.store(0, Ordering::Relaxed);
+    PRESENT.store(result.is_ok(), Ordering::Relaxed);
+    AUX_PRESENT.store(result.is_ok_and(|ports| ports.aux), Ordering::Relaxed);
+    result.map(|_| ())
+}
+
+/// Reinitializes the controller if it misbehaved.
+///
+/// Decoding state built from earlier input is stale afterwards, which
+/// [`resets`] tells.
+fn recover() {
+    if !NEEDS_RESET.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    warn!("PS/2: controller errors, reinitializing");
+    if let Err(e) = reset_controller() {
+        warn!("PS/2: controller reset failed: {:?}", e);
+    }
+    RESETS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Reads a raw scancode, or returns [`None`] if none is buffered.
+fn read_scancode() -> Option<u8> {
+    drain();
+    SCANCODES.lock().pop()
+}
+
+/// Reads a byte received from the aux device, or returns [`None`] if there
+/// is none.
+fn read_aux_byte() -> Option<u8> {
+    drain();
+    AUX_BYTES.lock().pop()
+}
+
+/// Sends a byte to the keyboard, returning `false` if the controller does
+/// not take it.
+fn send_data(val: u8) -> bool {
+    if !PRESENT.load(Ordering::Relaxed) {
+        return false;
+    }
+    let sent = i8042::send_keyboard(val).is_ok();
+    if !sent {
+        NEEDS_RESET.store(true, Ordering::Relaxed);
+    }
+    sent
+}
+
+/// Reads a character typed on the keyboard, or returns [`None`] if there is
+/// no input.
+pub fn read_byte() -> Option<u8> {
+    keyboard::read_byte()
+}
+
+/// Returns whether a device answered on the aux port.
+pub fn aux_present() -> bool {
+    AUX_PRESENT.load(Ordering::Relaxed)
+}
+
+/// Returns how many keyboard bytes were dropped because the buffer was full.
+pub fn overruns() -> usize {
+    OVERRUNS.load(Ordering::Relaxed)
+}
+
+/// Returns how many aux bytes were dropped because the buffer was full.
+pub fn aux_overruns() -> usize {
+    AUX_OVERRUNS.load(Ordering::Relaxed)
+}
+
+/// Returns how many bytes were received with a parity or timeout error.
+pub fn errors() -> usize {
+    ERRORS.load(Ordering::Relaxed)
+}
+
+/// Returns how many times the controller was reinitialized after errors.
+pub fn resets() -> usize {
+    RESETS.load(Ordering::Relaxed)
+}
+
+/// Sets the callback invoked from the port interrupts after new bytes have
+/// been buffered, e.g. to wake up a reader blocked on the console.
+pub fn set_wakeup(callback: Option<fn()>) {
+    *WAKEUP.lock() = callback;
+}
+
+/// Returns whether something answers at the controller ports. A floating
+/// bus reads as all ones.
+pub(crate) fn probe() -> bool {
+    lpc::inb(STATUS_PORT) != 0xff
+}
+
+/// Brings up the controller, with its interrupts off.
+pub(crate) fn init() {
+    if !lpc::is_present("i8042") {
+        return;
+    }
+    match reset_controller() {
+        Ok(()) => info!("PS/2: controller ready, aux port {}", if aux_present() { "on" } else { "off" }),
+        Err(e) => warn!("PS/2: controller init failed: {:?}", e),
+    }
+    keyboard::init();
+    mouse::init();
+}
+
+/// Enables the port interrupts that have an IRQ configured.
+#[cfg(feature = "irq")]
+pub(crate) fn init_irq() {
+    use crate::config::devices::{PS2_AUX_IRQ, PS2_KEYBOARD_IRQ};
+    if !PRESENT.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut interrupts = 0;
+    if PS2_KEYBOARD_IRQ != 0 {
+        axplat::irq::register(PS2_KEYBOARD_IRQ, irq_handler);
+        interrupts |= i8042::CONFIG_KBD_INT;
+    }
+    if PS2_AUX_IRQ != 0 && PS2_AUX_IRQ != PS2_KEYBOARD_IRQ {
+        axplat::irq::register(PS2_AUX_IRQ, irq_handler);
+    }
+    if PS2_AUX_IRQ != 0 {
+        interrupts |= i8042::CONFIG_AUX_INT;
+    }
+    if interrupts == 0 {
+        return;
+    }
+    INTERRUPTS.store(interrupts, Ordering::Relaxed);
+    // Keep readers away from the output buffer while the controller replies.
+    let scancodes = SCANCODES.lock();
+    if let Err(e) = i8042::enable_interrupts(interrupts, aux_present()) {
+        warn!("PS/2: cannot enable interrupts: {:?}", e);
+    }
+    drop(scancodes);
+}
+
+#[cfg(feature = "irq")]
+fn irq_handler() {
+    if drain() {
+        // Catch hotkeys even when nobody reads the keyboard. A controller
+        // reset is left to the next reader or poll, as it takes too long
+        // for an interrupt handler.
+        keyboard::decode();
+        crate::hotkey::dispatch();
+        let wakeup = *WAKEUP.lock();
+        if let Some(wakeup) = wakeup {
+            wakeup();
+        }
+    }
+}
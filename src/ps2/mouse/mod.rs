@@ -0,0 +1,99 @@
+//! PS/2 mouse or touchpad on the aux port.
+//!
+//! The device is detected and set up at boot, and again after the controller
+//! was reinitialized. [`read_event`] decodes the bytes it sent since the last
+//! call.
+
+mod protocol;
+
+use axplat::time::{NANOS_PER_MILLIS, monotonic_time_nanos};
+use kspin::SpinNoIrq;
+use log::{info, warn};
+
+pub use protocol::{
+    AbsolutePosition, AuxPort, Buttons, Decoder, DeviceInfo, Error, PointerEvent, Protocol,
+};
+
+/// The aux port of the i8042.
+struct I8042Aux;
+
+impl AuxPort for I8042Aux {
+    fn send(&mut self, byte: u8) -> bool {
+        super::i8042::send_aux(byte).is_ok()
+    }
+
+    fn recv(&mut self, timeout_ms: u64) -> Option<u8> {
+        let deadline = monotonic_time_nanos() + timeout_ms * NANOS_PER_MILLIS;
+        loop {
+            if let Some(byte) = super::read_aux_byte() {
+                return Some(byte);
+            }
+            if monotonic_time_nanos() >= deadline {
+                return None;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+struct Mouse {
+    info: Option<DeviceInfo>,
+    decoder: Option<Decoder>,
+    /// Controller resets seen, see [`super::resets`].
+    resets: usize,
+}
+
+static MOUSE: SpinNoIrq<Mouse> = SpinNoIrq::new(Mouse {
+    info: None,
+    decoder: None,
+    resets: 0,
+});
+
+/// Detects and sets up the aux device. The lock is not held meanwhile, as
+/// the device may take up to a second to answer.
+fn setup() {
+    let info = if super::aux_present() {
+        match protocol::setup(&mut I8042Aux) {
+            Ok(info) => {
+                info!("PS/2: pointer device {:?}", info);
+                Some(info)
+            }
+            Err(e) => {
+                warn!("PS/2: pointer device setup failed: {:?}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let mut mouse = MOUSE.lock();
+    mouse.info = info;
+    mouse.decoder = info.map(|info| Decoder::new(info.protocol));
+}
+
+/// Returns what was detected on the aux port, or [`None`] if no pointer
+/// device is set up.
+pub fn info() -> Option<DeviceInfo> {
+    MOUSE.lock().info
+}
+
+/// Reads the next pointer event, or returns [`None`] if there is none.
+pub fn read_event() -> Option<PointerEvent> {
+    super::recover();
+    let resets = super::resets();
+    if core::mem::replace(&mut MOUSE.lock().resets, resets) != resets {
+        setup();
+    }
+    let mut mouse = MOUSE.lock();
+    let decoder = mouse.decoder.as_mut()?;
+    while let Some(byte) = super::read_aux_byte() {
+        if let Some(event) = decoder.push(byte) {
+            return Some(event);
+        }
+    }
+    None
+}
+
+pub(super) fn init() {
+    setup();
+}
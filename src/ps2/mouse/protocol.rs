@@ -0,0 +1,627 @@
+//! PS/2 mouse protocol: device detection, setup and packet decoding.
+//!
+//! Supported protocols:
+//!
+//! - standard 3-byte packets;
+//! - IntelliMouse (4-byte packets with a wheel) and IntelliMouse Explorer
+//!   (wheel and buttons 4/5);
+//! - Synaptics touchpads in absolute mode (6-byte packets with position and
+//!   pressure). ALPS touchpads are recognized but driven in relative mode,
+//!   as their absolute protocols differ between versions.
+//!
+//! This file only depends on `core`, and talks to the device through an
+//! [`AuxPort`], so that the tests below can run it against a software model
+//! of the i8042 aux port. The `host-tests` crate builds it for the host to
+//! run them.
+
+/// Commands.
+const CMD_SET_SCALING_1_1: u8 = 0xe6;
+const CMD_SET_SCALING_2_1: u8 = 0xe7;
+const CMD_SET_RESOLUTION: u8 = 0xe8;
+const CMD_STATUS_REQUEST: u8 = 0xe9;
+const CMD_GET_ID: u8 = 0xf2;
+const CMD_SET_SAMPLE_RATE: u8 = 0xf3;
+const CMD_ENABLE_REPORTING: u8 = 0xf4;
+const CMD_DISABLE_REPORTING: u8 = 0xf5;
+const CMD_RESET: u8 = 0xff;
+
+/// Replies.
+const ACK: u8 = 0xfa;
+const RESEND: u8 = 0xfe;
+const SELF_TEST_OK: u8 = 0xaa;
+
+/// Device IDs.
+const ID_INTELLIMOUSE: u8 = 0x03;
+const ID_EXPLORER: u8 = 0x04;
+
+/// Middle byte of the Synaptics identify reply.
+const SYNAPTICS_MAGIC: u8 = 0x47;
+/// Synaptics mode byte: absolute packets at 80 packets/s.
+const SYNAPTICS_MODE_ABSOLUTE: u8 = 0xc0;
+/// Sample rate "setting" that commits a Synaptics mode byte.
+const SYNAPTICS_SET_MODE: u8 = 0x14;
+
+/// Reply timeouts.
+const REPLY_TIMEOUT_MS: u64 = 50;
+const RESET_TIMEOUT_MS: u64 = 1000;
+
+/// Packet byte 0 bits.
+const PKT_LEFT: u8 = 1 << 0;
+const PKT_RIGHT: u8 = 1 << 1;
+const PKT_MIDDLE: u8 = 1 << 2;
+const PKT_ALWAYS_1: u8 = 1 << 3;
+const PKT_X_SIGN: u8 = 1 << 4;
+const PKT_Y_SIGN: u8 = 1 << 5;
+const PKT_X_OVERFLOW: u8 = 1 << 6;
+const PKT_Y_OVERFLOW: u8 = 1 << 7;
+
+/// Byte link to the aux device.
+pub trait AuxPort {
+    /// Sends a byte to the device, returning `false` if it could not be
+    /// written.
+    fn send(&mut self, byte: u8) -> bool;
+    /// Waits up to `timeout_ms` for a byte from the device.
+    fn recv(&mut self, timeout_ms: u64) -> Option<u8>;
+}
+
+/// A device setup error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The device did not take a byte or did not reply in time.
+    Timeout,
+    /// The device replied something unexpected.
+    BadReply(u8),
+}
+
+/// The packet format a device was set up for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// Standard 3-byte packets.
+    Standard,
+    /// 4-byte packets with a wheel.
+    IntelliMouse,
+    /// 4-byte packets with a wheel and buttons 4/5.
+    Explorer,
+    /// Synaptics 6-byte absolute packets.
+    SynapticsAbsolute,
+}
+
+impl Protocol {
+    fn packet_len(self) -> usize {
+        match self {
+            Self::Standard => 3,
+            Self::IntelliMouse | Self::Explorer => 4,
+            Self::SynapticsAbsolute => 6,
+        }
+    }
+}
+
+/// What [`setup`] found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub protocol: Protocol,
+    /// Whether the device answered the ALPS identification.
+    pub alps: bool,
+    /// Synaptics firmware version (major, minor), if it is one.
+    pub synaptics: Option<(u8, u8)>,
+}
+
+/// Mouse buttons, one bit each.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Buttons(pub u8);
+
+impl Buttons {
+    pub const LEFT: u8 = 1 << 0;
+    pub const RIGHT: u8 = 1 << 1;
+    pub const MIDDLE: u8 = 1 << 2;
+    pub const BUTTON_4: u8 = 1 << 3;
+    pub const BUTTON_5: u8 = 1 << 4;
+
+    /// Returns whether the buttons in `mask` are all pressed.
+    pub const fn contains(self, mask: u8) -> bool {
+        self.0 & mask == mask
+    }
+}
+
+/// Finger position on an absolute touchpad, in device units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbsolutePosition {
+    pub x: u16,
+    pub y: u16,
+    /// 0 when nothing touches the pad.
+    pub pressure: u8,
+}
+
+/// A pointer report.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PointerEvent {
+    /// Relative motion, positive to the right.
+    pub dx: i16,
+    /// Relative motion, positive downwards (screen direction).
+    pub dy: i16,
+    /// Wheel motion, positive downwards (towards the user).
+    pub wheel: i8,
+    pub buttons: Buttons,
+    /// Position of absolute devices.
+    pub absolute: Option<AbsolutePosition>,
+}
+
+/// Sends a command byte and waits for its `ACK`, resending once if asked.
+fn command<P: AuxPort>(port: &mut P, byte: u8) -> Result<(), Error> {
+    for _ in 0..2 {
+        if !port.send(byte) {
+            return Err(Error::Timeout);
+        }
+        match port.recv(REPLY_TIMEOUT_MS) {
+            Some(ACK) => return Ok(()),
+            Some(RESEND) => continue,
+            Some(reply) => return Err(Error::BadReply(reply)),
+            None => return Err(Error::Timeout),
+        }
+    }
+    Err(Error::BadReply(RESEND))
+}
+
+fn command_arg<P: AuxPort>(port: &mut P, byte: u8, arg: u8) -> Result<(), Error> {
+    command(port, byte)?;
+    command(port, arg)
+}
+
+fn recv<P: AuxPort>(port: &mut P) -> Result<u8, Error> {
+    port.recv(REPLY_TIMEOUT_MS).ok_or(Error::Timeout)
+}
+
+fn status_request<P: AuxPort>(port: &mut P) -> Result<[u8; 3], Error> {
+    command(port, CMD_STATUS_REQUEST)?;
+    Ok([recv(port)?, recv(port)?, recv(port)?])
+}
+
+fn get_id<P: AuxPort>(port: &mut P) -> Result<u8, Error> {
+    command(port, CMD_GET_ID)?;
+    recv(port)
+}
+
+fn set_sample_rates<P: AuxPort>(port: &mut P, rates: &[u8]) -> Result<(), Error> {
+    rates
+        .iter()
+        .try_for_each(|&rate| command_arg(port, CMD_SET_SAMPLE_RATE, rate))
+}
+
+/// Sends a byte as the Synaptics "special command" sequence: four set
+/// resolution commands carrying two bits each, most significant first.
+fn synaptics_special<P: AuxPort>(port: &mut P, byte: u8) -> Result<(), Error> {
+    (0..4)
+        .rev()
+        .try_for_each(|i| command_arg(port, CMD_SET_RESOLUTION, (byte >> (2 * i)) & 3))
+}
+
+fn synaptics_identify<P: AuxPort>(port: &mut P) -> Result<Option<(u8, u8)>, Error> {
+    synaptics_special(port, 0x00)?;
+    let [minor, magic, major] = status_request(port)?;
+    Ok((magic == SYNAPTICS_MAGIC).then_some((major & 0x0f, minor)))
+}
+
+/// Recognizes ALPS touchpads by their answer to three 2:1 scaling commands
+/// followed by a status request.
+fn alps_identify<P: AuxPort>(port: &mut P) -> Result<bool, Error> {
+    for _ in 0..3 {
+        command(port, CMD_SET_SCALING_2_1)?;
+    }
+    let report = status_request(port)?;
+    command(port, CMD_SET_SCALING_1_1)?;
+    Ok(report[0] != 0 && matches!(report[1], 0x02 | 0x03) && matches!(report[2], 0x0a | 0x14 | 0x28 | 0x50 | 0x64))
+}
+
+/// Resets the device and sets it up for the richest protocol it supports,
+/// with reporting enabled.
+pub fn setup<P: AuxPort>(port: &mut P) -> Result<DeviceInfo, Error> {
+    // Stop any stream in progress so replies are not mixed with packets.
+    let _ = command(port, CMD_DISABLE_REPORTING);
+    while port.recv(0).is_some() {}
+
+    command(port, CMD_RESET)?;
+    match port.recv(RESET_TIMEOUT_MS) {
+        Some(SELF_TEST_OK) => {}
+        Some(reply) => return Err(Error::BadReply(reply)),
+        None => return Err(Error::Timeout),
+    }
+    // Device ID after the self test.
+    recv(port)?;
+
+    let mut info = DeviceInfo {
+        protocol: Protocol::Standard,
+        alps: false,
+        synaptics: synaptics_identify(port)?,
+    };
+    if info.synaptics.is_some() {
+        synaptics_special(port, SYNAPTICS_MODE_ABSOLUTE)?;
+        command_arg(port, CMD_SET_SAMPLE_RATE, SYNAPTICS_SET_MODE)?;
+        info.protocol = Protocol::SynapticsAbsolute;
+    } else {
+        info.alps = alps_identify(port)?;
+        // The magic sample rate sequences unlock the wheel, then buttons 4/5.
+        set_sample_rates(port, &[200, 100, 80])?;
+        if get_id(port)? == ID_INTELLIMOUSE {
+            info.protocol = Protocol::IntelliMouse;
+            set_sample_rates(port, &[200, 200, 80])?;
+            if get_id(port)? == ID_EXPLORER {
+                info.protocol = Protocol::Explorer;
+            }
+        }
+        set_sample_rates(port, &[100])?;
+    }
+    command(port, CMD_ENABLE_REPORTING)?;
+    Ok(info)
+}
+
+/// Assembles packets from the byte stream and decodes them.
+pub struct Decoder {
+    protocol: Protocol,
+    packet: [u8; 6],
+    len: usize,
+    /// Last absolute position while touching, to derive relative motion.
+    last: Option<(u16, u16)>,
+}
+
+impl Decoder {
+    pub const fn new(protocol: Protocol) -> Self {
+        Self {
+            protocol,
+            packet: [0; 6],
+            len: 0,
+            last: None,
+        }
+    }
+
+    /// Feeds one byte from the device, returning an event when it completes
+    /// a packet.
+    ///
+    /// Bytes that cannot start a packet are dropped, which brings the decoder
+    /// back in step after a lost byte.
+    pub fn push(&mut self, byte: u8) -> Option<PointerEvent> {
+        let sync_ok = match (self.protocol, self.len) {
+            (Protocol::SynapticsAbsolute, 0) => byte & 0xc8 == 0x80,
+            (Protocol::SynapticsAbsolute, 3) => byte & 0xc8 == 0xc0,
+            (_, 0) => byte & PKT_ALWAYS_1 != 0,
+            _ => true,
+        };
+        if !sync_ok {
+            self.len = 0;
+            return None;
+        }
+        self.packet[self.len] = byte;
+        self.len += 1;
+        if self.len < self.protocol.packet_len() {
+            return None;
+        }
+        self.len = 0;
+        Some(match self.protocol {
+            Protocol::SynapticsAbsolute => self.decode_synaptics(),
+            _ => self.decode_relative(),
+        })
+    }
+
+    fn decode_relative(&self) -> PointerEvent {
+        let p = &self.packet;
+        let mut buttons = p[0] & (PKT_LEFT | PKT_RIGHT | PKT_MIDDLE);
+        let delta = |value: u8, sign: u8, overflow: u8| -> i16 {
+            if p[0] & overflow != 0 {
+                0
+            } else if p[0] & sign != 0 {
+                value as i16 - 0x100
+            } else {
+                value as i16
+            }
+        };
+        let dx = delta(p[1], PKT_X_SIGN, PKT_X_OVERFLOW);
+        let dy = -delta(p[2], PKT_Y_SIGN, PKT_Y_OVERFLOW);
+        let wheel = match self.protocol {
+            Protocol::IntelliMouse => p[3] as i8,
+            Protocol::Explorer => {
+                if p[3] & 0x10 != 0 {
+                    buttons |= Buttons::BUTTON_4;
+                }
+                if p[3] & 0x20 != 0 {
+                    buttons |= Buttons::BUTTON_5;
+                }
+                // Sign-extend the 4-bit value.
+                ((p[3] << 4) as i8) >> 4
+            }
+            _ => 0,
+        };
+        PointerEvent {
+            dx,
+            dy,
+            wheel,
+            buttons: Buttons(buttons),
+            absolute: None,
+        }
+    }
+
+    fn decode_synaptics(&mut self) -> PointerEvent {
+        let p = &self.packet;
+        let x = ((p[3] as u16 & 0x10) << 8) | ((p[1] as u16 & 0x0f) << 8) | p[4] as u16;
+        let y = ((p[3] as u16 & 0x20) << 7) | ((p[1] as u16 & 0xf0) << 4) | p[5] as u16;
+        let pressure = p[2];
+        let buttons = Buttons(p[0] & (PKT_LEFT | PKT_RIGHT));
+        let (mut dx, mut dy) = (0, 0);
+        if pressure != 0 {
+            if let Some((last_x, last_y)) = self.last {
+                dx = (x as i32 - last_x as i32).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+                // The pad's Y axis points up.
+                dy = (last_y as i32 - y as i32).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+            }
+            self.last = Some((x, y));
+        } else {
+            self.last = None;
+        }
+        PointerEvent {
+            dx,
+            dy,
+            wheel: 0,
+            buttons,
+            absolute: Some(AbsolutePosition { x, y, pressure }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::VecDeque;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum Kind {
+        Standard,
+        IntelliMouse,
+        Explorer,
+        Synaptics,
+        Alps,
+    }
+
+    /// A PS/2 pointing device as seen through the controller's aux port.
+    struct Model {
+        kind: Kind,
+        /// Bytes waiting in the controller for the host.
+        output: VecDeque<u8>,
+        /// Command waiting for its argument byte.
+        pending: Option<u8>,
+        id: u8,
+        rates: Vec<u8>,
+        /// Arguments of the set resolution commands since the last other command.
+        resolutions: Vec<u8>,
+        scaling_2_1: usize,
+        reporting: bool,
+        synaptics_mode: Option<u8>,
+        /// Stops answering, to exercise timeouts.
+        dead: bool,
+    }
+
+    impl Model {
+        fn new(kind: Kind) -> Self {
+            Self {
+                kind,
+                output: VecDeque::new(),
+                pending: None,
+                id: 0,
+                rates: Vec::new(),
+                resolutions: Vec::new(),
+                scaling_2_1: 0,
+                reporting: false,
+                synaptics_mode: None,
+                dead: false,
+            }
+        }
+
+        fn reply(&mut self, bytes: &[u8]) {
+            self.output.extend(bytes);
+        }
+
+        fn argument(&mut self, cmd: u8, arg: u8) {
+            self.reply(&[0xfa]);
+            match cmd {
+                0xe8 => self.resolutions.push(arg),
+                0xf3 => {
+                    if self.kind == Kind::Synaptics && arg == 0x14 && self.resolutions.len() == 4 {
+                        let mode = self.resolutions.iter().fold(0, |acc, &r| acc << 2 | r);
+                        self.synaptics_mode = Some(mode);
+                    }
+                    self.resolutions.clear();
+                    self.rates.push(arg);
+                    let tail = |seq: &[u8]| self.rates.ends_with(seq);
+                    if tail(&[200, 100, 80])
+                        && self.kind != Kind::Standard
+                        && self.kind != Kind::Alps
+                    {
+                        self.id = 3;
+                    } else if tail(&[200, 200, 80]) && self.kind == Kind::Explorer && self.id == 3 {
+                        self.id = 4;
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        fn command(&mut self, cmd: u8) {
+            if !matches!(cmd, 0xe8 | 0xe9 | 0xf3) {
+                self.resolutions.clear();
+            }
+            if cmd != 0xe7 && cmd != 0xe9 {
+                self.scaling_2_1 = 0;
+            }
+            match cmd {
+                0xff => {
+                    *self = Self::new(self.kind);
+                    self.reply(&[0xfa, 0xaa, 0x00]);
+                }
+                0xe8 | 0xf3 => {
+                    self.reply(&[0xfa]);
+                    self.pending = Some(cmd);
+                }
+                0xe9 => {
+                    self.reply(&[0xfa]);
+                    if self.kind == Kind::Synaptics && self.resolutions.len() == 4 {
+                        self.reply(&[0x01, 0x47, 0x18]);
+                    } else if self.kind == Kind::Alps && self.scaling_2_1 == 3 {
+                        self.reply(&[0x73, 0x02, 0x64]);
+                    } else {
+                        self.reply(&[0x00, 0x02, 0x64]);
+                    }
+                    self.resolutions.clear();
+                    self.scaling_2_1 = 0;
+                }
+                0xe7 => {
+                    self.scaling_2_1 += 1;
+                    self.reply(&[0xfa]);
+                }
+                0xf2 => {
+                    let id = self.id;
+                    self.reply(&[0xfa, id]);
+                }
+                0xf4 => {
+                    self.reporting = true;
+                    self.reply(&[0xfa]);
+                }
+                0xf5 => {
+                    self.reporting = false;
+                    self.reply(&[0xfa]);
+                }
+                0xe6 => self.reply(&[0xfa]),
+                _ => self.reply(&[0xfe]),
+            }
+        }
+    }
+
+    impl AuxPort for Model {
+        fn send(&mut self, byte: u8) -> bool {
+            if self.dead {
+                return false;
+            }
+            match self.pending.take() {
+                Some(cmd) => self.argument(cmd, byte),
+                None => self.command(byte),
+            }
+            true
+        }
+
+        fn recv(&mut self, _timeout_ms: u64) -> Option<u8> {
+            self.output.pop_front()
+        }
+    }
+
+    fn decode(protocol: Protocol, bytes: &[u8]) -> Vec<PointerEvent> {
+        let mut decoder = Decoder::new(protocol);
+        bytes.iter().filter_map(|&b| decoder.push(b)).collect()
+    }
+
+    fn relative(dx: i16, dy: i16, wheel: i8, buttons: u8) -> PointerEvent {
+        PointerEvent {
+            dx,
+            dy,
+            wheel,
+            buttons: Buttons(buttons),
+            absolute: None,
+        }
+    }
+
+    #[test]
+    fn detection() {
+        for (kind, protocol) in [
+            (Kind::Standard, Protocol::Standard),
+            (Kind::IntelliMouse, Protocol::IntelliMouse),
+            (Kind::Explorer, Protocol::Explorer),
+            (Kind::Synaptics, Protocol::SynapticsAbsolute),
+            (Kind::Alps, Protocol::Standard),
+        ] {
+            let mut model = Model::new(kind);
+            let info = setup(&mut model).unwrap();
+            assert_eq!(info.protocol, protocol);
+            assert!(model.reporting);
+            assert_eq!(model.output.len(), 0, "stray replies");
+            assert_eq!(info.alps, kind == Kind::Alps);
+            assert_eq!(info.synaptics, (kind == Kind::Synaptics).then_some((8, 1)));
+            if kind == Kind::Synaptics {
+                assert_eq!(model.synaptics_mode, Some(0xc0));
+            }
+        }
+    }
+
+    #[test]
+    fn dead_device() {
+        let mut model = Model::new(Kind::Standard);
+        model.dead = true;
+        assert_eq!(setup(&mut model).map(|i| i.protocol), Err(Error::Timeout));
+    }
+
+    #[test]
+    fn standard_packets() {
+        assert_eq!(
+            decode(Protocol::Standard, &[0x09, 0x05, 0x03, 0x38, 0xfb, 0xfe]),
+            vec![relative(5, -3, 0, Buttons::LEFT), relative(-5, 2, 0, 0)],
+        );
+        // Resynchronizes after a lost byte.
+        assert_eq!(
+            decode(
+                Protocol::Standard,
+                &[0x05, 0x00, 0x0a, 0x01, 0x01, 0x08, 0x00, 0x00]
+            ),
+            vec![relative(1, -1, 0, Buttons::RIGHT), relative(0, 0, 0, 0)],
+        );
+        // Drops packets with an overflow.
+        assert_eq!(
+            decode(Protocol::Standard, &[0x48, 0xff, 0x01]),
+            vec![relative(0, -1, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn wheel_packets() {
+        assert_eq!(
+            decode(Protocol::IntelliMouse, &[0x0c, 0x00, 0x00, 0xff]),
+            vec![relative(0, 0, -1, Buttons::MIDDLE)],
+        );
+        assert_eq!(
+            decode(
+                Protocol::Explorer,
+                &[0x08, 0x00, 0x00, 0x31, 0x08, 0x00, 0x00, 0x0f]
+            ),
+            vec![
+                relative(0, 0, 1, Buttons::BUTTON_4 | Buttons::BUTTON_5),
+                relative(0, 0, -1, 0),
+            ],
+        );
+    }
+
+    #[test]
+    fn synaptics_packets() {
+        // Finger down at (3000, 2000), moves to (3010, 1990), lifts.
+        let packet = |x: u16, y: u16, z: u8, buttons: u8| {
+            [
+                0x80 | buttons,
+                ((y >> 4) & 0xf0) as u8 | ((x >> 8) & 0x0f) as u8,
+                z,
+                0xc0 | ((x >> 8) & 0x10) as u8 | ((y >> 7) & 0x20) as u8 | buttons,
+                x as u8,
+                y as u8,
+            ]
+        };
+        let mut bytes = Vec::new();
+        bytes.extend(packet(3000, 2000, 60, 0));
+        bytes.extend(packet(3010, 1990, 60, 0));
+        bytes.extend(packet(3010, 1990, 0, 1));
+        let events = decode(Protocol::SynapticsAbsolute, &bytes);
+        let abs = |x, y, pressure| Some(AbsolutePosition { x, y, pressure });
+        assert_eq!(
+            events.iter().map(|e| e.absolute).collect::<Vec<_>>(),
+            vec![abs(3000, 2000, 60), abs(3010, 1990, 60), abs(3010, 1990, 0)],
+        );
+        assert_eq!(
+            events.iter().map(|e| (e.dx, e.dy)).collect::<Vec<_>>(),
+            vec![(0, 0), (10, 10), (0, 0)],
+        );
+        assert_eq!(events[2].buttons, Buttons(Buttons::LEFT));
+    }
+}
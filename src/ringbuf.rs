@@ -0,0 +1,91 @@
+//! Fixed-capacity FIFO ring buffer, usable without the heap (e.g. from IRQ
+//! handlers or before the allocator is up).
+
+use core::mem::MaybeUninit;
+
+/// A FIFO queue holding at most `N` items.
+pub struct RingBuffer<T: Copy, const N: usize> {
+    buf: [MaybeUninit<T>; N],
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    /// Creates an empty ring buffer.
+    pub const fn new() -> Self {
+        Self {
+            buf: [const { MaybeUninit::uninit() }; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns the number of queued items.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns whether the buffer is full.
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Returns the number of free slots.
+    pub fn free(&self) -> usize {
+        N - self.len
+    }
+
+    /// Appends an item, or returns `false` if the buffer is full.
+    pub fn push(&mut self, item: T) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.buf[(self.head + self.len) % N] = MaybeUninit::new(item);
+        self.len += 1;
+        true
+    }
+
+    /// Appends an item, dropping the oldest one if the buffer is full.
+    ///
+    /// Returns `false` if an item was dropped.
+    pub fn push_overwrite(&mut self, item: T) -> bool {
+        let dropped = self.is_full() && self.pop().is_some();
+        self.push(item);
+        !dropped
+    }
+
+    /// Removes and returns the oldest item.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        // SAFETY: slots in `head..head + len` are initialized.
+        let item = unsafe { self.buf[self.head].assume_init() };
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(item)
+    }
+
+    /// Iterates over the queued items, oldest first, without removing them.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        // SAFETY: see `pop`.
+        (0..self.len).map(|i| unsafe { self.buf[(self.head + i) % N].assume_init() })
+    }
+
+    /// Removes all items.
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
+
+impl<T: Copy, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,19 @@
+//! Wall-clock time passed by the bootloader on the kernel command line.
+
+use super::RtcBackend;
+
+/// Reads the time from the `rtc.epoch=<seconds>` boot argument.
+///
+/// Useful when the firmware knows the time but the RTC hardware is not
+/// reachable from the kernel.
+pub struct CmdlineRtc;
+
+impl RtcBackend for CmdlineRtc {
+    fn name(&self) -> &'static str {
+        "bootargs"
+    }
+
+    fn read_epoch_secs(&self) -> Option<u64> {
+        crate::bootargs::get_usize("rtc.epoch").map(|secs| secs as u64)
+    }
+}
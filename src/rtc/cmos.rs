@@ -0,0 +1,122 @@
+//! MC146818-compatible CMOS RTC on the LPC bus (`cmos-rtc` in
+//! `lpc-devices`).
+//!
+//! The RTC is reached through the legacy index/data ports `0x70`/`0x71`.
+
+use super::{DateTime, RtcBackend};
+use crate::config::devices::CMOS_RTC_PORT;
+use crate::lpc;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+const REG_CENTURY: u8 = 0x32;
+
+/// Status A: an update cycle is in progress.
+const STATUS_A_UIP: u8 = 1 << 7;
+/// Status B: 24-hour mode.
+const STATUS_B_24H: u8 = 1 << 1;
+/// Status B: binary (instead of BCD) mode.
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// Hour register: PM flag in 12-hour mode.
+const HOUR_PM: u8 = 1 << 7;
+
+/// Maximum tries to get two identical consecutive readings.
+const MAX_READ_TRIES: usize = 16;
+
+/// Reads a register through the index port and the data port after it.
+fn read_reg(reg: u8) -> u8 {
+    let port = CMOS_RTC_PORT as u16;
+    lpc::outb(port, reg);
+    lpc::inb(port + 1)
+}
+
+/// Returns whether an RTC answers. A floating bus reads as all ones.
+pub(crate) fn probe() -> bool {
+    read_reg(REG_STATUS_B) != 0xff
+}
+
+fn bcd_to_bin(v: u8) -> u8 {
+    (v & 0x0f) + (v >> 4) * 10
+}
+
+#[derive(PartialEq, Eq)]
+struct RawTime([u8; 7]);
+
+impl RawTime {
+    /// Reads all time registers, or returns [`None`] during an update cycle.
+    fn read() -> Option<Self> {
+        if read_reg(REG_STATUS_A) & STATUS_A_UIP != 0 {
+            return None;
+        }
+        Some(Self(
+            [REG_SECONDS, REG_MINUTES, REG_HOURS, REG_DAY, REG_MONTH, REG_YEAR, REG_CENTURY]
+                .map(read_reg),
+        ))
+    }
+
+    /// Reads the time registers until two consecutive readings agree, so that
+    /// an update cycle cannot tear the result.
+    fn read_stable() -> Option<Self> {
+        let mut last = None;
+        for _ in 0..MAX_READ_TRIES {
+            let cur = Self::read();
+            if cur.is_some() && cur == last {
+                return cur;
+            }
+            last = cur;
+        }
+        None
+    }
+}
+
+/// CMOS RTC on the LPC bus. The time is assumed to be kept in UTC.
+pub struct CmosRtc;
+
+impl RtcBackend for CmosRtc {
+    fn name(&self) -> &'static str {
+        "cmos"
+    }
+
+    fn read_epoch_secs(&self) -> Option<u64> {
+        if !lpc::is_present("cmos-rtc") {
+            return None;
+        }
+        let status_b = read_reg(REG_STATUS_B);
+        let RawTime([sec, min, hour, day, month, year, century]) = RawTime::read_stable()?;
+
+        let pm = hour & HOUR_PM != 0;
+        let conv = |v: u8| {
+            if status_b & STATUS_B_BINARY != 0 {
+                v
+            } else {
+                bcd_to_bin(v)
+            }
+        };
+        let mut hour = conv(hour & !HOUR_PM) as u32;
+        if status_b & STATUS_B_24H == 0 {
+            hour %= 12;
+            if pm {
+                hour += 12;
+            }
+        }
+        let century = match conv(century) {
+            c @ 19..=21 => c as u32,
+            _ => 20,
+        };
+        DateTime {
+            year: century * 100 + conv(year) as u32,
+            month: conv(month) as u32,
+            day: conv(day) as u32,
+            hour,
+            minute: conv(min) as u32,
+            second: conv(sec) as u32,
+        }
+        .to_epoch_secs()
+    }
+}
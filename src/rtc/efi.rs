@@ -0,0 +1,73 @@
+//! Wall-clock time from the UEFI runtime service `GetTime`.
+
+use core::ffi::c_void;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{DateTime, RtcBackend};
+use crate::efi::RT_GET_TIME;
+
+/// `TimeZone` value meaning the time is not tied to a time zone.
+const EFI_UNSPECIFIED_TIMEZONE: i16 = 0x07ff;
+
+#[repr(C)]
+#[derive(Default)]
+struct EfiTime {
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+    pad1: u8,
+    nanosecond: u32,
+    time_zone: i16,
+    daylight: u8,
+    pad2: u8,
+}
+
+/// Status of the last failed `GetTime` call, 0 if none. Logged by
+/// [`super::init_later`].
+pub(super) static GET_TIME_ERROR: AtomicUsize = AtomicUsize::new(0);
+
+type EfiGetTime = unsafe extern "efiapi" fn(time: *mut EfiTime, caps: *mut c_void) -> usize;
+
+/// UEFI runtime `GetTime`.
+pub struct EfiRtc;
+
+impl RtcBackend for EfiRtc {
+    fn name(&self) -> &'static str {
+        "efi"
+    }
+
+    fn read_epoch_secs(&self) -> Option<u64> {
+        let get_time = crate::efi::runtime_service(RT_GET_TIME)?;
+        // SAFETY: the entry is the firmware's `GetTime` function.
+        let get_time: EfiGetTime = unsafe { core::mem::transmute(get_time) };
+
+        let mut time = EfiTime::default();
+        let ret = {
+            let _guard = kernel_guard::IrqSave::new();
+            // SAFETY: the runtime service code is identity mapped too.
+            unsafe { get_time(&mut time, core::ptr::null_mut()) }
+        };
+        if ret != 0 {
+            GET_TIME_ERROR.store(ret, Ordering::Relaxed);
+            return None;
+        }
+
+        let local = DateTime {
+            year: time.year as u32,
+            month: time.month as u32,
+            day: time.day as u32,
+            hour: time.hour as u32,
+            minute: time.minute as u32,
+            second: time.second as u32,
+        }
+        .to_epoch_secs()?;
+        // UTC = local time - TimeZone (in minutes).
+        match time.time_zone {
+            EFI_UNSPECIFIED_TIMEZONE => Some(local),
+            tz => local.checked_add_signed(-(tz as i64) * 60),
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Wall-clock time sources.
+//!
+//! At early boot, one of the RTC backends is read once to get the current
+//! Unix time. The difference between that and the monotonic clock becomes the
+//! offset returned by [`axplat::time::TimeIf::epochoffset_nanos`].
+//!
+//! The backend is chosen by the `rtc-source` config, which can be overridden
+//! with the `rtc=<source>` boot argument. In `auto` mode every backend is
+//! probed in the order of [`BACKENDS`] and the first valid time wins.
+//!
+//! Logging is not up yet at early boot, so the outcome is only reported by
+//! [`init_later`].
+
+mod cmdline;
+pub(crate) mod cmos;
+mod efi;
+mod pl031;
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use axplat::time::{NANOS_PER_SEC, monotonic_time_nanos};
+use kspin::SpinNoIrq;
+use log::{info, warn};
+
+use crate::config::devices::RTC_SOURCE;
+
+/// Earliest time accepted from a backend (2000-01-01 00:00:00 UTC). Anything
+/// older means the clock was never set.
+const MIN_VALID_EPOCH_SECS: u64 = 946_684_800;
+
+static EPOCHOFFSET_NANOS: AtomicU64 = AtomicU64::new(0);
+static REPORT: SpinNoIrq<Option<Report>> = SpinNoIrq::new(None);
+
+/// A source of wall-clock time.
+pub trait RtcBackend: Sync {
+    /// Short name used in config, boot arguments and logs.
+    fn name(&self) -> &'static str;
+
+    /// Reads the current time in seconds since the Unix epoch, or returns
+    /// [`None`] if the device is absent or not responding.
+    fn read_epoch_secs(&self) -> Option<u64>;
+}
+
+/// Number of entries in [`BACKENDS`].
+const NR_BACKENDS: usize = 4;
+
+/// All known backends, in `auto` probing order.
+static BACKENDS: [&dyn RtcBackend; NR_BACKENDS] = [
+    &cmdline::CmdlineRtc,
+    &efi::EfiRtc,
+    &cmos::CmosRtc,
+    &pl031::Pl031Rtc,
+];
+
+/// Broken-down calendar time, in UTC unless stated otherwise.
+#[derive(Debug, Clone, Copy)]
+pub struct DateTime {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    /// Converts the date to seconds since the Unix epoch.
+    ///
+    /// Returns [`None`] for out-of-range fields or dates before 1970.
+    pub fn to_epoch_secs(self) -> Option<u64> {
+        if !(1..=12).contains(&self.month)
+            || !(1..=31).contains(&self.day)
+            || self.hour > 23
+            || self.minute > 59
+            || self.second > 60
+        {
+            return None;
+        }
+        // Days from civil, see <http://howardhinnant.github.io/date_algorithms.html>.
+        let y = self.year as i64 - (self.month <= 2) as i64;
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let m = self.month as i64;
+        let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+        let secs = days * 86_400
+            + self.hour as i64 * 3_600
+            + self.minute as i64 * 60
+            + self.second as i64;
+        u64::try_from(secs).ok()
+    }
+}
+
+/// What [`init_early`] found, for [`init_later`] to log.
+struct Report {
+    source: &'static str,
+    /// Index in [`BACKENDS`] and time of the backend used.
+    chosen: Option<(usize, u64)>,
+    /// Times rejected as bogus, by index in [`BACKENDS`].
+    bogus: [Option<u64>; NR_BACKENDS],
+}
+
+fn select(source: &'static str) -> Report {
+    let mut report = Report { source, chosen: None, bogus: [None; NR_BACKENDS] };
+    for (i, backend) in BACKENDS.iter().enumerate() {
+        if source != "auto" && source != backend.name() {
+            continue;
+        }
+        match backend.read_epoch_secs() {
+            Some(secs) if secs >= MIN_VALID_EPOCH_SECS => {
+                report.chosen = Some((i, secs));
+                break;
+            }
+            Some(secs) => report.bogus[i] = Some(secs),
+            None => {}
+        }
+    }
+    report
+}
+
+/// Reads the wall-clock time and records the epoch offset.
+///
+/// Must be called after the generic timer is initialized. Since some
+/// backends call into firmware or read the DTB by physical address, it must
+/// also be called while the boot page table is still active.
+pub fn init_early() {
+    let source = crate::bootargs::get("rtc").unwrap_or(RTC_SOURCE);
+    let report = select(source);
+    if let Some((_, secs)) = report.chosen {
+        let epoch_nanos = secs * NANOS_PER_SEC;
+        EPOCHOFFSET_NANOS.store(
+            epoch_nanos.saturating_sub(monotonic_time_nanos()),
+            Ordering::Relaxed,
+        );
+    }
+    *REPORT.lock() = Some(report);
+}
+
+/// Logs the time source picked by [`init_early`].
+pub fn init_later() {
+    let Some(report) = REPORT.lock().take() else {
+        return;
+    };
+    let status = efi::GET_TIME_ERROR.load(Ordering::Relaxed);
+    if status != 0 {
+        warn!("RTC: EFI GetTime failed with status {:#x}", status);
+    }
+    for (backend, secs) in BACKENDS.iter().zip(report.bogus) {
+        if let Some(secs) = secs {
+            warn!("RTC: {} backend reports bogus time {}s", backend.name(), secs);
+        }
+    }
+    match report.chosen {
+        Some((i, secs)) => info!("RTC: using {} backend, epoch {}s", BACKENDS[i].name(), secs),
+        None => warn!(
+            "RTC: no usable time source (rtc={}), wall clock starts at 1970",
+            report.source
+        ),
+    }
+}
+
+/// Returns the offset (in nanoseconds) between the monotonic clock and the
+/// Unix epoch, or `0` if no RTC was found.
+pub fn epochoffset_nanos() -> u64 {
+    EPOCHOFFSET_NANOS.load(Ordering::Relaxed)
+}
@@ -0,0 +1,28 @@
+//! ARM PrimeCell PL031 real time clock.
+
+use axplat::mem::{pa, phys_to_virt};
+
+use super::RtcBackend;
+use crate::config::devices::RTC_PADDR;
+
+/// Data register: the current counter value in seconds.
+const RTCDR: usize = 0x00;
+
+/// PL031 at `rtc-paddr`. Not present on the laptop, but handy under QEMU.
+pub struct Pl031Rtc;
+
+impl RtcBackend for Pl031Rtc {
+    fn name(&self) -> &'static str {
+        "pl031"
+    }
+
+    fn read_epoch_secs(&self) -> Option<u64> {
+        if RTC_PADDR == 0 {
+            return None;
+        }
+        let base = phys_to_virt(pa!(RTC_PADDR)).as_usize();
+        // SAFETY: `rtc-paddr` is in the MMIO ranges and mapped as device memory.
+        let secs = unsafe { core::ptr::read_volatile((base + RTCDR) as *const u32) };
+        Some(secs as u64)
+    }
+}
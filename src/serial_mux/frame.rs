@@ -0,0 +1,178 @@
+//! Frame format of the serial multiplexer.
+//!
+//! A frame carries up to [`MAX_PAYLOAD`] bytes for one channel:
+//!
+//! ```text
+//! 0x00 | COBS(channel | payload | CRC-16 big-endian) | 0x00
+//! ```
+//!
+//! The CRC is CRC-16/XMODEM over the channel byte and the payload. COBS
+//! encoding removes every zero byte from the frame body, so `0x00` only
+//! appears as a delimiter and a receiver resynchronizes on the next one after
+//! line noise. The leading delimiter separates the frame from any plain text
+//! sent before it, such as early boot output.
+//!
+//! This file only depends on `core` and `crate::crc16`: the host-side decoder
+//! in `tools/` builds it as well.
+
+use crate::crc16::crc16;
+
+/// Maximum number of payload bytes in a frame.
+pub const MAX_PAYLOAD: usize = 128;
+
+/// Maximum size of a frame body before COBS encoding.
+const MAX_RAW: usize = 1 + MAX_PAYLOAD + 2;
+
+/// Maximum size of a COBS-encoded frame body.
+const MAX_BODY: usize = MAX_RAW + MAX_RAW.div_ceil(254);
+
+/// Maximum size of an encoded frame, delimiters included.
+pub const MAX_FRAME: usize = MAX_BODY + 2;
+
+/// Encodes a frame for `channel` into `out`, returning its length.
+///
+/// # Panics
+///
+/// Panics if `payload` is longer than [`MAX_PAYLOAD`].
+pub fn encode(channel: u8, payload: &[u8], out: &mut [u8; MAX_FRAME]) -> usize {
+    assert!(payload.len() <= MAX_PAYLOAD);
+    let mut raw = [0; MAX_RAW];
+    raw[0] = channel;
+    raw[1..=payload.len()].copy_from_slice(payload);
+    let len = payload.len() + 1;
+    let crc = crc16(&raw[..len]);
+    raw[len..len + 2].copy_from_slice(&crc.to_be_bytes());
+
+    out[0] = 0;
+    let body = cobs_encode(&raw[..len + 2], &mut out[1..]);
+    out[body + 1] = 0;
+    body + 2
+}
+
+/// COBS-encodes `src` into `dst`, returning the encoded length.
+fn cobs_encode(src: &[u8], dst: &mut [u8]) -> usize {
+    let mut code_pos = 0;
+    let mut code = 1u8;
+    let mut out = 1;
+    for &b in src {
+        if b != 0 {
+            dst[out] = b;
+            out += 1;
+            code += 1;
+        }
+        if b == 0 || code == 0xff {
+            dst[code_pos] = code;
+            code_pos = out;
+            out += 1;
+            code = 1;
+        }
+    }
+    dst[code_pos] = code;
+    out
+}
+
+/// Decodes a COBS block into `dst`, or returns [`None`] if it is malformed.
+fn cobs_decode(src: &[u8], dst: &mut [u8]) -> Option<usize> {
+    let mut i = 0;
+    let mut out = 0;
+    while i < src.len() {
+        let code = src[i] as usize;
+        i += 1;
+        if code == 0 || i + code - 1 > src.len() || out + code - 1 > dst.len() {
+            return None;
+        }
+        dst[out..out + code - 1].copy_from_slice(&src[i..i + code - 1]);
+        out += code - 1;
+        i += code - 1;
+        if code != 0xff && i < src.len() {
+            *dst.get_mut(out)? = 0;
+            out += 1;
+        }
+    }
+    Some(out)
+}
+
+/// What the [`Decoder`] made of the bytes up to a delimiter.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// A valid frame.
+    Frame {
+        /// Channel the payload belongs to.
+        channel: u8,
+        /// Payload bytes.
+        payload: &'a [u8],
+    },
+    /// Bytes that are not a valid frame, such as plain text written before
+    /// the multiplexer was enabled or a frame damaged on the line. Long runs
+    /// are returned in several pieces.
+    Garbage(&'a [u8]),
+}
+
+/// Incremental frame decoder.
+pub struct Decoder {
+    buf: [u8; MAX_BODY],
+    len: usize,
+    /// Byte that arrived when `buf` was full, stored by the next call.
+    carry: Option<u8>,
+    /// Set once `buf` overflowed, until the next delimiter.
+    overflow: bool,
+    raw: [u8; MAX_RAW],
+}
+
+impl Decoder {
+    /// Creates a decoder waiting for the start of a frame.
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_BODY],
+            len: 0,
+            carry: None,
+            overflow: false,
+            raw: [0; MAX_RAW],
+        }
+    }
+
+    /// Feeds one received byte.
+    ///
+    /// Returns an [`Event`] when `byte` completes a frame or a run of
+    /// garbage.
+    pub fn push(&mut self, byte: u8) -> Option<Event<'_>> {
+        if let Some(c) = self.carry.take() {
+            self.buf[0] = c;
+            self.len = 1;
+        }
+        if byte != 0 {
+            if self.len == MAX_BODY {
+                // Too long for a frame: hand out what came so far.
+                self.overflow = true;
+                self.carry = Some(byte);
+                return Some(Event::Garbage(&self.buf));
+            }
+            self.buf[self.len] = byte;
+            self.len += 1;
+            return None;
+        }
+        let len = core::mem::take(&mut self.len);
+        let overflow = core::mem::take(&mut self.overflow);
+        if len == 0 {
+            return None;
+        }
+        let body = &self.buf[..len];
+        if !overflow
+            && let Some(n) = cobs_decode(body, &mut self.raw)
+            && n >= 3
+            && crc16(&self.raw[..n - 2]) == u16::from_be_bytes([self.raw[n - 2], self.raw[n - 1]])
+        {
+            return Some(Event::Frame {
+                channel: self.raw[0],
+                payload: &self.raw[1..n - 2],
+            });
+        }
+        Some(Event::Garbage(body))
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,154 @@
+//! Logical channels multiplexed over the PL011 line.
+//!
+//! When enabled, everything written to the UART is wrapped in [`frame`]s
+//! tagged with a channel number, and received frames are sorted into one
+//! ring buffer per channel. The console (shell input and kernel output) uses
+//! [`CONSOLE`], leveled output of [`crate::console::write_log`] goes to
+//! [`LOG`], and [`TRACE`] is free for binary data. `tools/serial_mux_decode.rs`
+//! splits the channels apart on the host.
+//!
+//! The line stays plain text unless the `serial-mux` config or the
+//! `serialmux=on|off` boot argument says otherwise. A break condition still
+//! starts a SysRq command in either mode, but GDB break-in with `Ctrl-C` on
+//! the console UART only works in plain text mode.
+
+pub mod frame;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use kspin::SpinNoIrq;
+
+use crate::config::devices::{SERIAL_MUX, SERIAL_MUX_RX_BUF_SIZE};
+use crate::ringbuf::RingBuffer;
+
+/// Console channel: shell input, kernel output.
+pub const CONSOLE: u8 = 0;
+/// Kernel log messages.
+pub const LOG: u8 = 1;
+/// Binary trace data.
+pub const TRACE: u8 = 2;
+
+/// Number of channels with a receive buffer. Frames for higher channels are
+/// dropped.
+pub const MAX_CHANNELS: usize = 8;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static DECODER: SpinNoIrq<frame::Decoder> = SpinNoIrq::new(frame::Decoder::new());
+
+static RX_BUFS: [SpinNoIrq<RingBuffer<u8, SERIAL_MUX_RX_BUF_SIZE>>; MAX_CHANNELS] =
+    [const { SpinNoIrq::new(RingBuffer::new()) }; MAX_CHANNELS];
+static RX_OVERRUNS: [AtomicUsize; MAX_CHANNELS] = [const { AtomicUsize::new(0) }; MAX_CHANNELS];
+static RX_BAD_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns whether the line is multiplexed.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Writes bytes to a channel, split into as many frames as needed.
+///
+/// In plain text mode, [`CONSOLE`] data is written as is and data for the
+/// other channels is discarded.
+pub fn write(channel: u8, bytes: &[u8]) {
+    if !is_enabled() {
+        if channel == CONSOLE {
+            crate::pl011::write_raw(bytes);
+        }
+        return;
+    }
+    let mut out = [0; frame::MAX_FRAME];
+    for chunk in bytes.chunks(frame::MAX_PAYLOAD) {
+        let len = frame::encode(channel, chunk, &mut out);
+        // One frame per call, so concurrent writers do not interleave
+        // within a frame.
+        crate::pl011::write_raw(&out[..len]);
+    }
+}
+
+/// Reads bytes received on a channel into `buf`, returning how many were
+/// read.
+///
+/// Only returns what was buffered already: with the `irq` feature the RX
+/// interrupt fills the buffers, otherwise call [`crate::pl011::getchar`] to
+/// poll the UART.
+pub fn read(channel: u8, buf: &mut [u8]) -> usize {
+    let Some(rx) = RX_BUFS.get(channel as usize) else {
+        return 0;
+    };
+    let mut rx = rx.lock();
+    let mut len = 0;
+    for dst in buf.iter_mut() {
+        let Some(c) = rx.pop() else {
+            break;
+        };
+        *dst = c;
+        len += 1;
+    }
+    len
+}
+
+/// Reads a byte received on a channel.
+pub fn getchar(channel: u8) -> Option<u8> {
+    RX_BUFS.get(channel as usize)?.lock().pop()
+}
+
+/// Returns how many bytes of a channel were dropped because its receive
+/// buffer was full.
+pub fn rx_overruns(channel: u8) -> usize {
+    RX_OVERRUNS
+        .get(channel as usize)
+        .map_or(0, |c| c.load(Ordering::Relaxed))
+}
+
+/// Returns how many received runs of bytes were not valid frames.
+pub fn rx_bad_frames() -> usize {
+    RX_BAD_FRAMES.load(Ordering::Relaxed)
+}
+
+/// Feeds a byte received by the UART to the frame decoder.
+///
+/// Returns `true` if it completed a frame.
+pub(crate) fn receive(byte: u8) -> bool {
+    let mut decoder = DECODER.lock();
+    match decoder.push(byte) {
+        None => false,
+        Some(frame::Event::Garbage(_)) => {
+            RX_BAD_FRAMES.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+        Some(frame::Event::Frame { channel, payload }) => {
+            let Some(rx) = RX_BUFS.get(channel as usize) else {
+                return false;
+            };
+            let mut rx = rx.lock();
+            for &c in payload {
+                if !rx.push(c) {
+                    RX_OVERRUNS[channel as usize].fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            true
+        }
+    }
+}
+
+/// Writes a frame directly through `putchar`, without taking any lock.
+///
+/// Used by the UART emergency path.
+pub(crate) fn emergency_write(channel: u8, bytes: &[u8], mut putchar: impl FnMut(u8)) {
+    let mut out = [0; frame::MAX_FRAME];
+    for chunk in bytes.chunks(frame::MAX_PAYLOAD) {
+        let len = frame::encode(channel, chunk, &mut out);
+        out[..len].iter().for_each(|&c| putchar(c));
+    }
+}
+
+/// Enables multiplexing if the config or the command line asks for it.
+pub(crate) fn init() {
+    let enabled = match crate::bootargs::get("serialmux") {
+        Some("on") => true,
+        Some("off") => false,
+        _ => SERIAL_MUX,
+    };
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
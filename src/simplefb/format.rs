@@ -0,0 +1,241 @@
+//! Framebuffer pixel formats.
+//!
+//! This file only depends on `core`, so that the `host-tests` crate can
+//! build it for the host and run the tests below.
+
+/// Layout of a framebuffer pixel, named as in the `simple-framebuffer`
+/// device tree binding: components from the most to the least significant
+/// bits of a little-endian pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    R5G6B5,
+    X8R8G8B8,
+    A8R8G8B8,
+    X8B8G8R8,
+    A8B8G8R8,
+    X2R10G10B10,
+}
+
+impl PixelFormat {
+    /// Every format, in declaration order.
+    pub const ALL: [Self; 6] = [
+        Self::R5G6B5,
+        Self::X8R8G8B8,
+        Self::A8R8G8B8,
+        Self::X8B8G8R8,
+        Self::A8B8G8R8,
+        Self::X2R10G10B10,
+    ];
+
+    /// Returns the binding name, e.g. `x8r8g8b8`.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::R5G6B5 => "r5g6b5",
+            Self::X8R8G8B8 => "x8r8g8b8",
+            Self::A8R8G8B8 => "a8r8g8b8",
+            Self::X8B8G8R8 => "x8b8g8r8",
+            Self::A8B8G8R8 => "a8b8g8r8",
+            Self::X2R10G10B10 => "x2r10g10b10",
+        }
+    }
+
+    /// Looks up a format by its binding name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.name() == name)
+    }
+
+    /// Returns the size of a pixel in bytes.
+    pub const fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::R5G6B5 => 2,
+            _ => 4,
+        }
+    }
+
+    /// Packs a `0x00RRGGBB` color into a pixel of this format, in the low
+    /// [`bytes_per_pixel`](Self::bytes_per_pixel) bytes. Alpha is opaque.
+    pub const fn pack(self, rgb: u32) -> u32 {
+        let (r, g, b) = ((rgb >> 16) & 0xff, (rgb >> 8) & 0xff, rgb & 0xff);
+        match self {
+            Self::R5G6B5 => (r >> 3) << 11 | (g >> 2) << 5 | b >> 3,
+            Self::X8R8G8B8 => rgb & 0xff_ffff,
+            Self::A8R8G8B8 => 0xff00_0000 | (rgb & 0xff_ffff),
+            Self::X8B8G8R8 => b << 16 | g << 8 | r,
+            Self::A8B8G8R8 => 0xff00_0000 | b << 16 | g << 8 | r,
+            Self::X2R10G10B10 => widen(r) << 20 | widen(g) << 10 | widen(b),
+        }
+    }
+
+    /// Unpacks a pixel of this format into a `0x00RRGGBB` color.
+    pub const fn unpack(self, pixel: u32) -> u32 {
+        let (r, g, b) = match self {
+            Self::R5G6B5 => {
+                let (r, g, b) = ((pixel >> 11) & 0x1f, (pixel >> 5) & 0x3f, pixel & 0x1f);
+                (r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2)
+            }
+            Self::X8R8G8B8 | Self::A8R8G8B8 => {
+                ((pixel >> 16) & 0xff, (pixel >> 8) & 0xff, pixel & 0xff)
+            }
+            Self::X8B8G8R8 | Self::A8B8G8R8 => {
+                (pixel & 0xff, (pixel >> 8) & 0xff, (pixel >> 16) & 0xff)
+            }
+            Self::X2R10G10B10 => ((pixel >> 22) & 0xff, (pixel >> 12) & 0xff, (pixel >> 2) & 0xff),
+        };
+        r << 16 | g << 8 | b
+    }
+
+    /// Packs a row of `0x00RRGGBB` colors into little-endian pixels of this
+    /// format. Stops at the end of the shorter of the two.
+    pub fn pack_row(self, src: &[u32], dst: &mut [u8]) {
+        let bpp = self.bytes_per_pixel();
+        for (&rgb, out) in src.iter().zip(dst.chunks_exact_mut(bpp)) {
+            out.copy_from_slice(&self.pack(rgb).to_le_bytes()[..bpp]);
+        }
+    }
+}
+
+/// Widens an 8-bit component to 10 bits, mapping 0xff to 0x3ff.
+const fn widen(c: u32) -> u32 {
+    c << 2 | c >> 6
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    const WHITE: u32 = 0xff_ffff;
+    const RED: u32 = 0xff_0000;
+    const GREEN: u32 = 0x00_ff00;
+    const BLUE: u32 = 0x00_00ff;
+    /// A color that does not survive the 16-bit format unchanged.
+    const ODD: u32 = 0x12_3456;
+
+    /// Packs rows into a buffer with `stride` bytes per row, as the
+    /// framebuffer would be written, and returns row `y`.
+    fn render(format: PixelFormat, rows: &[&[u32]], stride: usize, y: usize) -> Vec<u8> {
+        let mut fb = vec![0xaa; stride * rows.len()];
+        for (i, row) in rows.iter().enumerate() {
+            format.pack_row(row, &mut fb[i * stride..(i + 1) * stride]);
+        }
+        fb[y * stride..(y + 1) * stride].to_vec()
+    }
+
+    #[test]
+    fn names() {
+        for format in PixelFormat::ALL {
+            assert_eq!(PixelFormat::from_name(format.name()), Some(format));
+        }
+        assert_eq!(PixelFormat::from_name("c8"), None);
+    }
+
+    #[test]
+    fn pack_unpack() {
+        let expected: [(PixelFormat, [u32; 5]); 6] = [
+            (
+                PixelFormat::R5G6B5,
+                [0xffff, 0xf800, 0x07e0, 0x001f, 0x11aa],
+            ),
+            (
+                PixelFormat::X8R8G8B8,
+                [0xff_ffff, 0xff_0000, 0x00_ff00, 0x00_00ff, 0x12_3456],
+            ),
+            (
+                PixelFormat::A8R8G8B8,
+                [
+                    0xffff_ffff,
+                    0xffff_0000,
+                    0xff00_ff00,
+                    0xff00_00ff,
+                    0xff12_3456,
+                ],
+            ),
+            (
+                PixelFormat::X8B8G8R8,
+                [0xff_ffff, 0x00_00ff, 0x00_ff00, 0xff_0000, 0x56_3412],
+            ),
+            (
+                PixelFormat::A8B8G8R8,
+                [
+                    0xffff_ffff,
+                    0xff00_00ff,
+                    0xff00_ff00,
+                    0xffff_0000,
+                    0xff56_3412,
+                ],
+            ),
+            (
+                PixelFormat::X2R10G10B10,
+                [
+                    0x3fff_ffff,
+                    0x3ff0_0000,
+                    0x000f_fc00,
+                    0x0000_03ff,
+                    0x0483_4159,
+                ],
+            ),
+        ];
+        for (format, pixels) in expected {
+            for (rgb, pixel) in [WHITE, RED, GREEN, BLUE, ODD].into_iter().zip(pixels) {
+                assert_eq!(
+                    format.pack(rgb),
+                    pixel,
+                    "{}: pack {:06x}",
+                    format.name(),
+                    rgb
+                );
+            }
+            for rgb in [0, WHITE, RED, GREEN, BLUE] {
+                assert_eq!(
+                    format.unpack(format.pack(rgb)),
+                    rgb,
+                    "{}: round trip",
+                    format.name()
+                );
+            }
+            // The top byte is ignored.
+            assert_eq!(format.pack(0xff00_0000 | ODD), format.pack(ODD));
+        }
+        // The 16-bit round trip is only close.
+        assert_eq!(
+            PixelFormat::R5G6B5.unpack(PixelFormat::R5G6B5.pack(ODD)),
+            0x10_3452
+        );
+        // 10-bit components keep the 8 bits.
+        assert_eq!(
+            PixelFormat::X2R10G10B10.unpack(PixelFormat::X2R10G10B10.pack(ODD)),
+            ODD
+        );
+    }
+
+    #[test]
+    fn pack_rows() {
+        // Two rows of two pixels, with padding at the end of each row.
+        let rows: &[&[u32]] = &[&[RED, GREEN], &[BLUE, WHITE]];
+        assert_eq!(
+            render(PixelFormat::R5G6B5, rows, 6, 1),
+            [0x1f, 0x00, 0xff, 0xff, 0xaa, 0xaa]
+        );
+        // Pixels are little-endian.
+        assert_eq!(
+            render(PixelFormat::X8R8G8B8, rows, 8, 0),
+            [0x00, 0x00, 0xff, 0x00, 0x00, 0xff, 0x00, 0x00]
+        );
+        assert_eq!(
+            render(PixelFormat::A8B8G8R8, rows, 10, 0),
+            [0xff, 0x00, 0x00, 0xff, 0x00, 0xff, 0x00, 0xff, 0xaa, 0xaa]
+        );
+        assert_eq!(
+            render(PixelFormat::X2R10G10B10, rows, 12, 1),
+            [
+                0xff, 0x03, 0x00, 0x00, 0xff, 0xff, 0xff, 0x3f, 0xaa, 0xaa, 0xaa, 0xaa
+            ]
+        );
+        // A short destination is not overrun.
+        let mut short = [0xaa; 3];
+        PixelFormat::R5G6B5.pack_row(&[WHITE, WHITE], &mut short);
+        assert_eq!(short, [0xff, 0xff, 0xaa]);
+    }
+}
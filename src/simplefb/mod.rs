@@ -0,0 +1,440 @@
+//! Framebuffer console on the framebuffer set up by the firmware.
+//!
+//! The mode (base, size, geometry and pixel format) is found at early boot,
+//! and [`mode()`] returns it. Besides the console, kernels can draw on the
+//! screen with [`fill_rect`] and [`draw_image`].
+//!
+//! The console renderer, the logo and the drawing functions all draw
+//! `0x00RRGGBB` pixels in rows of exactly the screen width. That is drawn
+//! straight to `x8r8g8b8` framebuffers without row padding; for any other
+//! mode it goes to a shadow buffer instead, whose changed rows are converted
+//! to the [`PixelFormat`] of the mode and copied to the framebuffer after
+//! each write. Only the rows a write drew on are converted, unless the
+//! console scrolled.
+
+mod format;
+mod mode;
+
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use axplat::mem::{pa, phys_to_virt};
+use kspin::SpinNoIrq;
+use lazyinit::LazyInit;
+use simplefb::{FramebufferConfig, LogBuffer, SimpleFbConsole};
+
+pub use format::PixelFormat;
+pub use mode::Mode;
+
+struct Framebuffer {
+    console: SimpleFbConsole,
+    /// Where the pixels are drawn: the framebuffer or the shadow buffer.
+    base: usize,
+    width: usize,
+    height: usize,
+    shadow: Option<Shadow>,
+}
+
+impl Framebuffer {
+    fn present(&mut self) {
+        if let Some(shadow) = &mut self.shadow {
+            shadow.present();
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.console.write_bytes(bytes);
+        if let Some(shadow) = &mut self.shadow {
+            let rows = shadow.cursor.advance(bytes);
+            shadow.mark(rows);
+        }
+        self.present();
+    }
+
+    /// Draws the visible part of a rectangle, taking each pixel from
+    /// `pixel(column, row)`.
+    fn draw(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        pixel: impl Fn(usize, usize) -> u32,
+    ) {
+        let width = width.min(self.width.saturating_sub(x));
+        let height = height.min(self.height.saturating_sub(y));
+        if let Some(shadow) = &mut self.shadow {
+            shadow.mark(y..y + height);
+        }
+        let base = self.base as *mut u32;
+        for row in 0..height {
+            for col in 0..width {
+                // SAFETY: the pixel lies within the drawing area.
+                unsafe {
+                    base.add((y + row) * self.width + x + col).write_volatile(pixel(col, row));
+                }
+            }
+        }
+        self.present();
+    }
+}
+
+/// Off-screen copy of the screen, in `0x00RRGGBB` pixels and rows of exactly
+/// the screen width.
+struct Shadow {
+    pixels: Vec<u32>,
+    /// Rows drawn on since the last copy to the framebuffer.
+    dirty: Range<usize>,
+    cursor: TextCursor,
+    /// A row packed in the framebuffer format, before copying it out.
+    packed: Vec<u8>,
+    mode: Mode,
+    vaddr: usize,
+}
+
+impl Shadow {
+    fn new(mode: Mode, vaddr: usize, font_height: usize) -> Self {
+        Self {
+            pixels: alloc::vec![0; mode.width * mode.height],
+            dirty: 0..mode.height,
+            cursor: TextCursor::new(mode.width, mode.height, font_height),
+            packed: alloc::vec![0; mode.width * mode.format.bytes_per_pixel()],
+            mode,
+            vaddr,
+        }
+    }
+
+    /// Adds `rows` to the rows to copy out.
+    fn mark(&mut self, rows: Range<usize>) {
+        if rows.is_empty() {
+            return;
+        }
+        self.dirty = if self.dirty.is_empty() {
+            rows
+        } else {
+            self.dirty.start.min(rows.start)..self.dirty.end.max(rows.end)
+        };
+    }
+
+    /// Copies the rows drawn on since the last call to the framebuffer.
+    fn present(&mut self) {
+        let bpp = self.mode.format.bytes_per_pixel();
+        let rows = core::mem::replace(&mut self.dirty, 0..0);
+        let rows = rows.start..rows.end.min(self.mode.height);
+        for y in rows {
+            let row = &self.pixels[y * self.mode.width..][..self.mode.width];
+            self.mode.format.pack_row(row, &mut self.packed);
+            let dst = self.vaddr + y * self.mode.stride;
+            // One access per pixel, the framebuffer may be device memory.
+            for (x, pixel) in self.packed.chunks_exact(bpp).enumerate() {
+                // SAFETY: the row lies within the framebuffer, as checked when
+                // the mode was probed, and rows start at a multiple of the
+                // pixel size.
+                unsafe {
+                    match *pixel {
+                        [a, b] => {
+                            ((dst + x * 2) as *mut u16).write_volatile(u16::from_le_bytes([a, b]))
+                        }
+                        [a, b, c, d] => ((dst + x * 4) as *mut u32)
+                            .write_volatile(u32::from_le_bytes([a, b, c, d])),
+                        _ => unreachable!(),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Cursor of the [`SimpleFbConsole`], which does not expose its own, to tell
+/// which rows a write draws on. Follows its handling of control characters
+/// and ANSI sequences.
+struct TextCursor {
+    col: usize,
+    row: usize,
+    cols: usize,
+    rows: usize,
+    font_height: usize,
+    /// Screen height in pixels, all of which scrolling moves.
+    height: usize,
+    /// Inside an ANSI sequence: after `ESC` (1) or `ESC [` (2).
+    escape: u8,
+}
+
+impl TextCursor {
+    fn new(width: usize, height: usize, font_height: usize) -> Self {
+        // The console falls back to 8 pixels, and its glyphs are square.
+        let font_height = if font_height == 0 { 8 } else { font_height };
+        Self {
+            col: 0,
+            row: 0,
+            cols: width / font_height,
+            rows: height / font_height,
+            font_height,
+            height,
+            escape: 0,
+        }
+    }
+
+    fn new_line(&mut self) -> bool {
+        self.col = 0;
+        self.row += 1;
+        let scrolled = self.row >= self.rows;
+        if scrolled {
+            self.row = self.rows.saturating_sub(1);
+        }
+        scrolled
+    }
+
+    /// Moves past a visible character, adding its row to `drawn`. Returns
+    /// whether the console scrolled.
+    fn put(&mut self, drawn: &mut Range<usize>) -> bool {
+        let scrolled = self.col >= self.cols && self.new_line();
+        if Range::is_empty(drawn) {
+            drawn.start = self.row;
+        }
+        drawn.end = self.row + 1;
+        self.col += 1;
+        scrolled
+    }
+
+    /// Moves over `bytes` written to the console, and returns the pixel
+    /// rows drawn on: the whole screen if it scrolled.
+    fn advance(&mut self, bytes: &[u8]) -> Range<usize> {
+        // Without scrolling, the cursor only moves down, so the drawn rows
+        // start at the first one drawn on.
+        let mut drawn = 0..0;
+        let mut scrolled = false;
+        for &c in bytes {
+            match (self.escape, c) {
+                (0, 0x1b) => self.escape = 1,
+                (0, b'\n') => scrolled |= self.new_line(),
+                (0, b'\r') => self.col = 0,
+                (0, b'\t') => {
+                    for _ in 0..4 - self.col % 4 {
+                        scrolled |= self.put(&mut drawn);
+                    }
+                }
+                (0, _) => scrolled |= self.put(&mut drawn),
+                (1, b'[') => self.escape = 2,
+                (2, b'0'..=b'9' | b';') => {}
+                _ => self.escape = 0,
+            }
+        }
+        if scrolled {
+            0..self.height
+        } else {
+            drawn.start * self.font_height..drawn.end * self.font_height
+        }
+    }
+}
+
+static MODE: LazyInit<Mode> = LazyInit::new();
+static SIMPLEFB: LazyInit<SpinNoIrq<Framebuffer>> = LazyInit::new();
+static mut LOG_BUFFER_STORAGE: [u8; 64 * 1024] = [0; 64 * 1024];
+const LOGO_PNG: &[u8] = include_bytes!("../../assets/arceos.png");
+
+/// Display the picture centered on a white background
+fn display_logo(config: &FramebufferConfig, width: usize, height: usize, data: &[u32]) {
+    // Fill screen with white background
+    unsafe {
+        let ptr = config.base_addr as *mut u32;
+        let total_pixels = config.width * config.height;
+        for i in 0..total_pixels {
+            core::ptr::write_volatile(ptr.add(i), 0xFFFFFF); // White
+        }
+    }
+
+    // Calculate centered position
+    let x_offset = (config.width.saturating_sub(width)) / 2;
+    let y_offset = (config.height.saturating_sub(height)) / 2;
+
+    simplefb::picture::draw_picture(config, x_offset, y_offset, width, height, data);
+}
+
+/// Delay function (simple busy-wait)
+fn simple_delay(count: usize) {
+    // Delay 1s
+    let freq: u64;
+    let start: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, cntfrq_el0", out(reg) freq);
+        core::arch::asm!("mrs {}, cntpct_el0", out(reg) start);
+    }
+    let target = start.wrapping_add(freq.wrapping_mul(count as u64));
+    loop {
+        let current: u64;
+        unsafe {
+            core::arch::asm!("mrs {}, cntpct_el0", out(reg) current);
+        }
+        if current >= target {
+            break;
+        }
+    }
+}
+
+/// Decode embedded PNG data
+/// Return: (width, height, pixel_data)
+fn decode_png(bytes: &[u8]) -> Option<(usize, usize, alloc::vec::Vec<u32>)> {
+    if let Ok(header) = minipng::decode_png_header(bytes) {
+        let mut buffer = alloc::vec![0u8; header.required_bytes()];
+        if let Ok(image) = minipng::decode_png(bytes, &mut buffer) {
+            let width = image.width() as usize;
+            let height = image.height() as usize;
+            let pixels = image.pixels();
+
+            // Convert to u32 (ARGB/BGRA)
+            let mut data = alloc::vec![0u32; width * height];
+
+            match image.color_type() {
+                minipng::ColorType::Rgba => {
+                    for i in 0..width * height {
+                        if i * 4 + 3 < pixels.len() {
+                            let r = pixels[i * 4] as u32;
+                            let g = pixels[i * 4 + 1] as u32;
+                            let b = pixels[i * 4 + 2] as u32;
+                            let a = pixels[i * 4 + 3] as u32;
+
+                            // Alpha blending with white background
+                            let bg = 255u32;
+                            let r = (r * a + bg * (255 - a)) / 255;
+                            let g = (g * a + bg * (255 - a)) / 255;
+                            let b = (b * a + bg * (255 - a)) / 255;
+
+                            data[i] = (r << 16) | (g << 8) | b;
+                        }
+                    }
+                }
+                minipng::ColorType::Rgb => {
+                    for i in 0..width * height {
+                        if i * 3 + 2 < pixels.len() {
+                            let r = pixels[i * 3] as u32;
+                            let g = pixels[i * 3 + 1] as u32;
+                            let b = pixels[i * 3 + 2] as u32;
+                            data[i] = (r << 16) | (g << 8) | b;
+                        }
+                    }
+                }
+                minipng::ColorType::GrayAlpha => {
+                    for i in 0..width * height {
+                        if i * 2 + 1 < pixels.len() {
+                            let gray = pixels[i * 2] as u32;
+                            let a = pixels[i * 2 + 1] as u32;
+                            let bg = 255u32;
+                            let val = (gray * a + bg * (255 - a)) / 255;
+                            data[i] = (val << 16) | (val << 8) | val;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            return Some((width, height, data));
+        }
+    }
+    None
+}
+
+fn show_logo(config: &FramebufferConfig, shadow: Option<&mut Shadow>) {
+    if let Some((width, height, data)) = decode_png(LOGO_PNG) {
+        display_logo(config, width, height, &data);
+        if let Some(shadow) = shadow {
+            shadow.mark(0..config.height);
+            shadow.present();
+        }
+        simple_delay(1); // Display for 1 seconds
+    }
+}
+
+/// Finds the framebuffer mode. Called from
+/// [`axplat::init::InitIf::init_early`], as the EFI tables are only
+/// reachable then; what it finds is logged by [`init`].
+pub(crate) fn probe() {
+    MODE.init_once(mode::probe());
+}
+
+/// Returns the framebuffer mode, once probed.
+///
+/// [`fill_rect`] and [`draw_image`] take `0x00RRGGBB` colors whatever the
+/// [`PixelFormat`] of the mode, which only matters to kernels accessing the
+/// framebuffer directly.
+pub fn mode() -> Option<Mode> {
+    MODE.get().copied()
+}
+
+/// Shows the logo, then sets up the console with glyphs `font_height`
+/// pixels high.
+pub(crate) fn init(font_height: usize) {
+    let Some(&mode) = MODE.get() else {
+        return;
+    };
+    mode::log_probe(&mode);
+    let vaddr = phys_to_virt(pa!(mode.paddr)).as_usize();
+    let direct = mode.format == PixelFormat::X8R8G8B8 && mode.stride == mode.width * 4;
+    let mut shadow = (!direct).then(|| Shadow::new(mode, vaddr, font_height));
+    let config = FramebufferConfig {
+        base_addr: shadow.as_ref().map_or(vaddr, |s| s.pixels.as_ptr() as usize),
+        width: mode.width,
+        height: mode.height,
+        font_height,
+    };
+
+    // Decode and display logo
+    show_logo(&config, shadow.as_mut());
+
+    // Initialize LogBuffer
+    // SAFETY: We are in initialization code, single threaded context assumed or handled by caller
+    let storage = core::ptr::addr_of!(LOG_BUFFER_STORAGE).cast_mut();
+    // SAFETY: storage is valid and has static lifetime
+    let log_buffer = LogBuffer::new(storage);
+
+    let base = config.base_addr;
+    let console = SimpleFbConsole::new(config, log_buffer);
+    let mut fb = Framebuffer { console, base, width: mode.width, height: mode.height, shadow };
+    fb.console.clear();
+    if let Some(shadow) = &mut fb.shadow {
+        shadow.mark(0..mode.height);
+    }
+    fb.present();
+    SIMPLEFB.init_once(SpinNoIrq::new(fb));
+}
+
+/// Fills a rectangle with a `0x00RRGGBB` color.
+///
+/// Parts outside the screen are clipped. The console draws over it when it
+/// scrolls.
+pub fn fill_rect(x: usize, y: usize, width: usize, height: usize, rgb: u32) {
+    if SIMPLEFB.is_inited() {
+        SIMPLEFB.lock().draw(x, y, width, height, |_, _| rgb);
+    }
+}
+
+/// Draws an image of `0x00RRGGBB` pixels, stored row by row, with its top
+/// left corner at (`x`, `y`).
+///
+/// Parts outside the screen are clipped, as are rows missing from `pixels`.
+/// The console draws over it when it scrolls.
+pub fn draw_image(x: usize, y: usize, width: usize, height: usize, pixels: &[u32]) {
+    let height = height.min(pixels.len().checked_div(width).unwrap_or(0));
+    if SIMPLEFB.is_inited() {
+        SIMPLEFB.lock().draw(x, y, width, height, |col, row| pixels[row * width + col]);
+    }
+}
+
+/// The framebuffer as a console output device.
+pub static CONSOLE_SINK: crate::console::ConsoleSink =
+    crate::console::ConsoleSink::new("fb0", write_bytes);
+
+/// Writes bytes to the framebuffer console.
+pub fn write_bytes(bytes: &[u8]) {
+    if SIMPLEFB.is_inited() {
+        SIMPLEFB.lock().write_bytes(bytes);
+    }
+}
+
+/// Writes bytes to the framebuffer console, breaking the lock if needed.
+///
+/// Used by the panic path, see [`crate::console::emergency_write`].
+pub fn emergency_write(bytes: &[u8]) {
+    if SIMPLEFB.is_inited() {
+        crate::console::lock_or_break(&*SIMPLEFB).write_bytes(bytes);
+    }
+}
@@ -0,0 +1,261 @@
+//! Discovery of the framebuffer mode set up by the firmware.
+//!
+//! In order, the first valid mode found in:
+//!
+//! 1. a `simple-framebuffer` node under `/chosen`, then anywhere in the DTB;
+//! 2. the `screen_info` EFI configuration table
+//!    (`LINUX_EFI_SCREEN_INFO_TABLE_GUID`);
+//! 3. the `simplefb-*` config.
+//!
+//! The `screen_info` table is not a UEFI table: the Linux EFI stub installs
+//! it from the GOP mode before starting the kernel, and firmware never does.
+//! It is only found when the kernel image is started by a Linux-style EFI
+//! stub or a loader that copies what it does; booted straight from the
+//! firmware, the mode must come from the DTB or the config.
+//!
+//! A mode is only used if the framebuffer lies in the mapped MMIO or RAM
+//! ranges.
+//!
+//! The probe runs before logging is up, so what it finds is logged later by
+//! [`log_probe`].
+
+use kspin::SpinNoIrq;
+use log::{info, warn};
+
+use super::PixelFormat;
+use crate::config::devices::{
+    MMIO_RANGES, SIMPLEFB_FORMAT, SIMPLEFB_HEIGHT, SIMPLEFB_PADDR, SIMPLEFB_STRIDE,
+    SIMPLEFB_WIDTH,
+};
+use crate::config::plat::{PHYS_MEMORY_BASE, PHYS_MEMORY_SIZE};
+use crate::fdt::FdtNode;
+
+/// `LINUX_EFI_SCREEN_INFO_TABLE_GUID`, in memory layout.
+const SCREEN_INFO_TABLE_GUID: [u8; 16] = [
+    0x0a, 0xc2, 0x3f, 0xe0, 0xdc, 0x85, 0x6e, 0x40, 0xb9, 0x0e, 0x4a, 0xb5, 0x02, 0x37, 0x1d,
+    0x95,
+];
+/// Offsets in `struct screen_info`.
+const SI_IS_VGA: usize = 15;
+const SI_LFB_WIDTH: usize = 18;
+const SI_LFB_HEIGHT: usize = 20;
+const SI_LFB_DEPTH: usize = 22;
+const SI_LFB_BASE: usize = 24;
+const SI_LFB_SIZE: usize = 28;
+const SI_LFB_LINELENGTH: usize = 36;
+const SI_RED_SIZE: usize = 38;
+const SI_CAPABILITIES: usize = 54;
+const SI_EXT_LFB_BASE: usize = 58;
+/// `orig_video_isVGA` of an EFI framebuffer.
+const VIDEO_TYPE_EFI: u8 = 0x70;
+/// `capabilities`: `ext_lfb_base` holds the high half of the base.
+const VIDEO_CAPABILITY_64BIT_BASE: u32 = 1 << 1;
+
+/// A linear framebuffer mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mode {
+    /// Physical address of the framebuffer.
+    pub paddr: usize,
+    /// Size of the framebuffer memory in bytes.
+    pub size: usize,
+    /// Visible size in pixels.
+    pub width: usize,
+    pub height: usize,
+    /// Bytes from one row to the next.
+    pub stride: usize,
+    pub format: PixelFormat,
+}
+
+impl Mode {
+    fn is_valid(&self) -> bool {
+        self.width > 0
+            && self.height > 0
+            && self.stride >= self.width * self.format.bytes_per_pixel()
+            && self.stride.checked_mul(self.height).is_some_and(|len| len <= self.size)
+    }
+
+    /// Returns whether the framebuffer lies in a mapped range. Its base and
+    /// size come from the firmware, so their sum may overflow.
+    fn is_mapped(&self) -> bool {
+        let Some(end) = self.paddr.checked_add(self.size) else {
+            return false;
+        };
+        MMIO_RANGES
+            .iter()
+            .chain(&[(PHYS_MEMORY_BASE, PHYS_MEMORY_SIZE)])
+            .any(|&(base, size)| base <= self.paddr && end <= base + size)
+    }
+}
+
+/// A mode passed over by [`probe`].
+#[derive(Debug, Clone, Copy)]
+enum Skipped {
+    /// The DTB `format` is not supported.
+    DtbFormat(&'static str),
+    /// The EFI pixel depth and component sizes and positions are not
+    /// supported.
+    EfiFormat(usize, [usize; 6]),
+    /// The `simplefb-format` config is unknown.
+    ConfigFormat,
+    Invalid(&'static str, Mode),
+    Unmapped(&'static str, Mode),
+}
+
+/// What [`probe`] found, for [`log_probe`].
+struct Report {
+    source: &'static str,
+    /// At most one per source.
+    skipped: [Option<Skipped>; 3],
+}
+
+static REPORT: SpinNoIrq<Report> =
+    SpinNoIrq::new(Report { source: "config", skipped: [None; 3] });
+
+fn skip(skipped: Skipped) {
+    let mut report = REPORT.lock();
+    if let Some(slot) = report.skipped.iter_mut().find(|s| s.is_none()) {
+        *slot = Some(skipped);
+    }
+}
+
+fn from_dtb() -> Option<Mode> {
+    let fdt = crate::fdt::get()?;
+    let usable = |node: &FdtNode| {
+        node.is_compatible("simple-framebuffer")
+            && node.property_str("status").is_none_or(|s| s == "okay" || s == "ok")
+    };
+    let (parent, node) = fdt
+        .find_node("/chosen")
+        .and_then(|chosen| Some((chosen, chosen.children().find(usable)?)))
+        .or_else(|| fdt.find_with_parent(usable))?;
+    let (paddr, size) = node.reg_cells(0, parent.cells())?;
+    let format = node.property_str("format")?;
+    let Some(format) = PixelFormat::from_name(format) else {
+        skip(Skipped::DtbFormat(format));
+        return None;
+    };
+    Some(Mode {
+        paddr: paddr as usize,
+        size: size as usize,
+        width: node.property_u64("width")? as usize,
+        height: node.property_u64("height")? as usize,
+        stride: node.property_u64("stride")? as usize,
+        format,
+    })
+}
+
+/// Reads the mode from the `screen_info` table, which only a Linux-style EFI
+/// stub installs (see the module docs).
+fn from_efi() -> Option<Mode> {
+    let si = crate::efi::config_table(&SCREEN_INFO_TABLE_GUID)?;
+    let read = |off: usize, len: usize| -> usize {
+        let mut bytes = [0; 4];
+        for (i, b) in bytes.iter_mut().take(len).enumerate() {
+            // SAFETY: firmware tables live in low memory, identity mapped at
+            // boot.
+            *b = unsafe { core::ptr::read_volatile((si + off + i) as *const u8) };
+        }
+        u32::from_le_bytes(bytes) as usize
+    };
+    if read(SI_IS_VGA, 1) as u8 != VIDEO_TYPE_EFI {
+        return None;
+    }
+    let mut paddr = read(SI_LFB_BASE, 4);
+    if read(SI_CAPABILITIES, 4) as u32 & VIDEO_CAPABILITY_64BIT_BASE != 0 {
+        paddr |= read(SI_EXT_LFB_BASE, 4) << 32;
+    }
+    // Size and position of the red, green and blue components.
+    let rgb = core::array::from_fn::<_, 6, _>(|i| read(SI_RED_SIZE + i, 1));
+    let format = match (read(SI_LFB_DEPTH, 2), rgb) {
+        (16, [5, 11, 6, 5, 5, 0]) => PixelFormat::R5G6B5,
+        (32, [8, 16, 8, 8, 8, 0]) => PixelFormat::X8R8G8B8,
+        (32, [8, 0, 8, 8, 8, 16]) => PixelFormat::X8B8G8R8,
+        (32, [10, 20, 10, 10, 10, 0]) => PixelFormat::X2R10G10B10,
+        (depth, rgb) => {
+            skip(Skipped::EfiFormat(depth, rgb));
+            return None;
+        }
+    };
+    Some(Mode {
+        paddr,
+        size: read(SI_LFB_SIZE, 4),
+        width: read(SI_LFB_WIDTH, 2),
+        height: read(SI_LFB_HEIGHT, 2),
+        stride: read(SI_LFB_LINELENGTH, 2),
+        format,
+    })
+}
+
+fn from_config() -> Mode {
+    let format = PixelFormat::from_name(SIMPLEFB_FORMAT).unwrap_or_else(|| {
+        skip(Skipped::ConfigFormat);
+        PixelFormat::X8R8G8B8
+    });
+    let stride = match SIMPLEFB_STRIDE {
+        0 => SIMPLEFB_WIDTH * format.bytes_per_pixel(),
+        stride => stride,
+    };
+    Mode {
+        paddr: SIMPLEFB_PADDR,
+        size: stride * SIMPLEFB_HEIGHT,
+        width: SIMPLEFB_WIDTH,
+        height: SIMPLEFB_HEIGHT,
+        stride,
+        format,
+    }
+}
+
+/// Finds the framebuffer mode. Must run while the boot identity map is
+/// active, for the EFI tables.
+pub(super) fn probe() -> Mode {
+    let found = [("DTB", from_dtb as fn() -> Option<Mode>), ("EFI", from_efi)]
+        .into_iter()
+        .find_map(|(source, find)| {
+            let mode = find()?;
+            if !mode.is_valid() {
+                skip(Skipped::Invalid(source, mode));
+                return None;
+            }
+            if !mode.is_mapped() {
+                skip(Skipped::Unmapped(source, mode));
+                return None;
+            }
+            Some((source, mode))
+        });
+    let (source, mode) = found.unwrap_or_else(|| ("config", from_config()));
+    REPORT.lock().source = source;
+    mode
+}
+
+/// Logs the mode found by [`probe`], and the ones it passed over.
+pub(super) fn log_probe(mode: &Mode) {
+    let report = REPORT.lock();
+    for skipped in report.skipped.iter().flatten() {
+        match *skipped {
+            Skipped::DtbFormat(format) => {
+                warn!("simplefb: unsupported DTB framebuffer format {:?}", format)
+            }
+            Skipped::EfiFormat(depth, rgb) => {
+                warn!("simplefb: unsupported EFI framebuffer format, depth {} {:?}", depth, rgb)
+            }
+            Skipped::ConfigFormat => {
+                warn!("simplefb: unknown simplefb-format {:?}", SIMPLEFB_FORMAT)
+            }
+            Skipped::Invalid(source, mode) => {
+                warn!("simplefb: ignoring invalid {} mode {:x?}", source, mode)
+            }
+            Skipped::Unmapped(source, mode) => {
+                warn!("simplefb: {} framebuffer at {:#x} is not mapped", source, mode.paddr)
+            }
+        }
+    }
+    info!(
+        "simplefb: {}x{} {}, stride {}, at {:#x} (from {})",
+        mode.width,
+        mode.height,
+        mode.format.name(),
+        mode.stride,
+        mode.paddr,
+        report.source
+    );
+}
@@ -0,0 +1,208 @@
+//! Magic SysRq-style debug commands.
+//!
+//! A command is a single key, entered after:
+//!
+//! - a break condition on the PL011 line (e.g. `Ctrl-A F` in picocom,
+//!   `~#` in cu);
+//! - the `sysrq-kbd-trigger` byte on the PS/2 keyboard (`Ctrl-\` by
+//!   default). Pressing the trigger twice passes it through. Key presses
+//!   are checked as they are decoded, from the PS/2 interrupt when there is
+//!   one, so the trigger works even when nobody reads the console.
+//!
+//! | Key | Action                                         |
+//! |-----|------------------------------------------------|
+//! | `b` | reboot immediately                             |
+//! | `d` | dump the kernel log buffer                     |
+//! | `i` | show IRQ statistics (`irq` feature)            |
+//! | `l` | dump registers and backtraces of all CPUs      |
+//! | `m` | show the memory map                            |
+//! | `o` | power off                                      |
+//!
+//! Any other key prints the list. Actions run in the context that received
+//! the key, usually an interrupt handler, and write straight to the console
+//! devices.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use aarch64_cpu::registers::{ELR_EL1, ESR_EL1, FAR_EL1, Readable, SP_EL0, SPSR_EL1};
+use kspin::SpinNoIrq;
+
+use crate::config::devices::{CONSOLE_LOG_BUF_SIZE, MMIO_RANGES, SYSRQ_KBD_TRIGGER};
+use crate::config::plat::{PHYS_MEMORY_BASE, PHYS_MEMORY_SIZE, PHYS_VIRT_OFFSET};
+use crate::console::Writer as Console;
+
+/// A SysRq action.
+struct Action {
+    key: u8,
+    help: &'static str,
+    run: fn(),
+}
+
+static ACTIONS: &[Action] = &[
+    Action {
+        key: b'b',
+        help: "reboot",
+        run: reboot,
+    },
+    Action {
+        key: b'd',
+        help: "dump log buffer",
+        run: dump_log,
+    },
+    #[cfg(feature = "irq")]
+    Action {
+        key: b'i',
+        help: "show IRQ statistics",
+        run: show_irqs,
+    },
+    Action {
+        key: b'l',
+        help: "show registers of all CPUs",
+        run: show_all_cpus,
+    },
+    Action {
+        key: b'm',
+        help: "show memory map",
+        run: show_memory_map,
+    },
+    Action {
+        key: b'o',
+        help: "power off",
+        run: power_off,
+    },
+];
+
+/// Whether the keyboard trigger byte was just received.
+static KBD_ARMED: AtomicBool = AtomicBool::new(false);
+
+/// Command key received on the keyboard and not run yet, 0 if none.
+static KBD_COMMAND: AtomicU8 = AtomicU8::new(0);
+
+/// Copy of the log buffer being dumped, too large for the stack.
+static LOG_COPY: SpinNoIrq<[u8; CONSOLE_LOG_BUF_SIZE]> = SpinNoIrq::new([0; CONSOLE_LOG_BUF_SIZE]);
+
+/// Runs the action bound to `key`, or prints the list of actions.
+pub fn handle(key: u8) {
+    let _ = write!(Console, "\nSysRq: ");
+    match ACTIONS.iter().find(|a| a.key == key) {
+        Some(action) => {
+            let _ = writeln!(Console, "{}", action.help);
+            (action.run)();
+        }
+        None => {
+            let _ = write!(Console, "HELP:");
+            for action in ACTIONS {
+                let _ = write!(Console, " {}={}", action.key as char, action.help);
+            }
+            let _ = writeln!(Console);
+        }
+    }
+}
+
+/// Looks for the trigger sequence in the bytes produced by key presses.
+///
+/// Returns `true` if `c` was consumed by SysRq and must not be passed on.
+/// The command is run later by [`run_keyboard`], as the keyboard driver is
+/// locked here.
+pub(crate) fn filter_keyboard(c: u8) -> bool {
+    if SYSRQ_KBD_TRIGGER == 0 {
+        return false;
+    }
+    if KBD_ARMED.swap(false, Ordering::Relaxed) {
+        if c == SYSRQ_KBD_TRIGGER as u8 {
+            return false;
+        }
+        KBD_COMMAND.store(c, Ordering::Relaxed);
+        return true;
+    }
+    if c == SYSRQ_KBD_TRIGGER as u8 {
+        KBD_ARMED.store(true, Ordering::Relaxed);
+        return true;
+    }
+    false
+}
+
+/// Runs the command received on the keyboard, if any.
+///
+/// Called with no driver lock held.
+pub(crate) fn run_keyboard() {
+    match KBD_COMMAND.swap(0, Ordering::Relaxed) {
+        0 => {}
+        key => handle(key),
+    }
+}
+
+fn reboot() {
+    crate::pl011::flush();
+    crate::ns16550::flush();
+    crate::power::system_reset();
+}
+
+fn power_off() {
+    crate::power::system_off();
+}
+
+fn dump_log() {
+    let mut copy = LOG_COPY.lock();
+    let len = crate::console::read_log_buffer(&mut *copy);
+    // Not through `write_bytes`, which would append the dump to the log.
+    crate::console::write_devices(&copy[..len]);
+    let _ = writeln!(Console, "SysRq: end of log buffer ({} bytes)", len);
+}
+
+#[cfg(feature = "irq")]
+fn show_irqs() {
+    use crate::config::plat::MAX_CPU_NUM;
+    for irq in 0..crate::gicv3::MAX_IRQ_COUNT {
+        let count = crate::gicv3::irq_count(irq);
+        if count != 0 {
+            let _ = writeln!(Console, "  IRQ {:4}: {}", irq, count);
+        }
+    }
+    for cpu in 0..MAX_CPU_NUM {
+        let _ = writeln!(Console, "  CPU {:4}: {}", cpu, crate::gicv3::cpu_irq_count(cpu));
+    }
+}
+
+fn show_memory_map() {
+    let ram_end = PHYS_MEMORY_BASE + PHYS_MEMORY_SIZE;
+    let _ = writeln!(Console, "  [{:#012x}, {:#012x}) RAM", PHYS_MEMORY_BASE, ram_end);
+    for &(base, size) in MMIO_RANGES.iter() {
+        let _ = writeln!(Console, "  [{:#012x}, {:#012x}) MMIO", base, base + size);
+    }
+    let _ = writeln!(Console, "  linear mapping offset {:#x}", PHYS_VIRT_OFFSET);
+}
+
+/// Prints the registers of the interrupted context and a backtrace.
+fn show_current_cpu() {
+    let _ = writeln!(
+        Console,
+        "CPU {}: ELR {:#018x} SPSR {:#010x} ESR {:#010x} FAR {:#018x} SP_EL0 {:#018x}",
+        aarch64_cpu::registers::MPIDR_EL1.get() & 0xffffff,
+        ELR_EL1.get(),
+        SPSR_EL1.get(),
+        ESR_EL1.get(),
+        FAR_EL1.get(),
+        SP_EL0.get(),
+    );
+    crate::backtrace::write_interrupted(&mut Console);
+}
+
+fn show_all_cpus() {
+    #[cfg(all(feature = "irq", feature = "smp"))]
+    crate::gicv3::send_sgi(crate::config::devices::SYSRQ_IPI_IRQ, None);
+    show_current_cpu();
+}
+
+/// Registers the register dump request handler.
+#[cfg(feature = "irq")]
+pub(crate) fn init() {
+    axplat::irq::register(crate::config::devices::SYSRQ_IPI_IRQ, show_current_cpu);
+}
+
+/// Enables the register dump request SGI on a secondary CPU.
+#[cfg(all(feature = "irq", feature = "smp"))]
+pub(crate) fn init_secondary() {
+    axplat::irq::set_enable(crate::config::devices::SYSRQ_IPI_IRQ, true);
+}
@@ -0,0 +1,150 @@
+//! Workarounds for generic timer counter read errata.
+//!
+//! Some licensee cores may return a torn or stale value from a single
+//! `CNTPCT_EL0` read. The workaround is picked from `MIDR_EL1` at boot and can
+//! be overridden with the `timer.workaround=<none|reread|vcnt>` boot argument.
+//! The choice is logged by [`init_later`], as [`init`] runs before logging is
+//! up.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use aarch64_cpu::registers::{CNTPCT_EL0, CNTVCT_EL0, MIDR_EL1, Readable};
+use kspin::SpinNoIrq;
+use log::info;
+
+/// Maximum number of rereads before giving up on a stable value.
+const MAX_REREAD_TRIES: usize = 200;
+/// Two consecutive reads closer than this many ticks are considered stable.
+const REREAD_MAX_DELTA: u64 = 32;
+
+/// How the counter is read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Workaround {
+    /// A single `CNTPCT_EL0` read.
+    None = 0,
+    /// Read `CNTPCT_EL0` until two consecutive values are close enough.
+    Reread = 1,
+    /// Read `CNTVCT_EL0` instead, which is not affected on some parts.
+    /// Relies on `CNTVOFF_EL2` being zero, as set up by `switch_to_el1`.
+    VirtualCounter = 2,
+}
+
+impl Workaround {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            1 => Self::Reread,
+            2 => Self::VirtualCounter,
+            _ => Self::None,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Self::None),
+            "reread" => Some(Self::Reread),
+            "vcnt" => Some(Self::VirtualCounter),
+            _ => None,
+        }
+    }
+}
+
+/// An affected core.
+struct Erratum {
+    /// Description printed at boot.
+    desc: &'static str,
+    implementer: u64,
+    /// Primary part number.
+    part: u64,
+    workaround: Workaround,
+}
+
+/// Cores known to need a workaround.
+///
+/// Each entry must name the erratum and the document it is published in.
+/// Automatic selection on D3000/FT parts is blocked on Phytium publishing
+/// their counter read errata: no erratum notice for implementer `0x70` is
+/// public, and guessing part numbers could slow down every counter read of
+/// an unaffected core. Until then, the workaround has to be asked for with
+/// `timer.workaround=`, and [`init_later`] logs the MIDR so that affected
+/// parts can be identified.
+static ERRATA: &[Erratum] = &[];
+
+static WORKAROUND: AtomicU8 = AtomicU8::new(Workaround::None as u8);
+/// Why the workaround was chosen, logged by [`init_later`].
+static REASON: SpinNoIrq<Option<Reason>> = SpinNoIrq::new(None);
+
+#[derive(Clone, Copy)]
+enum Reason {
+    Bootargs,
+    Erratum(&'static Erratum),
+}
+
+/// Selects the counter read workaround for this CPU.
+pub fn init() {
+    let midr = MIDR_EL1.get();
+    let implementer = (midr >> 24) & 0xff;
+    let part = (midr >> 4) & 0xfff;
+    let detected = ERRATA.iter().find(|e| e.implementer == implementer && e.part == part);
+
+    let (workaround, reason) =
+        match crate::bootargs::get("timer.workaround").and_then(Workaround::from_name) {
+            Some(w) => (w, Some(Reason::Bootargs)),
+            None => match detected {
+                Some(e) => (e.workaround, Some(Reason::Erratum(e))),
+                None => (Workaround::None, None),
+            },
+        };
+    WORKAROUND.store(workaround as u8, Ordering::Relaxed);
+    *REASON.lock() = reason;
+}
+
+/// Logs the counter read method chosen by [`init`], including
+/// [`Workaround::None`], once logging is up.
+pub fn init_later() {
+    let workaround = Workaround::from_u8(WORKAROUND.load(Ordering::Relaxed));
+    match *REASON.lock() {
+        Some(Reason::Bootargs) => {
+            info!("generic timer: counter workaround {:?} forced by bootargs", workaround)
+        }
+        Some(Reason::Erratum(e)) => {
+            info!("generic timer: {} erratum, using {:?}", e.desc, workaround)
+        }
+        None => info!(
+            "generic timer: counter read {:?}, no known erratum for MIDR {:#x}",
+            workaround,
+            MIDR_EL1.get()
+        ),
+    }
+}
+
+fn read_cntpct() -> u64 {
+    // Keep the read from being speculated ahead of earlier instructions.
+    unsafe { core::arch::asm!("isb") };
+    CNTPCT_EL0.get()
+}
+
+fn read_reread() -> u64 {
+    let mut prev = read_cntpct();
+    for _ in 0..MAX_REREAD_TRIES {
+        let cur = read_cntpct();
+        if cur.wrapping_sub(prev) < REREAD_MAX_DELTA {
+            return cur;
+        }
+        prev = cur;
+    }
+    prev
+}
+
+/// Reads the system counter, applying the selected workaround.
+#[inline]
+pub fn read_counter() -> u64 {
+    match Workaround::from_u8(WORKAROUND.load(Ordering::Relaxed)) {
+        Workaround::None => CNTPCT_EL0.get(),
+        Workaround::Reread => read_reread(),
+        Workaround::VirtualCounter => {
+            unsafe { core::arch::asm!("isb") };
+            CNTVCT_EL0.get()
+        }
+    }
+}
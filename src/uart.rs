@@ -0,0 +1,412 @@
+//! Buffering shared by the UART drivers ([`crate::pl011`] and
+//! [`crate::ns16550`]).
+//!
+//! A [`BufferedUart`] keeps the RX and TX ring buffers, the error counters
+//! and the SysRq state of one UART, and reaches the hardware through the
+//! [`UartRegs`] hooks of its driver.
+//!
+//! Received bytes are moved into the RX ring buffer by
+//! [`BufferedUart::receive`], called from the driver's interrupt handler.
+//! Output is queued in the TX ring buffer and drained by
+//! [`BufferedUart::transmit`] from the same handler, so writers only wait
+//! when the buffer is full. Before the driver
+//! calls [`BufferedUart::enable_irq`], and in synchronous mode, output is
+//! written directly with busy-waiting, and input is polled.
+
+#[cfg(feature = "sysrq")]
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use kspin::SpinNoIrq;
+
+use crate::config::devices::{UART_RX_BUF_SIZE, UART_TX_BUF_SIZE};
+use crate::ringbuf::RingBuffer;
+
+/// Error flags of a received byte, returned by [`UartRegs::read_rx`].
+pub(crate) const RX_OVERRUN: u8 = 1 << 0;
+pub(crate) const RX_BREAK: u8 = 1 << 1;
+pub(crate) const RX_FRAMING: u8 = 1 << 2;
+pub(crate) const RX_PARITY: u8 = 1 << 3;
+
+/// Counters of bytes dropped on the receive path.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RxOverruns {
+    /// Dropped because the software ring buffer was full.
+    pub buffer: usize,
+    /// Dropped by the UART because its FIFO was full.
+    pub fifo: usize,
+}
+
+/// Counters of line errors on the receive path.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LineErrors {
+    /// Bytes received without a valid stop bit.
+    pub framing: usize,
+    /// Bytes received with a wrong parity bit.
+    pub parity: usize,
+    /// Break conditions detected on the line.
+    pub breaks: usize,
+}
+
+/// Register access of a UART.
+pub(crate) trait UartRegs {
+    /// Reads the byte at the head of the RX FIFO with its error flags
+    /// (`RX_*`), or returns [`None`] if the FIFO is empty.
+    fn read_rx(&self) -> Option<(u8, u8)>;
+    /// Returns how many bytes the TX FIFO takes without waiting.
+    fn tx_room(&self) -> usize;
+    fn write_tx(&self, c: u8);
+    fn set_tx_irq(&self, enabled: bool);
+    /// Returns whether every byte written has left the transmitter.
+    fn tx_idle(&self) -> bool;
+}
+
+/// A UART with RX and TX ring buffers.
+pub(crate) struct BufferedUart<R> {
+    regs: R,
+    /// Whether the UART is found and set up.
+    present: AtomicBool,
+    /// Serializes reads of the RX FIFO and line reprogramming.
+    line: SpinNoIrq<()>,
+
+    rx_buf: SpinNoIrq<RingBuffer<u8, UART_RX_BUF_SIZE>>,
+    rx_echo: AtomicBool,
+    /// Bytes lost because the RX ring buffer was full.
+    rx_sw_overruns: AtomicUsize,
+    /// Bytes lost because the hardware RX FIFO was full.
+    rx_hw_overruns: AtomicUsize,
+    rx_framing_errors: AtomicUsize,
+    rx_parity_errors: AtomicUsize,
+    rx_breaks: AtomicUsize,
+    rx_wakeup: SpinNoIrq<Option<fn()>>,
+    /// Whether the next received byte is a SysRq command.
+    #[cfg(feature = "sysrq")]
+    sysrq_armed: AtomicBool,
+    /// SysRq command waiting to be run, 0 if none.
+    #[cfg(feature = "sysrq")]
+    sysrq_key: AtomicU8,
+
+    tx_buf: SpinNoIrq<RingBuffer<u8, UART_TX_BUF_SIZE>>,
+    /// Whether the interrupt handler is registered.
+    tx_irq_ready: AtomicBool,
+    /// Whether output bypasses the TX ring buffer.
+    tx_sync: AtomicBool,
+}
+
+impl<R: UartRegs> BufferedUart<R> {
+    pub(crate) const fn new(regs: R, echo: bool) -> Self {
+        Self {
+            regs,
+            present: AtomicBool::new(false),
+            line: SpinNoIrq::new(()),
+            rx_buf: SpinNoIrq::new(RingBuffer::new()),
+            rx_echo: AtomicBool::new(echo),
+            rx_sw_overruns: AtomicUsize::new(0),
+            rx_hw_overruns: AtomicUsize::new(0),
+            rx_framing_errors: AtomicUsize::new(0),
+            rx_parity_errors: AtomicUsize::new(0),
+            rx_breaks: AtomicUsize::new(0),
+            rx_wakeup: SpinNoIrq::new(None),
+            #[cfg(feature = "sysrq")]
+            sysrq_armed: AtomicBool::new(false),
+            #[cfg(feature = "sysrq")]
+            sysrq_key: AtomicU8::new(0),
+            tx_buf: SpinNoIrq::new(RingBuffer::new()),
+            tx_irq_ready: AtomicBool::new(false),
+            tx_sync: AtomicBool::new(false),
+        }
+    }
+
+    /// Marks the UART as set up; nothing is read or written before.
+    pub(crate) fn set_present(&self) {
+        self.present.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_present(&self) -> bool {
+        self.present.load(Ordering::Relaxed)
+    }
+
+    /// Runs `f` with reads of the RX FIFO held off.
+    pub(crate) fn with_line<T>(&self, f: impl FnOnce() -> T) -> T {
+        let _line = self.line.lock();
+        f()
+    }
+
+    /// Runs `f` to reprogram the line, once queued output is flushed and
+    /// with readers and writers held off.
+    pub(crate) fn reprogram<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.flush();
+        let _line = self.line.lock();
+        let _tx = self.tx_buf.lock();
+        f()
+    }
+
+    /// Reads a received byte from the RX FIFO.
+    ///
+    /// Bytes with framing or parity errors and break conditions are counted
+    /// and dropped. With the `sysrq` feature, the byte after a break is taken
+    /// as a SysRq command instead, run by [`Self::run_sysrq`]. The caller
+    /// must be in [`Self::with_line`].
+    pub(crate) fn read_rx(&self) -> Option<u8> {
+        loop {
+            let (c, errors) = self.regs.read_rx()?;
+            if errors & RX_OVERRUN != 0 {
+                // The byte itself is fine, the one after it was lost.
+                self.rx_hw_overruns.fetch_add(1, Ordering::Relaxed);
+            }
+            if errors & RX_BREAK != 0 {
+                self.rx_breaks.fetch_add(1, Ordering::Relaxed);
+                #[cfg(feature = "sysrq")]
+                self.sysrq_armed.store(true, Ordering::Relaxed);
+                continue;
+            } else if errors & RX_FRAMING != 0 {
+                self.rx_framing_errors.fetch_add(1, Ordering::Relaxed);
+                continue;
+            } else if errors & RX_PARITY != 0 {
+                self.rx_parity_errors.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            #[cfg(feature = "sysrq")]
+            if self.sysrq_armed.swap(false, Ordering::Relaxed) {
+                self.sysrq_key.store(c, Ordering::Relaxed);
+                continue;
+            }
+            return Some(c);
+        }
+    }
+
+    /// Runs the SysRq command received by [`Self::read_rx`], if any.
+    ///
+    /// Called once the UART locks are released, as the command prints.
+    pub(crate) fn run_sysrq(&self) {
+        #[cfg(feature = "sysrq")]
+        match self.sysrq_key.swap(0, Ordering::Relaxed) {
+            0 => {}
+            key => crate::sysrq::handle(key),
+        }
+    }
+
+    /// Writes a byte, waiting for room in the TX FIFO.
+    pub(crate) fn putchar_sync(&self, c: u8) {
+        while self.regs.tx_room() == 0 {
+            core::hint::spin_loop();
+        }
+        self.regs.write_tx(c);
+    }
+
+    /// Moves queued bytes into the TX FIFO while it has room.
+    fn fill_fifo(&self, tx: &mut RingBuffer<u8, UART_TX_BUF_SIZE>) {
+        loop {
+            let room = self.regs.tx_room();
+            if room == 0 {
+                return;
+            }
+            for _ in 0..room {
+                let Some(c) = tx.pop() else {
+                    return;
+                };
+                self.regs.write_tx(c);
+            }
+        }
+    }
+
+    /// Writes all queued bytes synchronously.
+    fn drain_sync(&self, tx: &mut RingBuffer<u8, UART_TX_BUF_SIZE>) {
+        while let Some(c) = tx.pop() {
+            self.putchar_sync(c);
+        }
+    }
+
+    fn queue_byte(&self, tx: &mut RingBuffer<u8, UART_TX_BUF_SIZE>, c: u8) {
+        if !tx.push(c) {
+            // Full: make room by sending the oldest byte ourselves.
+            if let Some(old) = tx.pop() {
+                self.putchar_sync(old);
+            }
+            tx.push(c);
+        }
+    }
+
+    /// Writes bytes through the TX ring buffer, or synchronously before the
+    /// TX interrupt is ready. With `crlf`, `\n` is sent as `\r\n`.
+    pub(crate) fn write(&self, bytes: &[u8], crlf: bool) {
+        if !self.is_present() {
+            return;
+        }
+        let mut tx = self.tx_buf.lock();
+        let sync =
+            self.tx_sync.load(Ordering::Relaxed) || !self.tx_irq_ready.load(Ordering::Relaxed);
+        if sync {
+            self.drain_sync(&mut tx);
+            for &c in bytes {
+                if crlf && c == b'\n' {
+                    self.putchar_sync(b'\r');
+                }
+                self.putchar_sync(c);
+            }
+            return;
+        }
+        for &c in bytes {
+            if crlf && c == b'\n' {
+                self.queue_byte(&mut tx, b'\r');
+            }
+            self.queue_byte(&mut tx, c);
+        }
+        self.fill_fifo(&mut tx);
+        if !tx.is_empty() {
+            self.regs.set_tx_irq(true);
+        }
+    }
+
+    /// Reads a byte, or returns [`None`] if no input is available.
+    ///
+    /// Bytes already buffered by the interrupt come first, then the UART is
+    /// polled.
+    pub(crate) fn getchar(&self) -> Option<u8> {
+        if !self.is_present() {
+            return None;
+        }
+        if let Some(c) = self.rx_buf.lock().pop() {
+            return Some(c);
+        }
+        let c = self.with_line(|| self.read_rx());
+        self.run_sysrq();
+        c
+    }
+
+    /// Switches output to synchronous mode, bypassing the TX interrupt.
+    /// Pending bytes are flushed first.
+    pub(crate) fn set_synchronous(&self, enabled: bool) {
+        self.tx_sync.store(enabled, Ordering::Relaxed);
+        if enabled {
+            self.flush();
+        }
+    }
+
+    /// Switches to synchronous mode for good, with the TX interrupt off, and
+    /// writes out queued bytes. The TX buffer lock is broken if its holder
+    /// does not release it.
+    ///
+    /// Returns `false` if the UART is not set up.
+    pub(crate) fn take_over(&self) -> bool {
+        if !self.is_present() {
+            return false;
+        }
+        self.tx_sync.store(true, Ordering::Relaxed);
+        self.regs.set_tx_irq(false);
+        self.drain_sync(&mut crate::console::lock_or_break(&self.tx_buf));
+        true
+    }
+
+    /// Writes bytes directly to the UART after [`Self::take_over`], for the
+    /// panic path. `\n` is sent as `\r\n`.
+    pub(crate) fn emergency_write(&self, bytes: &[u8]) {
+        if !self.take_over() {
+            return;
+        }
+        for &c in bytes {
+            if c == b'\n' {
+                self.putchar_sync(b'\r');
+            }
+            self.putchar_sync(c);
+        }
+    }
+
+    /// Waits until all queued bytes have been transmitted.
+    pub(crate) fn flush(&self) {
+        if !self.is_present() {
+            return;
+        }
+        self.drain_sync(&mut self.tx_buf.lock());
+        while !self.regs.tx_idle() {
+            core::hint::spin_loop();
+        }
+    }
+
+    pub(crate) fn set_echo(&self, enabled: bool) {
+        self.rx_echo.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(crate) fn echo_enabled(&self) -> bool {
+        self.rx_echo.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_rx_wakeup(&self, callback: Option<fn()>) {
+        *self.rx_wakeup.lock() = callback;
+    }
+
+    /// Invokes the RX wakeup callback.
+    #[cfg(feature = "irq")]
+    pub(crate) fn wake_reader(&self) {
+        let wakeup = *self.rx_wakeup.lock();
+        if let Some(wakeup) = wakeup {
+            wakeup();
+        }
+    }
+
+    pub(crate) fn rx_overruns(&self) -> RxOverruns {
+        RxOverruns {
+            buffer: self.rx_sw_overruns.load(Ordering::Relaxed),
+            fifo: self.rx_hw_overruns.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn line_errors(&self) -> LineErrors {
+        LineErrors {
+            framing: self.rx_framing_errors.load(Ordering::Relaxed),
+            parity: self.rx_parity_errors.load(Ordering::Relaxed),
+            breaks: self.rx_breaks.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Runs `enable` to unmask the UART interrupts, serialized with the TX
+    /// interrupt updates, then routes output through the TX interrupt.
+    ///
+    /// The interrupt handler must call [`Self::receive`] and
+    /// [`Self::transmit`].
+    #[cfg(feature = "irq")]
+    pub(crate) fn enable_irq(&self, enable: impl FnOnce()) {
+        let _tx = self.tx_buf.lock();
+        enable();
+        self.tx_irq_ready.store(true, Ordering::Relaxed);
+    }
+
+    /// Moves the bytes in the RX FIFO into the RX ring buffer, except those
+    /// `consume` takes, and wakes up the reader.
+    #[cfg(feature = "irq")]
+    pub(crate) fn receive(&self, mut consume: impl FnMut(u8) -> bool) {
+        let line = self.line.lock();
+        let echo = self.rx_echo.load(Ordering::Relaxed);
+        let mut received = false;
+        let mut rx_buf = self.rx_buf.lock();
+        while let Some(c) = self.read_rx() {
+            if consume(c) {
+                continue;
+            }
+            received = true;
+            if !rx_buf.push(c) {
+                self.rx_sw_overruns.fetch_add(1, Ordering::Relaxed);
+            }
+            if echo {
+                self.write(&[c], true);
+            }
+        }
+        drop(rx_buf);
+        drop(line);
+        self.run_sysrq();
+        if received {
+            self.wake_reader();
+        }
+    }
+
+    /// Refills the TX FIFO from the TX ring buffer, turning the TX interrupt
+    /// off once the buffer is empty.
+    #[cfg(feature = "irq")]
+    pub(crate) fn transmit(&self) {
+        let mut tx = self.tx_buf.lock();
+        self.fill_fifo(&mut tx);
+        if tx.is_empty() {
+            self.regs.set_tx_irq(false);
+        }
+    }
+}
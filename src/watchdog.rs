@@ -0,0 +1,271 @@
+//! ARM SBSA Generic Watchdog.
+//!
+//! The watchdog counts against the system counter. Once armed, it must be
+//! refreshed with [`pet`] within the timeout. The first expiry raises the WS0
+//! interrupt, which dumps the state of the interrupted CPU; if the watchdog is
+//! still not refreshed after a second timeout, WS1 resets the machine.
+//!
+//! The frames are discovered from the DTB (`arm,sbsa-gwdt`), then the ACPI
+//! GTDT, then the `wdt-*` configs.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use aarch64_cpu::registers::{CNTFRQ_EL0, Readable};
+use axplat::mem::{pa, phys_to_virt};
+use lazyinit::LazyInit;
+use log::{error, info, warn};
+
+use crate::config::devices::{
+    MMIO_RANGES, WDT_CTRL_PADDR, WDT_IRQ, WDT_PANIC_ON_TIMEOUT, WDT_REFRESH_PADDR,
+};
+
+/// Refresh frame: Watchdog Refresh Register.
+const WRR: usize = 0x000;
+/// Control frame: Watchdog Control and Status Register.
+const WCS: usize = 0x000;
+/// Control frame: Watchdog Offset Register.
+const WOR: usize = 0x008;
+/// Control frame: Watchdog Interface Identification Register.
+const W_IIDR: usize = 0xfcc;
+
+const WCS_EN: u32 = 1 << 0;
+const WCS_WS0: u32 = 1 << 1;
+const WCS_WS1: u32 = 1 << 2;
+
+/// Size of each watchdog frame.
+const FRAME_SIZE: usize = 0x1000;
+
+/// GTDT platform timer structure type of an SBSA watchdog.
+const GTDT_TYPE_WATCHDOG: u8 = 1;
+/// GTDT watchdog flag: the watchdog is in the secure world.
+const GTDT_WATCHDOG_SECURE: u32 = 1 << 2;
+
+/// Physical resources of the watchdog.
+#[derive(Debug, Clone, Copy)]
+struct Resources {
+    ctrl_paddr: usize,
+    refresh_paddr: usize,
+    irq: usize,
+}
+
+struct SbsaGwdt {
+    ctrl: usize,
+    refresh: usize,
+    irq: usize,
+    /// Widest value accepted by `WOR` (32 bits in v0, 48 bits from v1).
+    max_offset: u64,
+}
+
+static RESOURCES: LazyInit<Resources> = LazyInit::new();
+static WDT: LazyInit<SbsaGwdt> = LazyInit::new();
+/// Offset currently programmed in `WOR`, in ticks.
+static TIMEOUT_TICKS: AtomicU64 = AtomicU64::new(0);
+/// Whether [`ws0_handler`] masked WS0 until the next refresh.
+static WS0_MASKED: AtomicBool = AtomicBool::new(false);
+
+impl SbsaGwdt {
+    fn read_ctrl(&self, reg: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.ctrl + reg) as *const u32) }
+    }
+
+    fn write_ctrl(&self, reg: usize, val: u32) {
+        unsafe { core::ptr::write_volatile((self.ctrl + reg) as *mut u32, val) }
+    }
+
+    fn set_offset(&self, ticks: u64) {
+        self.write_ctrl(WOR, ticks as u32);
+        // The upper half only exists from architecture version 1.
+        if self.max_offset > u32::MAX as u64 {
+            self.write_ctrl(WOR + 4, (ticks >> 32) as u32);
+        }
+    }
+
+    fn refresh(&self) {
+        // Any write to WRR is an explicit refresh.
+        unsafe { core::ptr::write_volatile((self.refresh + WRR) as *mut u32, 0) }
+    }
+}
+
+fn from_dtb() -> Option<Resources> {
+    let fdt = crate::fdt::get()?;
+    let (parent, node) = fdt.find_with_parent(|node| node.is_compatible("arm,sbsa-gwdt"))?;
+    let (ctrl, _) = node.reg_cells(0, parent.cells())?;
+    let (refresh, _) = node.reg_cells(1, parent.cells())?;
+    // `interrupts = <type number flags>` with type 0 for SPI and 1 for PPI.
+    let intr = node.property("interrupts")?;
+    let cell = |i: usize| {
+        intr.get(i * 4..i * 4 + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    };
+    let irq = match cell(0)? {
+        0 => cell(1)? + 32,
+        1 => cell(1)? + 16,
+        _ => return None,
+    };
+    Some(Resources {
+        ctrl_paddr: ctrl as usize,
+        refresh_paddr: refresh as usize,
+        irq,
+    })
+}
+
+fn from_gtdt() -> Option<Resources> {
+    let (gtdt, len) = crate::acpi::find_table(b"GTDT")?;
+    // SAFETY: `find_table` validated the table, which is identity mapped.
+    unsafe {
+        use crate::acpi::read;
+        let count = read::<u32>(gtdt + 88) as usize;
+        let mut off = read::<u32>(gtdt + 92) as usize;
+        for _ in 0..count {
+            if off + 4 > len {
+                break;
+            }
+            let ty = read::<u8>(gtdt + off);
+            let entry_len = read::<u16>(gtdt + off + 1) as usize;
+            if ty == GTDT_TYPE_WATCHDOG
+                && read::<u32>(gtdt + off + 24) & GTDT_WATCHDOG_SECURE == 0
+            {
+                return Some(Resources {
+                    refresh_paddr: read::<u64>(gtdt + off + 4) as usize,
+                    ctrl_paddr: read::<u64>(gtdt + off + 12) as usize,
+                    irq: read::<u32>(gtdt + off + 20) as usize,
+                });
+            }
+            if entry_len == 0 {
+                break;
+            }
+            off += entry_len;
+        }
+    }
+    None
+}
+
+fn from_config() -> Option<Resources> {
+    (WDT_CTRL_PADDR != 0 && WDT_REFRESH_PADDR != 0).then_some(Resources {
+        ctrl_paddr: WDT_CTRL_PADDR,
+        refresh_paddr: WDT_REFRESH_PADDR,
+        irq: WDT_IRQ,
+    })
+}
+
+fn is_mapped(paddr: usize) -> bool {
+    MMIO_RANGES
+        .iter()
+        .any(|&(base, size)| paddr >= base && paddr + FRAME_SIZE <= base + size)
+}
+
+/// Looks for a watchdog in the firmware tables.
+///
+/// Must be called from `init_early`, while the DTB and the ACPI tables can
+/// still be reached.
+pub fn probe() {
+    if let Some(res) = from_dtb().or_else(from_gtdt).or_else(from_config) {
+        RESOURCES.init_once(res);
+    }
+}
+
+/// Initializes the watchdog found by [`probe`], leaving it disabled.
+pub fn init() {
+    let Some(res) = RESOURCES.get() else {
+        return;
+    };
+    if !is_mapped(res.ctrl_paddr) || !is_mapped(res.refresh_paddr) {
+        warn!(
+            "watchdog: frames at {:#x}/{:#x} are outside the MMIO ranges, ignored",
+            res.ctrl_paddr, res.refresh_paddr
+        );
+        return;
+    }
+    let ctrl = phys_to_virt(pa!(res.ctrl_paddr)).as_usize();
+    let iidr = unsafe { core::ptr::read_volatile((ctrl + W_IIDR) as *const u32) };
+    let version = (iidr >> 16) & 0xf;
+    let wdt = SbsaGwdt {
+        ctrl,
+        refresh: phys_to_virt(pa!(res.refresh_paddr)).as_usize(),
+        irq: res.irq,
+        max_offset: if version >= 1 { (1 << 48) - 1 } else { u32::MAX as u64 },
+    };
+    if wdt.read_ctrl(WCS) & WCS_EN != 0 {
+        warn!("watchdog: left enabled by firmware, disabling");
+    }
+    wdt.write_ctrl(WCS, 0);
+    info!(
+        "watchdog: SBSA v{} at {:#x}, WS0 IRQ {}",
+        version, res.ctrl_paddr, res.irq
+    );
+    if wdt.irq != 0 {
+        axplat::irq::register(wdt.irq, ws0_handler);
+    }
+    WDT.init_once(wdt);
+}
+
+/// Returns whether a watchdog is available.
+pub fn is_present() -> bool {
+    WDT.is_inited()
+}
+
+/// Arms the watchdog with the given timeout.
+///
+/// If [`pet`] is not called within `timeout_ms`, WS0 fires; the machine is
+/// reset after another `timeout_ms`. Returns `false` if there is no watchdog.
+pub fn arm(timeout_ms: u64) -> bool {
+    let Some(wdt) = WDT.get() else {
+        return false;
+    };
+    let ticks = (CNTFRQ_EL0.get() * timeout_ms / 1000).min(wdt.max_offset);
+    TIMEOUT_TICKS.store(ticks, Ordering::Relaxed);
+    wdt.write_ctrl(WCS, 0);
+    wdt.set_offset(ticks);
+    wdt.refresh();
+    wdt.write_ctrl(WCS, WCS_EN);
+    if wdt.irq != 0 {
+        WS0_MASKED.store(false, Ordering::Relaxed);
+        axplat::irq::set_enable(wdt.irq, true);
+    }
+    true
+}
+
+/// Refreshes the watchdog, restarting the timeout.
+///
+/// Also unmasks WS0 if [`ws0_handler`] masked it.
+pub fn pet() {
+    if let Some(wdt) = WDT.get() {
+        wdt.refresh();
+        if WS0_MASKED.swap(false, Ordering::Relaxed) {
+            axplat::irq::set_enable(wdt.irq, true);
+        }
+    }
+}
+
+/// Disables the watchdog.
+pub fn disable() {
+    if let Some(wdt) = WDT.get() {
+        wdt.write_ctrl(WCS, 0);
+    }
+}
+
+/// WS0 interrupt handler.
+fn ws0_handler() {
+    let Some(wdt) = WDT.get() else {
+        return;
+    };
+    let status = wdt.read_ctrl(WCS);
+    if status & (WCS_WS0 | WCS_WS1) == 0 {
+        return;
+    }
+    let freq = CNTFRQ_EL0.get();
+    error!(
+        "watchdog: WS0 timeout on CPU {} (WCS {:#x}), reset in {} ms",
+        aarch64_cpu::registers::MPIDR_EL1.get() & 0xffffff,
+        status,
+        TIMEOUT_TICKS.load(Ordering::Relaxed) * 1000 / freq,
+    );
+    if WDT_PANIC_ON_TIMEOUT {
+        crate::console::enter_panic_mode();
+        panic!("watchdog timeout");
+    }
+    // WS0 stays asserted until the next refresh, mask it to avoid a storm.
+    // `pet` unmasks it once the refresh has cleared it.
+    axplat::irq::set_enable(wdt.irq, false);
+    WS0_MASKED.store(true, Ordering::Relaxed);
+}
@@ -0,0 +1,477 @@
+//! XMODEM-1K and YMODEM file transfer over a serial port.
+//!
+//! Both directions use 16-bit CRC blocks; when sending, the plain checksum
+//! variant is also accepted if the receiver asks for it. Timeouts are measured
+//! with the monotonic clock, and each block is retried up to [`MAX_RETRIES`]
+//! times before the transfer is cancelled.
+//!
+//! The transfer runs on any [`SerialPort`]; [`Pl011Port`] takes over the
+//! console UART for its lifetime:
+//!
+//! ```ignore
+//! let len = xmodem::receive(&mut Pl011Port::new(), &mut buf)?;
+//! ```
+//!
+//! On the host, with the port as the terminal: `sx -k image.bin` or
+//! `sb image.bin` to send, `rx -c out.bin` or `rb` to receive.
+
+use axplat::time::{NANOS_PER_MILLIS, monotonic_time_nanos};
+
+use crate::crc16::crc16;
+
+/// Start of a 128-byte block.
+const SOH: u8 = 0x01;
+/// Start of a 1024-byte block.
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+/// Padding of the last block.
+const SUB: u8 = 0x1a;
+/// Asks the sender for CRC blocks.
+const CRC_MODE: u8 = b'C';
+
+/// Times a block (or the start of the transfer) is retried.
+pub const MAX_RETRIES: usize = 10;
+
+/// Time allowed between two bytes of a block.
+const BYTE_TIMEOUT_MS: u64 = 1_000;
+/// Time allowed for the next block to start.
+const BLOCK_TIMEOUT_MS: u64 = 10_000;
+/// Interval between start requests while waiting for the sender.
+const START_INTERVAL_MS: u64 = 3_000;
+/// Time the sender waits for the receiver to start.
+const SEND_START_TIMEOUT_MS: u64 = 60_000;
+
+/// Maximum length of a YMODEM file name.
+const MAX_NAME_LEN: usize = 128;
+
+/// A byte-oriented serial line.
+pub trait SerialPort {
+    /// Returns the next received byte, if any, without blocking.
+    fn try_read(&mut self) -> Option<u8>;
+
+    /// Writes binary data.
+    fn write_all(&mut self, bytes: &[u8]);
+}
+
+/// The PL011 console UART, taken over for a transfer.
+///
+/// While it lives, the UART console is disabled (so log output does not
+/// corrupt the transfer), RX echo is off and Ctrl-C does not break into
+/// the GDB stub. All are restored on drop.
+pub struct Pl011Port {
+    console_enabled: bool,
+    echo: bool,
+    #[cfg(all(feature = "gdbstub", feature = "irq"))]
+    console_break: bool,
+}
+
+impl Pl011Port {
+    pub fn new() -> Self {
+        let port = Self {
+            console_enabled: crate::console::is_enabled(crate::pl011::CONSOLE_SINK.name()),
+            echo: crate::pl011::echo_enabled(),
+            // Ctrl-C is a valid byte of a block.
+            #[cfg(all(feature = "gdbstub", feature = "irq"))]
+            console_break: crate::gdbstub::set_console_break(false),
+        };
+        crate::pl011::flush();
+        crate::console::set_enabled(crate::pl011::CONSOLE_SINK.name(), false);
+        crate::pl011::set_echo(false);
+        port
+    }
+}
+
+impl Default for Pl011Port {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Pl011Port {
+    fn drop(&mut self) {
+        crate::pl011::flush();
+        crate::pl011::set_echo(self.echo);
+        #[cfg(all(feature = "gdbstub", feature = "irq"))]
+        crate::gdbstub::set_console_break(self.console_break);
+        crate::console::set_enabled(crate::pl011::CONSOLE_SINK.name(), self.console_enabled);
+    }
+}
+
+impl SerialPort for Pl011Port {
+    fn try_read(&mut self) -> Option<u8> {
+        crate::pl011::getchar()
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) {
+        crate::pl011::write_raw(bytes);
+    }
+}
+
+/// Errors of a transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The other side did not answer in time.
+    Timeout,
+    /// The other side cancelled the transfer.
+    Cancelled,
+    /// A block failed more than [`MAX_RETRIES`] times.
+    TooManyErrors,
+    /// A block arrived out of sequence.
+    OutOfSequence,
+    /// The receive buffer is too small for the file.
+    BufferTooSmall,
+    /// Malformed YMODEM header block.
+    BadHeader,
+    /// The YMODEM receiver asked for checksum blocks, YMODEM needs CRC.
+    CrcRequired,
+}
+
+/// A file received with YMODEM.
+#[derive(Debug, Clone)]
+pub struct FileInfo {
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+    /// File size from the header, if the sender gave one.
+    pub size: Option<usize>,
+    /// Number of bytes stored in the buffer.
+    pub len: usize,
+}
+
+impl FileInfo {
+    /// Returns the file name sent by the sender.
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("")
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn read_timeout(port: &mut impl SerialPort, timeout_ms: u64) -> Option<u8> {
+    let deadline = monotonic_time_nanos() + timeout_ms * NANOS_PER_MILLIS;
+    loop {
+        if let Some(c) = port.try_read() {
+            return Some(c);
+        }
+        if monotonic_time_nanos() >= deadline {
+            return None;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Discards input until the line stays quiet, to resynchronize after an
+/// error.
+fn purge(port: &mut impl SerialPort) {
+    while read_timeout(port, BYTE_TIMEOUT_MS).is_some() {}
+}
+
+fn cancel(port: &mut impl SerialPort) {
+    port.write_all(&[CAN; 5]);
+}
+
+/// A packet from the sender.
+enum Packet {
+    /// A block with its number and length, data in the receive buffer.
+    Block(u8, usize),
+    Eot,
+    Cancel,
+}
+
+/// Why a packet could not be received.
+enum PacketError {
+    Timeout,
+    /// Bad header or CRC: the block must be sent again.
+    Corrupt,
+}
+
+/// Receives one packet into `data`, waiting `timeout_ms` for it to start.
+fn recv_packet(
+    port: &mut impl SerialPort,
+    data: &mut [u8; 1024],
+    timeout_ms: u64,
+) -> Result<Packet, PacketError> {
+    let len = match read_timeout(port, timeout_ms).ok_or(PacketError::Timeout)? {
+        SOH => 128,
+        STX => 1024,
+        EOT => return Ok(Packet::Eot),
+        // A single CAN may be line noise.
+        CAN => match read_timeout(port, BYTE_TIMEOUT_MS) {
+            Some(CAN) => return Ok(Packet::Cancel),
+            _ => return Err(PacketError::Corrupt),
+        },
+        _ => return Err(PacketError::Corrupt),
+    };
+    let mut read = || read_timeout(port, BYTE_TIMEOUT_MS).ok_or(PacketError::Timeout);
+    let num = read()?;
+    let num_inv = read()?;
+    for b in data[..len].iter_mut() {
+        *b = read()?;
+    }
+    let crc = u16::from_be_bytes([read()?, read()?]);
+    if num != !num_inv || crc != crc16(&data[..len]) {
+        return Err(PacketError::Corrupt);
+    }
+    Ok(Packet::Block(num, len))
+}
+
+/// Receives blocks numbered from 1 into `buf` until EOT.
+///
+/// `nak_first_eot` asks the sender to repeat EOT before it is acknowledged,
+/// as YMODEM does.
+fn receive_data(
+    port: &mut impl SerialPort,
+    buf: &mut [u8],
+    nak_first_eot: bool,
+) -> Result<usize, Error> {
+    let mut block = [0u8; 1024];
+    let mut expected = 1u8;
+    let mut len = 0;
+    let mut started = false;
+    let mut errors = 0;
+    let mut eot_seen = false;
+    port.write_all(&[CRC_MODE]);
+    loop {
+        let timeout = if started {
+            BLOCK_TIMEOUT_MS
+        } else {
+            START_INTERVAL_MS
+        };
+        match recv_packet(port, &mut block, timeout) {
+            Ok(Packet::Block(num, n)) if num == expected => {
+                if len + n > buf.len() {
+                    cancel(port);
+                    return Err(Error::BufferTooSmall);
+                }
+                buf[len..len + n].copy_from_slice(&block[..n]);
+                len += n;
+                expected = expected.wrapping_add(1);
+                started = true;
+                errors = 0;
+                port.write_all(&[ACK]);
+            }
+            // Our ACK was lost and the sender repeated the block.
+            Ok(Packet::Block(num, _)) if started && num == expected.wrapping_sub(1) => {
+                port.write_all(&[ACK]);
+            }
+            Ok(Packet::Block(..)) => {
+                cancel(port);
+                return Err(Error::OutOfSequence);
+            }
+            Ok(Packet::Eot) if nak_first_eot && !eot_seen => {
+                eot_seen = true;
+                port.write_all(&[NAK]);
+            }
+            Ok(Packet::Eot) => {
+                port.write_all(&[ACK]);
+                return Ok(len);
+            }
+            Ok(Packet::Cancel) => return Err(Error::Cancelled),
+            Err(err) => {
+                errors += 1;
+                if errors > MAX_RETRIES {
+                    cancel(port);
+                    return Err(if started {
+                        Error::TooManyErrors
+                    } else {
+                        Error::Timeout
+                    });
+                }
+                if matches!(err, PacketError::Corrupt) {
+                    purge(port);
+                }
+                port.write_all(&[if started { NAK } else { CRC_MODE }]);
+            }
+        }
+    }
+}
+
+/// Receives a file with XMODEM(-1K) into `buf`.
+///
+/// Returns the number of bytes received. XMODEM does not carry the file size,
+/// so the last block is included with its `SUB` (0x1a) padding.
+pub fn receive(port: &mut impl SerialPort, buf: &mut [u8]) -> Result<usize, Error> {
+    receive_data(port, buf, false)
+}
+
+/// Receives the YMODEM header block (block 0).
+fn receive_header(port: &mut impl SerialPort, block: &mut [u8; 1024]) -> Result<usize, Error> {
+    port.write_all(&[CRC_MODE]);
+    for _ in 0..MAX_RETRIES {
+        match recv_packet(port, block, START_INTERVAL_MS) {
+            Ok(Packet::Block(0, n)) => {
+                port.write_all(&[ACK]);
+                return Ok(n);
+            }
+            Ok(Packet::Cancel) => return Err(Error::Cancelled),
+            Ok(_) => return Err(Error::OutOfSequence),
+            Err(PacketError::Corrupt) => {
+                purge(port);
+                port.write_all(&[CRC_MODE]);
+            }
+            Err(PacketError::Timeout) => port.write_all(&[CRC_MODE]),
+        }
+    }
+    cancel(port);
+    Err(Error::Timeout)
+}
+
+/// Parses `name\0size[ ...]\0` from a header block.
+fn parse_header(block: &[u8]) -> Result<FileInfo, Error> {
+    let name_len = block.iter().position(|&b| b == 0).ok_or(Error::BadHeader)?;
+    if name_len > MAX_NAME_LEN {
+        return Err(Error::BadHeader);
+    }
+    let mut name = [0; MAX_NAME_LEN];
+    name[..name_len].copy_from_slice(&block[..name_len]);
+    let rest = &block[name_len + 1..];
+    let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+    let size = core::str::from_utf8(&rest[..digits])
+        .ok()
+        .and_then(|s| s.parse().ok());
+    Ok(FileInfo {
+        name,
+        name_len,
+        size,
+        len: 0,
+    })
+}
+
+/// Receives a file with YMODEM into `buf`.
+///
+/// Returns [`None`] if the sender had no file to send. Only the first file of
+/// a batch is received, the rest of the batch is cancelled. The data is cut
+/// to the size given in the header.
+pub fn ymodem_receive(port: &mut impl SerialPort, buf: &mut [u8]) -> Result<Option<FileInfo>, Error> {
+    let mut block = [0u8; 1024];
+    let n = receive_header(port, &mut block)?;
+    if block[0] == 0 {
+        return Ok(None);
+    }
+    let mut info = parse_header(&block[..n])?;
+    if info.size.is_some_and(|size| size > buf.len()) {
+        cancel(port);
+        return Err(Error::BufferTooSmall);
+    }
+    let len = receive_data(port, buf, true)?;
+    info.len = info.size.map_or(len, |size| size.min(len));
+
+    // The batch ends with an empty header.
+    let n = receive_header(port, &mut block)?;
+    if block[..n].iter().any(|&b| b != 0) {
+        cancel(port);
+    }
+    Ok(Some(info))
+}
+
+/// Waits for the receiver to start, returning whether it wants CRC blocks.
+fn wait_start(port: &mut impl SerialPort) -> Result<bool, Error> {
+    let deadline = monotonic_time_nanos() + SEND_START_TIMEOUT_MS * NANOS_PER_MILLIS;
+    while monotonic_time_nanos() < deadline {
+        match read_timeout(port, BLOCK_TIMEOUT_MS) {
+            Some(CRC_MODE) => return Ok(true),
+            Some(NAK) => return Ok(false),
+            Some(CAN) if read_timeout(port, BYTE_TIMEOUT_MS) == Some(CAN) => {
+                return Err(Error::Cancelled);
+            }
+            _ => {}
+        }
+    }
+    Err(Error::Timeout)
+}
+
+/// Sends one block, padded with `pad` to 128 or 1024 bytes, until the
+/// receiver acknowledges it.
+fn send_block(
+    port: &mut impl SerialPort,
+    num: u8,
+    data: &[u8],
+    pad: u8,
+    crc: bool,
+) -> Result<(), Error> {
+    let mut block = [pad; 1024];
+    // 1K blocks need CRC.
+    let len = if data.len() > 128 && crc { 1024 } else { 128 };
+    block[..data.len()].copy_from_slice(data);
+    let block = &block[..len];
+    for _ in 0..MAX_RETRIES {
+        port.write_all(&[if len == 1024 { STX } else { SOH }, num, !num]);
+        port.write_all(block);
+        if crc {
+            port.write_all(&crc16(block).to_be_bytes());
+        } else {
+            port.write_all(&[checksum(block)]);
+        }
+        match read_timeout(port, BLOCK_TIMEOUT_MS) {
+            Some(ACK) => return Ok(()),
+            Some(CAN) if read_timeout(port, BYTE_TIMEOUT_MS) == Some(CAN) => {
+                return Err(Error::Cancelled);
+            }
+            _ => {}
+        }
+    }
+    cancel(port);
+    Err(Error::TooManyErrors)
+}
+
+/// Sends `data` in blocks numbered from 1, then EOT.
+fn send_data(port: &mut impl SerialPort, data: &[u8], crc: bool) -> Result<(), Error> {
+    let chunk = if crc { 1024 } else { 128 };
+    for (i, part) in data.chunks(chunk).enumerate() {
+        send_block(port, (i + 1) as u8, part, SUB, crc)?;
+    }
+    for _ in 0..MAX_RETRIES {
+        port.write_all(&[EOT]);
+        // A YMODEM receiver NAKs the first EOT.
+        if read_timeout(port, BLOCK_TIMEOUT_MS) == Some(ACK) {
+            return Ok(());
+        }
+    }
+    Err(Error::TooManyErrors)
+}
+
+/// Sends `data` with XMODEM-1K (or XMODEM with 128-byte checksum blocks, if
+/// the receiver asks for it).
+pub fn send(port: &mut impl SerialPort, data: &[u8]) -> Result<(), Error> {
+    let crc = wait_start(port)?;
+    send_data(port, data, crc)
+}
+
+/// Sends `data` as a file called `name` with YMODEM.
+pub fn ymodem_send(port: &mut impl SerialPort, name: &str, data: &[u8]) -> Result<(), Error> {
+    // `name\0size\0`, the size in decimal.
+    let mut header = [0u8; 1024];
+    let name = &name.as_bytes()[..name.len().min(MAX_NAME_LEN)];
+    header[..name.len()].copy_from_slice(name);
+    let mut size = [0u8; 20];
+    let mut digits = 0;
+    let mut n = data.len();
+    loop {
+        size[size.len() - 1 - digits] = b'0' + (n % 10) as u8;
+        digits += 1;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    let header_len = name.len() + 1 + digits + 1;
+    header[name.len() + 1..header_len - 1].copy_from_slice(&size[size.len() - digits..]);
+
+    if !wait_start(port)? {
+        cancel(port);
+        return Err(Error::CrcRequired);
+    }
+    send_block(port, 0, &header[..header_len], 0, true)?;
+    if !wait_start(port)? {
+        cancel(port);
+        return Err(Error::CrcRequired);
+    }
+    send_data(port, data, true)?;
+    // End of batch.
+    wait_start(port)?;
+    send_block(port, 0, &[], 0, true)
+}
@@ -0,0 +1,119 @@
+//! Host-side decoder for the `serial-mux` framing of the N80 serial line.
+//!
+//! Build with a host toolchain, from the crate root:
+//!
+//! ```text
+//! rustc --edition 2024 -O tools/serial_mux_decode.rs -o serial_mux_decode
+//! ```
+//!
+//! Decoding (`serial_mux_decode [FILE]`, standard input by default):
+//!
+//! - channel 0 (console) goes to standard output;
+//! - channel 1 (log) goes to standard error;
+//! - any other channel `N` is appended to `channel<N>.bin`;
+//! - bytes outside valid frames, such as boot output printed before the
+//!   multiplexer was enabled, go to standard output unchanged.
+//!
+//! Encoding (`serial_mux_decode --encode CHANNEL`) frames standard input for
+//! the given channel and writes the frames to standard output, e.g. to type
+//! into the console channel through `socat`.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::process::ExitCode;
+
+#[path = "../src/crc16.rs"]
+mod crc16;
+#[path = "../src/serial_mux/frame.rs"]
+mod frame;
+
+use frame::{Decoder, Event};
+
+const CONSOLE: u8 = 0;
+const LOG: u8 = 1;
+
+fn decode(mut input: impl Read) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    let mut stderr = io::stderr().lock();
+    let mut files = BTreeMap::new();
+    let mut decoder = Decoder::new();
+    let mut buf = [0; 4096];
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for &b in &buf[..n] {
+            match decoder.push(b) {
+                None => {}
+                Some(Event::Garbage(bytes)) => stdout.write_all(bytes)?,
+                Some(Event::Frame { channel: CONSOLE, payload }) => stdout.write_all(payload)?,
+                Some(Event::Frame { channel: LOG, payload }) => stderr.write_all(payload)?,
+                Some(Event::Frame { channel, payload }) => {
+                    let file = match files.entry(channel) {
+                        std::collections::btree_map::Entry::Occupied(e) => e.into_mut(),
+                        std::collections::btree_map::Entry::Vacant(e) => {
+                            let file = File::options()
+                                .create(true)
+                                .append(true)
+                                .open(format!("channel{channel}.bin"))?;
+                            e.insert(BufWriter::new(file))
+                        }
+                    };
+                    file.write_all(payload)?;
+                }
+            }
+        }
+        stdout.flush()?;
+        for file in files.values_mut() {
+            file.flush()?;
+        }
+    }
+}
+
+fn encode(channel: u8) -> io::Result<()> {
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    let mut buf = [0; frame::MAX_PAYLOAD];
+    let mut out = [0; frame::MAX_FRAME];
+    loop {
+        let n = match stdin.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let len = frame::encode(channel, &buf[..n], &mut out);
+        stdout.write_all(&out[..len])?;
+        stdout.flush()?;
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => decode(io::stdin().lock()),
+        ["--encode", channel] => match channel.parse() {
+            Ok(channel) => encode(channel),
+            Err(_) => {
+                eprintln!("invalid channel: {channel}");
+                return ExitCode::FAILURE;
+            }
+        },
+        [path] if !path.starts_with('-') => File::open(path).and_then(decode),
+        _ => {
+            eprintln!("usage: serial_mux_decode [FILE] | --encode CHANNEL");
+            return ExitCode::FAILURE;
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("serial_mux_decode: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}